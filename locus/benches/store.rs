@@ -0,0 +1,136 @@
+//! Store lookups and writes at desktop-session and stress sizes.
+//!
+//! Run with `cargo bench`. The store module is compiled in directly because
+//! it is private to the `locus` binary.
+
+use std::{collections::HashMap, hint::black_box};
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use locus::{RelationEndpoint, RelationOperation, keys};
+
+#[allow(dead_code)]
+#[path = "../src/index.rs"]
+mod index;
+#[allow(dead_code)]
+#[path = "../src/journal.rs"]
+mod journal;
+#[allow(dead_code)]
+#[path = "../src/store.rs"]
+mod store;
+
+use store::RelationStore;
+
+const SIZES: [usize; 2] = [10_000, 100_000];
+const WINDOW_APP: &str = "org.rsynapse.window.app-instance";
+const WINDOW_AGENT: &str = "org.rsynapse.window.agent-session";
+const PROJECT_AGENT: &str = "org.rsynapse.project.agent-session";
+const WORKSPACE_PROJECT: &str = "org.rsynapse.workspace.project";
+
+/// The `id`th key of `kind`, spelled the way the registry requires.
+fn key(kind: &str, id: usize) -> RelationEndpoint {
+    let id = match kind {
+        keys::APP_INSTANCE_ID => format!("00000000-0000-4000-8000-{id:012}"),
+        keys::PROJECT_PATH => format!("/src/{id}"),
+        _ => id.to_string(),
+    };
+    RelationEndpoint::stable_key(kind, id)
+}
+
+/// `size` records spread over four relations, with many-to-one fan-in on
+/// every target so `Subjects` has real work to do.
+fn populated(size: usize) -> (tempfile::TempDir, RelationStore) {
+    let temp = tempfile::tempdir().expect("tempdir");
+    let mut store = RelationStore::open(temp.path().join("relations.json")).expect("open store");
+    let operations = (0..size)
+        .map(|index| {
+            let (subject, relation, target) = match index % 4 {
+                0 => (
+                    key(keys::NIRI_WINDOW_ID, index),
+                    WINDOW_APP,
+                    key(keys::APP_INSTANCE_ID, index % 1_000),
+                ),
+                1 => (
+                    key(keys::NIRI_WINDOW_ID, index),
+                    WINDOW_AGENT,
+                    key(keys::AGENT_SESSION_ID, index % 100),
+                ),
+                2 => (
+                    key(keys::PROJECT_PATH, index),
+                    PROJECT_AGENT,
+                    key(keys::AGENT_SESSION_ID, index % 100),
+                ),
+                _ => (
+                    key(keys::NIRI_WORKSPACE_ID, index),
+                    WORKSPACE_PROJECT,
+                    key(keys::PROJECT_PATH, index % 1_000),
+                ),
+            };
+            RelationOperation::set(subject, relation, target, HashMap::new())
+        })
+        .collect();
+    store.apply(operations, None).expect("populate store");
+    (temp, store)
+}
+
+fn queries(c: &mut Criterion) {
+    let mut group = c.benchmark_group("query");
+    for size in SIZES {
+        let (_temp, store) = populated(size);
+        let window = key(keys::NIRI_WINDOW_ID, size / 2);
+        let agent = key(keys::AGENT_SESSION_ID, 42);
+        group.bench_with_input(BenchmarkId::new("targets", size), &window, |b, window| {
+            b.iter(|| black_box(store.targets(window, WINDOW_APP)))
+        });
+        group.bench_with_input(BenchmarkId::new("subjects", size), &agent, |b, agent| {
+            b.iter(|| black_box(store.subjects(WINDOW_AGENT, agent)))
+        });
+        group.bench_with_input(BenchmarkId::new("list", size), &size, |b, _| {
+            b.iter(|| black_box(store.list(PROJECT_AGENT)))
+        });
+    }
+    group.finish();
+}
+
+fn writes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("write");
+    for size in SIZES {
+        let (_temp, mut store) = populated(size);
+        // Window relations are live-scoped and never persisted, so this
+        // measures the in-memory mutation alone.
+        let window = key(keys::NIRI_WINDOW_ID, size / 2);
+        let mut app = 0;
+        group.bench_with_input(BenchmarkId::new("set_one", size), &window, |b, window| {
+            b.iter(|| {
+                app = (app + 1) % 1_000;
+                store
+                    .set_one(
+                        window.clone(),
+                        WINDOW_APP.to_string(),
+                        key(keys::APP_INSTANCE_ID, app),
+                        HashMap::new(),
+                    )
+                    .expect("set window app")
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("set_unset", size), &window, |b, window| {
+            let agent = key(keys::AGENT_SESSION_ID, 1_000);
+            b.iter(|| {
+                store
+                    .set(
+                        window.clone(),
+                        WINDOW_AGENT.to_string(),
+                        agent.clone(),
+                        HashMap::new(),
+                    )
+                    .expect("set window agent");
+                store
+                    .unset(window, WINDOW_AGENT, &agent)
+                    .expect("unset window agent")
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, queries, writes);
+criterion_main!(benches);
@@ -0,0 +1,414 @@
+//! Command-line client for the Locus relation service.
+//!
+//! Endpoints are written as `kind=id` or `bus:service/path#interface`.
+//! Results are printed as JSON, one value per line.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Write},
+    path::PathBuf,
+};
+
+use anyhow::{Context, Result, bail};
+use clap::{Parser, Subcommand};
+use futures_util::{StreamExt, stream};
+use serde::Serialize;
+use zbus::Connection;
+
+use locus::{
+    ImportMode, Metadata, MetadataValue, RelationEndpoint, RelationRecord, RelationsProxy,
+    SetOptions, WatchProxy,
+};
+
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about = "A command-line client for the Locus relation service."
+)]
+struct Args {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Add a relation, keeping the subject's other targets.
+    Set(SetArgs),
+    /// Add a relation, replacing the subject's other targets.
+    SetOne(SetArgs),
+    /// Change single metadata entries of a relation, keeping the others.
+    PatchMetadata {
+        subject: RelationEndpoint,
+        relation: String,
+        target: RelationEndpoint,
+        /// Entry to set as `key=value`, or `key:=json` for a typed value; may
+        /// be repeated.
+        #[arg(long = "meta", value_name = "KEY=VALUE", value_parser = parse_metadata)]
+        set: Vec<(String, MetadataValue)>,
+        /// Key to remove; may be repeated.
+        #[arg(long, value_name = "KEY")]
+        remove: Vec<String>,
+    },
+    /// Remove one relation.
+    Unset {
+        subject: RelationEndpoint,
+        relation: String,
+        target: RelationEndpoint,
+        /// Only remove the record while it has this revision.
+        #[arg(long, value_name = "REVISION")]
+        if_revision: Option<u64>,
+    },
+    /// Remove every target of a subject under a relation.
+    Clear {
+        subject: RelationEndpoint,
+        relation: String,
+    },
+    /// Print the targets of a subject.
+    Targets {
+        subject: RelationEndpoint,
+        relation: String,
+    },
+    /// Print the subjects pointing at a target.
+    Subjects {
+        relation: String,
+        target: RelationEndpoint,
+    },
+    /// Print the records of a relation, or of every relation.
+    List {
+        relation: Option<String>,
+        /// Only print records with this metadata entry, written like
+        /// `set --meta`; may be repeated.
+        #[arg(long = "meta", value_name = "KEY=VALUE", value_parser = parse_metadata)]
+        metadata: Vec<(String, MetadataValue)>,
+    },
+    /// Stream matching record changes until interrupted.
+    Watch {
+        #[arg(long)]
+        subject: Option<RelationEndpoint>,
+        #[arg(long)]
+        relation: Option<String>,
+        #[arg(long)]
+        target: Option<RelationEndpoint>,
+    },
+    /// Write every durable record as a relation snapshot to a file, or to
+    /// stdout.
+    Export { file: Option<PathBuf> },
+    /// Import a relation snapshot from a file, or from stdin, in one
+    /// transaction.
+    Import {
+        file: Option<PathBuf>,
+        /// `replace`, `merge-newer`, or `merge-keep-existing`.
+        #[arg(long, default_value = "replace", value_parser = parse_import_mode)]
+        mode: ImportMode,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct SetArgs {
+    subject: RelationEndpoint,
+    relation: String,
+    target: RelationEndpoint,
+    /// Metadata entry as `key=value`, or `key:=json` for a boolean, number,
+    /// or string; may be repeated.
+    #[arg(long = "meta", value_name = "KEY=VALUE", value_parser = parse_metadata)]
+    metadata: Vec<(String, MetadataValue)>,
+    /// Remove the record this many milliseconds after the write.
+    #[arg(long, conflicts_with = "if_revision")]
+    ttl_ms: Option<u64>,
+    /// Only write while the record has this revision; `0` expects no
+    /// record. With `set-one`, compares the subject's newest record.
+    #[arg(long, value_name = "REVISION")]
+    if_revision: Option<u64>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+enum WatchEvent {
+    Added { record: RelationRecord },
+    Updated { record: RelationRecord },
+    Removed { record: RelationRecord },
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let connection = Connection::session().await?;
+    let relations = RelationsProxy::new(&connection).await?;
+
+    match args.command {
+        Commands::Set(set) => print_json(&write(&relations, set, false).await?)?,
+        Commands::SetOne(set) => print_json(&write(&relations, set, true).await?)?,
+        Commands::PatchMetadata {
+            subject,
+            relation,
+            target,
+            set,
+            remove,
+        } => {
+            let remove = remove.iter().map(String::as_str).collect::<Vec<_>>();
+            print_json(
+                &relations
+                    .patch_metadata(
+                        subject,
+                        &relation,
+                        target,
+                        set.into_iter().collect(),
+                        &remove,
+                    )
+                    .await?,
+            )?
+        }
+        Commands::Unset {
+            subject,
+            relation,
+            target,
+            if_revision,
+        } => print_json(&match if_revision {
+            Some(revision) => {
+                relations
+                    .unset_if_revision(subject, &relation, target, revision)
+                    .await?
+            }
+            None => relations.unset(subject, &relation, target).await?,
+        })?,
+        Commands::Clear { subject, relation } => {
+            print_json(&relations.clear(subject, &relation).await?)?
+        }
+        Commands::Targets { subject, relation } => {
+            print_lines(&relations.targets(subject, &relation).await?)?
+        }
+        Commands::Subjects { relation, target } => {
+            print_lines(&relations.subjects(&relation, target).await?)?
+        }
+        Commands::List { relation, metadata } => {
+            let relation = relation.as_deref().unwrap_or_default();
+            print_lines(&if metadata.is_empty() {
+                relations.list(relation).await?
+            } else {
+                relations
+                    .list_matching(relation, metadata.into_iter().collect())
+                    .await?
+            })?
+        }
+        Commands::Watch {
+            subject,
+            relation,
+            target,
+        } => {
+            watch(
+                &connection,
+                &relations,
+                subject,
+                relation.unwrap_or_default(),
+                target,
+            )
+            .await?
+        }
+        Commands::Export { file } => {
+            let records = relations.export().await?;
+            match file {
+                Some(path) => write_snapshot(
+                    BufWriter::new(
+                        File::create(&path)
+                            .with_context(|| format!("creating {}", path.display()))?,
+                    ),
+                    &records,
+                )?,
+                None => write_snapshot(io::stdout().lock(), &records)?,
+            }
+        }
+        Commands::Import { file, mode } => {
+            let records: Vec<RelationRecord> = match file {
+                Some(path) => serde_json::from_reader(BufReader::new(
+                    File::open(&path).with_context(|| format!("opening {}", path.display()))?,
+                ))
+                .with_context(|| format!("reading {}", path.display()))?,
+                None => serde_json::from_reader(io::stdin().lock()).context("reading stdin")?,
+            };
+            print_json(&relations.import(records, mode).await?)?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn write(
+    relations: &RelationsProxy<'_>,
+    set: SetArgs,
+    single_target: bool,
+) -> zbus::Result<RelationRecord> {
+    let SetArgs {
+        subject,
+        relation,
+        target,
+        metadata,
+        ttl_ms,
+        if_revision,
+    } = set;
+    let metadata = metadata.into_iter().collect::<Metadata>();
+    if let Some(revision) = if_revision {
+        return if single_target {
+            relations
+                .set_one_if_revision(subject, &relation, target, metadata, revision)
+                .await
+        } else {
+            relations
+                .set_if_revision(subject, &relation, target, metadata, revision)
+                .await
+        };
+    }
+    match (ttl_ms, single_target) {
+        (None, false) => relations.set(subject, &relation, target, metadata).await,
+        (None, true) => {
+            relations
+                .set_one(subject, &relation, target, metadata)
+                .await
+        }
+        (Some(ttl_ms), false) => {
+            let options = SetOptions::expiring_after(ttl_ms);
+            relations
+                .set_with_options(subject, &relation, target, metadata, options)
+                .await
+        }
+        (Some(ttl_ms), true) => {
+            let options = SetOptions::expiring_after(ttl_ms);
+            relations
+                .set_one_with_options(subject, &relation, target, metadata, options)
+                .await
+        }
+    }
+}
+
+async fn watch(
+    connection: &Connection,
+    relations: &RelationsProxy<'_>,
+    subject: Option<RelationEndpoint>,
+    relation: String,
+    target: Option<RelationEndpoint>,
+) -> Result<()> {
+    let path = relations
+        .watch(
+            subject.into_iter().collect(),
+            &relation,
+            target.into_iter().collect(),
+        )
+        .await?;
+    let watch = WatchProxy::builder(connection).path(path)?.build().await?;
+    let added = watch.receive_relation_added().await?.map(|signal| {
+        signal.args().map(|args| WatchEvent::Added {
+            record: args.record().clone(),
+        })
+    });
+    let updated = watch.receive_relation_updated().await?.map(|signal| {
+        signal.args().map(|args| WatchEvent::Updated {
+            record: args.record().clone(),
+        })
+    });
+    let removed = watch.receive_relation_removed().await?.map(|signal| {
+        signal.args().map(|args| WatchEvent::Removed {
+            record: args.record().clone(),
+        })
+    });
+    let mut events = stream::select(added, stream::select(updated, removed));
+    loop {
+        tokio::select! {
+            event = events.next() => match event {
+                Some(event) => print_json(&event?)?,
+                None => return Ok(()),
+            },
+            signal = tokio::signal::ctrl_c() => return Ok(signal?),
+        }
+    }
+}
+
+fn parse_import_mode(mode: &str) -> Result<ImportMode> {
+    Ok(match mode {
+        "replace" => ImportMode::Replace,
+        "merge-newer" => ImportMode::MergeNewer,
+        "merge-keep-existing" => ImportMode::MergeKeepExisting,
+        _ => bail!("unknown import mode {mode:?}"),
+    })
+}
+
+/// `key=value` sets a string; `key:=json` sets the JSON value, so
+/// `count:=3`, `pinned:=true`, and `name:='"3"'` keep their types.
+fn parse_metadata(entry: &str) -> Result<(String, MetadataValue)> {
+    let Some((key, value)) = entry.split_once('=') else {
+        bail!("metadata entry {entry:?} is not key=value");
+    };
+    match key.strip_suffix(':') {
+        Some(key) => {
+            let value = serde_json::from_str(value)
+                .with_context(|| format!("metadata value {value:?} of {key:?}"))?;
+            Ok((key.to_owned(), value))
+        }
+        None => Ok((key.to_owned(), value.into())),
+    }
+}
+
+fn print_json(value: &impl Serialize) -> Result<()> {
+    write_lines(io::stdout().lock(), std::slice::from_ref(value))
+}
+
+fn print_lines<T: Serialize>(values: &[T]) -> Result<()> {
+    write_lines(io::stdout().lock(), values)
+}
+
+/// Writes records the way Locus writes its snapshot file, so either can be
+/// imported.
+fn write_snapshot(mut writer: impl Write, records: &[RelationRecord]) -> Result<()> {
+    serde_json::to_writer_pretty(&mut writer, records)?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_lines<T: Serialize>(mut writer: impl Write, values: &[T]) -> Result<()> {
+    for value in values {
+        serde_json::to_writer(&mut writer, value)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn import_modes_parse_from_their_bus_names() {
+        for mode in [
+            ImportMode::Replace,
+            ImportMode::MergeNewer,
+            ImportMode::MergeKeepExisting,
+        ] {
+            let name = serde_json::to_value(mode).expect("mode json");
+            let name = name.as_str().expect("mode name");
+            assert_eq!(parse_import_mode(name).expect("parse mode"), mode);
+        }
+        assert!(parse_import_mode("merge").is_err());
+    }
+
+    #[test]
+    fn metadata_entries_parse_as_strings_or_typed_json() {
+        let parse = |entry| parse_metadata(entry).expect("parse metadata");
+        assert_eq!(parse("branch=main"), ("branch".to_owned(), "main".into()));
+        assert_eq!(parse("url=a=b"), ("url".to_owned(), "a=b".into()));
+        assert_eq!(
+            parse("count:=3"),
+            ("count".to_owned(), MetadataValue::Int(3))
+        );
+        assert_eq!(
+            parse("ratio:=0.5"),
+            ("ratio".to_owned(), MetadataValue::Double(0.5))
+        );
+        assert_eq!(
+            parse("pinned:=true"),
+            ("pinned".to_owned(), MetadataValue::Bool(true))
+        );
+        assert_eq!(parse(r#"id:="3""#), ("id".to_owned(), "3".into()));
+        assert!(parse_metadata("count:=[1]").is_err());
+        assert!(parse_metadata("branch").is_err());
+    }
+}
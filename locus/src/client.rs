@@ -0,0 +1,440 @@
+//! Live views of Locus records kept in a local cache.
+//!
+//! A view opens a `Watch` object for its query and subscribes to that
+//! object's signals before reading its `Records`, so no change can fall
+//! between the two, and applies each signal to its cache instead of querying
+//! again. Signals queued while the read was in flight may describe records
+//! older than it; revisions tell them apart.
+
+use std::pin::pin;
+
+use futures_util::{
+    StreamExt,
+    future::{self, Either},
+    stream::{self, BoxStream},
+};
+use tracing::warn;
+use zbus::{CacheProperties, Connection, fdo, zvariant::OwnedObjectPath};
+
+use crate::{BUS_NAME, RelationEndpoint, RelationRecord, RelationsProxy, WatchProxy};
+
+/// The records a view holds: one relation, optionally narrowed to a subject
+/// or target. An empty relation matches every relation.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RecordQuery {
+    pub relation: String,
+    pub subject: Option<RelationEndpoint>,
+    pub target: Option<RelationEndpoint>,
+}
+
+impl RecordQuery {
+    pub fn new(relation: impl Into<String>) -> Self {
+        Self {
+            relation: relation.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_subject(mut self, subject: RelationEndpoint) -> Self {
+        self.subject = Some(subject);
+        self
+    }
+
+    pub fn with_target(mut self, target: RelationEndpoint) -> Self {
+        self.target = Some(target);
+        self
+    }
+
+    pub fn matches(&self, record: &RelationRecord) -> bool {
+        (self.relation.is_empty() || record.relation == self.relation)
+            && self
+                .subject
+                .as_ref()
+                .is_none_or(|subject| &record.subject == subject)
+            && self
+                .target
+                .as_ref()
+                .is_none_or(|target| &record.target == target)
+    }
+}
+
+/// A connection to Locus that hands out live views.
+#[derive(Clone, Debug)]
+pub struct LocusClient {
+    proxy: RelationsProxy<'static>,
+}
+
+impl LocusClient {
+    pub async fn new(connection: &Connection) -> zbus::Result<Self> {
+        Ok(Self {
+            proxy: RelationsProxy::new(connection).await?,
+        })
+    }
+
+    pub async fn session() -> zbus::Result<Self> {
+        Self::new(&Connection::session().await?).await
+    }
+
+    /// The underlying proxy, for writes and one-off queries.
+    pub fn relations(&self) -> &RelationsProxy<'static> {
+        &self.proxy
+    }
+
+    /// Snapshots of the records matching `query`: the current ones first,
+    /// then one after every change to them. While Locus is not running the
+    /// view is empty; it is watched again when Locus reappears. The stream
+    /// ends when the connection closes, and dropping it closes the watch.
+    pub async fn watch(
+        &self,
+        query: RecordQuery,
+    ) -> zbus::Result<BoxStream<'static, Vec<RelationRecord>>> {
+        let owner = self.owner_changes().await?;
+        let mut cache = RecordCache::new(query);
+        let (subscription, records) = open(&self.proxy, &cache.query).await?;
+        cache.seed(records);
+        let initial = cache.records.clone();
+
+        let changes = stream::unfold(
+            (cache, owner, subscription, self.proxy.clone()),
+            |(mut cache, mut owner, mut subscription, proxy)| async move {
+                loop {
+                    let event =
+                        match future::select(owner.next(), pin!(next_event(&mut subscription)))
+                            .await
+                        {
+                            Either::Left((event, _)) => event?,
+                            Either::Right((event, _)) => event,
+                        };
+                    let changed = match event {
+                        Event::Upsert(record) => cache.upsert(record),
+                        Event::Remove(record) => cache.remove(&record),
+                        Event::Owner(false) => {
+                            subscription = None;
+                            cache.seed(Vec::new())
+                        }
+                        Event::Owner(true) => match open(&proxy, &cache.query).await {
+                            Ok((opened, records)) => {
+                                subscription = opened;
+                                cache.seed(records)
+                            }
+                            Err(error) => {
+                                warn!("failed to watch {:?} in Locus: {error}", cache.query);
+                                false
+                            }
+                        },
+                    };
+                    if changed {
+                        let snapshot = cache.records.clone();
+                        return Some((snapshot, (cache, owner, subscription, proxy)));
+                    }
+                }
+            },
+        );
+        Ok(stream::once(future::ready(initial)).chain(changes).boxed())
+    }
+
+    /// The targets `subject` has under `relation`, whenever they change.
+    pub async fn watch_targets(
+        &self,
+        subject: RelationEndpoint,
+        relation: &str,
+    ) -> zbus::Result<BoxStream<'static, Vec<RelationEndpoint>>> {
+        let records = self
+            .watch(RecordQuery::new(relation).with_subject(subject))
+            .await?;
+        Ok(distinct(records.map(|records| {
+            records.into_iter().map(|record| record.target).collect()
+        })))
+    }
+
+    /// The subjects pointing at `target` under `relation`, whenever they
+    /// change.
+    pub async fn watch_subjects(
+        &self,
+        relation: &str,
+        target: RelationEndpoint,
+    ) -> zbus::Result<BoxStream<'static, Vec<RelationEndpoint>>> {
+        let records = self
+            .watch(RecordQuery::new(relation).with_target(target))
+            .await?;
+        Ok(distinct(records.map(|records| {
+            records.into_iter().map(|record| record.subject).collect()
+        })))
+    }
+
+    /// Whether Locus owns its bus name, after each change of owner.
+    async fn owner_changes(&self) -> zbus::Result<BoxStream<'static, Event>> {
+        Ok(fdo::DBusProxy::new(self.proxy.inner().connection())
+            .await?
+            .receive_name_owner_changed_with_args(&[(0, BUS_NAME)])
+            .await?
+            .filter_map(|signal| {
+                future::ready(
+                    decode(signal.args().map(|args| args.new_owner.is_some())).map(Event::Owner),
+                )
+            })
+            .boxed())
+    }
+}
+
+/// The signals of the `Watch` object behind a view. Dropping it unwatches
+/// the object, which Locus would otherwise keep until the connection closes.
+struct Subscription {
+    events: BoxStream<'static, Event>,
+    _watch: OpenWatch,
+}
+
+struct OpenWatch {
+    proxy: RelationsProxy<'static>,
+    path: OwnedObjectPath,
+}
+
+impl Drop for OpenWatch {
+    fn drop(&mut self) {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let proxy = self.proxy.clone();
+        let path = self.path.clone();
+        runtime.spawn(async move {
+            // Fails harmlessly when Locus already dropped the watch.
+            let _ = proxy.unwatch(&path).await;
+        });
+    }
+}
+
+/// Opens a `Watch` for `query` and reads its records once subscribed to its
+/// signals. Without Locus on the bus there is nothing to watch and no
+/// records.
+async fn open(
+    proxy: &RelationsProxy<'static>,
+    query: &RecordQuery,
+) -> zbus::Result<(Option<Subscription>, Vec<RelationRecord>)> {
+    let path = match proxy
+        .watch(
+            query.subject.iter().cloned().collect(),
+            &query.relation,
+            query.target.iter().cloned().collect(),
+        )
+        .await
+    {
+        Ok(path) => path,
+        Err(error) if is_unavailable(&error) => return Ok((None, Vec::new())),
+        Err(error) => return Err(error),
+    };
+    let open_watch = OpenWatch {
+        proxy: proxy.clone(),
+        path: path.clone(),
+    };
+    let watch = WatchProxy::builder(proxy.inner().connection())
+        .path(path)?
+        .cache_properties(CacheProperties::No)
+        .build()
+        .await?;
+    let added = watch.receive_relation_added().await?.filter_map(|signal| {
+        future::ready(decode(signal.args().map(|args| args.record)).map(Event::Upsert))
+    });
+    let updated = watch
+        .receive_relation_updated()
+        .await?
+        .filter_map(|signal| {
+            future::ready(decode(signal.args().map(|args| args.record)).map(Event::Upsert))
+        });
+    let removed = watch
+        .receive_relation_removed()
+        .await?
+        .filter_map(|signal| {
+            future::ready(decode(signal.args().map(|args| args.record)).map(Event::Remove))
+        });
+    let records = watch.records().await?;
+    let events = stream::select_all([added.boxed(), updated.boxed(), removed.boxed()]).boxed();
+    Ok((
+        Some(Subscription {
+            events,
+            _watch: open_watch,
+        }),
+        records,
+    ))
+}
+
+/// The next change from the view's watch, or never while there is none.
+async fn next_event(subscription: &mut Option<Subscription>) -> Event {
+    match subscription {
+        Some(Subscription { events, .. }) => match events.next().await {
+            Some(event) => event,
+            None => future::pending().await,
+        },
+        None => future::pending().await,
+    }
+}
+
+/// One change a view applies to its cache. `Clear` needs no event of its own
+/// because Locus reports every record it removes.
+#[derive(Debug)]
+enum Event {
+    Upsert(RelationRecord),
+    Remove(RelationRecord),
+    /// Whether Locus owns its bus name after a change of owner.
+    Owner(bool),
+}
+
+#[derive(Debug)]
+struct RecordCache {
+    query: RecordQuery,
+    records: Vec<RelationRecord>,
+}
+
+impl RecordCache {
+    fn new(query: RecordQuery) -> Self {
+        Self {
+            query,
+            records: Vec::new(),
+        }
+    }
+
+    /// Replaces the cache with a fresh listing and reports whether it
+    /// changed.
+    fn seed(&mut self, records: Vec<RelationRecord>) -> bool {
+        let records = records
+            .into_iter()
+            .filter(|record| self.query.matches(record))
+            .collect::<Vec<_>>();
+        let changed = records != self.records;
+        self.records = records;
+        changed
+    }
+
+    /// Adds or replaces a record unless the cache already holds a newer
+    /// revision of it.
+    fn upsert(&mut self, record: RelationRecord) -> bool {
+        if !self.query.matches(&record) {
+            return false;
+        }
+        match self.position(&record) {
+            Some(index) if self.records[index].revision > record.revision => false,
+            Some(index) if self.records[index] == record => false,
+            Some(index) => {
+                self.records[index] = record;
+                true
+            }
+            None => {
+                self.records.push(record);
+                true
+            }
+        }
+    }
+
+    /// Drops a record unless the cache holds a revision written after the
+    /// removal.
+    fn remove(&mut self, record: &RelationRecord) -> bool {
+        match self.position(record) {
+            Some(index) if self.records[index].revision <= record.revision => {
+                self.records.remove(index);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn position(&self, record: &RelationRecord) -> Option<usize> {
+        self.records.iter().position(|cached| {
+            cached.subject == record.subject
+                && cached.relation == record.relation
+                && cached.target == record.target
+        })
+    }
+}
+
+fn decode<T>(args: zbus::Result<T>) -> Option<T> {
+    args.inspect_err(|error| warn!("failed to decode Locus signal: {error}"))
+        .ok()
+}
+
+/// Drops snapshots equal to the one before them.
+fn distinct<T>(
+    snapshots: impl futures_util::Stream<Item = T> + Send + 'static,
+) -> BoxStream<'static, T>
+where
+    T: Clone + PartialEq + Send + 'static,
+{
+    let mut last = None;
+    snapshots
+        .filter(move |snapshot| {
+            let changed = last.as_ref() != Some(snapshot);
+            if changed {
+                last = Some(snapshot.clone());
+            }
+            future::ready(changed)
+        })
+        .boxed()
+}
+
+fn is_unavailable(error: &zbus::Error) -> bool {
+    match error {
+        zbus::Error::MethodError(name, _, _) => {
+            name.as_str() == "org.freedesktop.DBus.Error.ServiceUnknown"
+        }
+        zbus::Error::FDO(error) => {
+            matches!(error.as_ref(), fdo::Error::ServiceUnknown(_))
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Metadata, keys};
+
+    fn record(target: &str, revision: u64) -> RelationRecord {
+        RelationRecord {
+            subject: RelationEndpoint::stable_key(keys::NIRI_WORKSPACE_ID, "1"),
+            relation: "org.rsynapse.workspace.project".to_owned(),
+            target: RelationEndpoint::stable_key(keys::PROJECT_PATH, target),
+            metadata: Metadata::new(),
+            created_at_unix_ms: 1,
+            updated_at_unix_ms: revision,
+            owner: String::new(),
+            expires_at_unix_ms: 0,
+            revision,
+        }
+    }
+
+    fn cache() -> RecordCache {
+        RecordCache::new(
+            RecordQuery::new("org.rsynapse.workspace.project")
+                .with_subject(RelationEndpoint::stable_key(keys::NIRI_WORKSPACE_ID, "1")),
+        )
+    }
+
+    #[test]
+    fn signals_older_than_the_listing_are_ignored() {
+        let mut cache = cache();
+        cache.seed(vec![record("/src/a", 5)]);
+
+        assert!(!cache.upsert(record("/src/a", 3)));
+        assert!(!cache.remove(&record("/src/a", 4)));
+        assert_eq!(cache.records, vec![record("/src/a", 5)]);
+
+        assert!(cache.upsert(record("/src/a", 6)));
+        assert!(!cache.upsert(record("/src/a", 6)));
+        assert!(cache.remove(&record("/src/a", 7)));
+        assert!(cache.records.is_empty());
+    }
+
+    #[test]
+    fn cache_keeps_only_matching_records() {
+        let mut cache = cache();
+        let mut other = record("/src/b", 2);
+        other.subject = RelationEndpoint::stable_key(keys::NIRI_WORKSPACE_ID, "2");
+
+        assert!(cache.seed(vec![record("/src/a", 1), other.clone()]));
+        assert!(!cache.upsert(other));
+        assert!(cache.upsert(record("/src/c", 3)));
+        assert_eq!(
+            cache.records,
+            vec![record("/src/a", 1), record("/src/c", 3)]
+        );
+        assert!(!cache.seed(vec![record("/src/a", 1), record("/src/c", 3)]));
+    }
+}
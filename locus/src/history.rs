@@ -0,0 +1,276 @@
+//! Bounded in-memory audit log of committed record changes.
+
+use std::collections::{HashMap, VecDeque};
+
+use locus::{HistoryEntry, HistoryFilter, RelationRecord};
+
+use crate::store::RelationChange;
+
+/// Entries kept before the oldest are dropped.
+const HISTORY_CAPACITY: usize = 4_096;
+
+/// Who made a change, as reported by the bus.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Caller {
+    /// Unique bus name, empty for changes Locus made itself.
+    pub name: String,
+    pub pid: u32,
+    pub exe: String,
+    pub uid: Option<u32>,
+}
+
+#[derive(Debug)]
+pub struct History {
+    entries: VecDeque<HistoryEntry>,
+    capacity: usize,
+    /// Credentials of connected writers. A unique name's credentials never
+    /// change, so each is looked up once and forgotten when it disconnects.
+    callers: HashMap<String, Caller>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::with_capacity(HISTORY_CAPACITY)
+    }
+}
+
+impl History {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            capacity,
+            callers: HashMap::new(),
+        }
+    }
+
+    /// Appends one entry per changed record. `Cleared` completions add
+    /// nothing; each record they removed has its own entry.
+    pub fn record(
+        &mut self,
+        operation: &str,
+        caller: &Caller,
+        changes: &[RelationChange],
+        at_unix_ms: u64,
+    ) {
+        for change in changes {
+            let (before, after) = match change {
+                RelationChange::Added(record) => (None, Some(record)),
+                RelationChange::Updated { previous, record } => (Some(&**previous), Some(record)),
+                RelationChange::Removed(record) => (Some(record), None),
+                RelationChange::Cleared { .. } => continue,
+            };
+            if self.entries.len() == self.capacity {
+                self.entries.pop_front();
+            }
+            self.entries.push_back(HistoryEntry {
+                operation: operation.to_owned(),
+                before: before.into_iter().cloned().collect(),
+                after: after.into_iter().cloned().collect(),
+                at_unix_ms,
+                caller: caller.name.clone(),
+                caller_pid: caller.pid,
+                caller_exe: caller.exe.clone(),
+            });
+        }
+    }
+
+    /// Matching entries, newest first. A `limit` of `0` means no limit.
+    pub fn query(&self, filter: &HistoryFilter, limit: u32) -> Vec<HistoryEntry> {
+        let limit = match limit {
+            0 => usize::MAX,
+            limit => limit.try_into().unwrap_or(usize::MAX),
+        };
+        self.entries
+            .iter()
+            .rev()
+            .filter(|entry| entry_matches(filter, entry))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    pub fn caller(&self, name: &str) -> Option<&Caller> {
+        self.callers.get(name)
+    }
+
+    pub fn remember_caller(&mut self, caller: Caller) {
+        self.callers.insert(caller.name.clone(), caller);
+    }
+
+    pub fn forget_caller(&mut self, name: &str) {
+        self.callers.remove(name);
+    }
+}
+
+fn entry_matches(filter: &HistoryFilter, entry: &HistoryEntry) -> bool {
+    filter
+        .since_unix_ms
+        .is_none_or(|since| entry.at_unix_ms >= since)
+        && filter
+            .caller
+            .as_ref()
+            .is_none_or(|caller| &entry.caller == caller)
+        && entry
+            .before
+            .iter()
+            .chain(&entry.after)
+            .any(|record| record_matches(filter, record))
+}
+
+fn record_matches(filter: &HistoryFilter, record: &RelationRecord) -> bool {
+    filter
+        .subject
+        .as_ref()
+        .is_none_or(|subject| &record.subject == subject)
+        && filter
+            .relation
+            .as_ref()
+            .is_none_or(|relation| &record.relation == relation)
+        && filter
+            .target
+            .as_ref()
+            .is_none_or(|target| &record.target == target)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use locus::{RelationEndpoint, keys};
+
+    use super::*;
+
+    fn workspace(id: &str) -> RelationEndpoint {
+        RelationEndpoint::stable_key(keys::NIRI_WORKSPACE_ID, id)
+    }
+
+    fn project(path: &str) -> RelationEndpoint {
+        RelationEndpoint::stable_key(keys::PROJECT_PATH, path)
+    }
+
+    fn record(workspace_id: &str, project_path: &str) -> RelationRecord {
+        RelationRecord {
+            subject: workspace(workspace_id),
+            relation: "org.rsynapse.workspace.project".to_owned(),
+            target: project(project_path),
+            metadata: HashMap::new(),
+            created_at_unix_ms: 1,
+            updated_at_unix_ms: 1,
+            owner: String::new(),
+            expires_at_unix_ms: 0,
+            revision: 0,
+        }
+    }
+
+    fn writer() -> Caller {
+        Caller {
+            name: ":1.7".to_owned(),
+            pid: 42,
+            exe: "/usr/bin/bash".to_owned(),
+            uid: Some(1000),
+        }
+    }
+
+    #[test]
+    fn records_before_and_after_with_the_caller() {
+        let mut history = History::default();
+        history.record(
+            "SetOne",
+            &writer(),
+            &[
+                RelationChange::Removed(record("1", "/old")),
+                RelationChange::Added(record("1", "/new")),
+                RelationChange::Cleared {
+                    subject: workspace("1"),
+                    relation: "org.rsynapse.workspace.project".to_owned(),
+                    removed_count: 1,
+                },
+            ],
+            5,
+        );
+
+        let entries = history.query(&HistoryFilter::default(), 0);
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].before, Vec::new());
+        assert_eq!(entries[0].after, vec![record("1", "/new")]);
+        assert_eq!(entries[1].before, vec![record("1", "/old")]);
+        assert_eq!(entries[1].after, Vec::new());
+        assert_eq!(entries[1].operation, "SetOne");
+        assert_eq!(
+            (
+                entries[1].caller.as_str(),
+                entries[1].caller_pid,
+                entries[1].caller_exe.as_str()
+            ),
+            (":1.7", 42, "/usr/bin/bash")
+        );
+    }
+
+    #[test]
+    fn query_filters_newest_first_and_limits() {
+        let mut history = History::default();
+        history.record(
+            "Set",
+            &writer(),
+            &[RelationChange::Added(record("1", "/a"))],
+            1,
+        );
+        history.record(
+            "Set",
+            &Caller::default(),
+            &[RelationChange::Added(record("2", "/a"))],
+            2,
+        );
+        history.record(
+            "Set",
+            &writer(),
+            &[RelationChange::Updated {
+                previous: Box::new(record("1", "/a")),
+                record: record("1", "/a"),
+            }],
+            3,
+        );
+
+        let workspace_one = HistoryFilter {
+            subject: Some(workspace("1")),
+            ..HistoryFilter::default()
+        };
+        let times = |entries: Vec<HistoryEntry>| {
+            entries
+                .iter()
+                .map(|entry| entry.at_unix_ms)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(times(history.query(&workspace_one, 0)), [3, 1]);
+        assert_eq!(times(history.query(&workspace_one, 1)), [3]);
+        let since = HistoryFilter {
+            since_unix_ms: Some(2),
+            caller: Some(String::new()),
+            ..HistoryFilter::default()
+        };
+        assert_eq!(times(history.query(&since, 0)), [2]);
+    }
+
+    #[test]
+    fn oldest_entries_are_dropped_at_capacity() {
+        let mut history = History::with_capacity(2);
+        for at in 1..=3 {
+            history.record(
+                "Set",
+                &writer(),
+                &[RelationChange::Added(record("1", "/a"))],
+                at,
+            );
+        }
+
+        let entries = history.query(&HistoryFilter::default(), 0);
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| entry.at_unix_ms)
+                .collect::<Vec<_>>(),
+            [3, 2]
+        );
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use locus::{RelationEndpoint, RelationRecord};
+
+type RelationRecords = BTreeMap<(RelationEndpoint, RelationEndpoint), RelationRecord>;
+
+/// Relation records indexed for the store's lookups.
+///
+/// Records live under their relation, ordered by subject and target, which is
+/// also the `List` order. `targets` and `subjects` are answered from the
+/// (subject, relation) and (relation, target) indexes without touching other
+/// relations.
+#[derive(Debug, Default)]
+pub struct RelationIndex {
+    by_relation: BTreeMap<String, RelationRecords>,
+    by_subject: HashMap<RelationEndpoint, HashMap<String, BTreeSet<RelationEndpoint>>>,
+    by_target: HashMap<String, HashMap<RelationEndpoint, BTreeSet<RelationEndpoint>>>,
+    len: usize,
+}
+
+impl RelationIndex {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn get(
+        &self,
+        subject: &RelationEndpoint,
+        relation: &str,
+        target: &RelationEndpoint,
+    ) -> Option<&RelationRecord> {
+        self.by_relation
+            .get(relation)?
+            .get(&(subject.clone(), target.clone()))
+    }
+
+    /// Inserts or replaces the record with the same subject, relation and
+    /// target, returning the replaced record.
+    pub fn insert(&mut self, record: RelationRecord) -> Option<RelationRecord> {
+        let subject = record.subject.clone();
+        let relation = record.relation.clone();
+        let target = record.target.clone();
+        let previous = self
+            .by_relation
+            .entry(relation.clone())
+            .or_default()
+            .insert((subject.clone(), target.clone()), record);
+        if previous.is_some() {
+            return previous;
+        }
+
+        self.by_subject
+            .entry(subject.clone())
+            .or_default()
+            .entry(relation.clone())
+            .or_default()
+            .insert(target.clone());
+        self.by_target
+            .entry(relation)
+            .or_default()
+            .entry(target)
+            .or_default()
+            .insert(subject);
+        self.len += 1;
+        None
+    }
+
+    pub fn remove(
+        &mut self,
+        subject: &RelationEndpoint,
+        relation: &str,
+        target: &RelationEndpoint,
+    ) -> Option<RelationRecord> {
+        let records = self.by_relation.get_mut(relation)?;
+        let record = records.remove(&(subject.clone(), target.clone()))?;
+        if records.is_empty() {
+            self.by_relation.remove(relation);
+        }
+
+        if let Some(relations) = self.by_subject.get_mut(subject) {
+            if let Some(targets) = relations.get_mut(relation) {
+                targets.remove(target);
+                if targets.is_empty() {
+                    relations.remove(relation);
+                }
+            }
+            if relations.is_empty() {
+                self.by_subject.remove(subject);
+            }
+        }
+        if let Some(targets) = self.by_target.get_mut(relation) {
+            if let Some(subjects) = targets.get_mut(target) {
+                subjects.remove(subject);
+                if subjects.is_empty() {
+                    targets.remove(target);
+                }
+            }
+            if targets.is_empty() {
+                self.by_target.remove(relation);
+            }
+        }
+        self.len -= 1;
+        Some(record)
+    }
+
+    /// Targets of `subject` under `relation`, in endpoint order.
+    pub fn targets(
+        &self,
+        subject: &RelationEndpoint,
+        relation: &str,
+    ) -> impl Iterator<Item = &RelationEndpoint> {
+        self.by_subject
+            .get(subject)
+            .and_then(|relations| relations.get(relation))
+            .into_iter()
+            .flatten()
+    }
+
+    /// Subjects pointing at `target` under `relation`, in endpoint order.
+    pub fn subjects(
+        &self,
+        relation: &str,
+        target: &RelationEndpoint,
+    ) -> impl Iterator<Item = &RelationEndpoint> {
+        self.by_target
+            .get(relation)
+            .and_then(|targets| targets.get(target))
+            .into_iter()
+            .flatten()
+    }
+
+    /// Records of `relation`, ordered by subject and then target.
+    pub fn relation<'a>(
+        &'a self,
+        relation: &str,
+    ) -> impl Iterator<Item = &'a RelationRecord> + Clone + use<'a> {
+        self.by_relation
+            .get(relation)
+            .into_iter()
+            .flat_map(|records| records.values())
+    }
+
+    /// Relations with at least one record, in order.
+    pub fn relations(&self) -> impl Iterator<Item = &String> {
+        self.by_relation.keys()
+    }
+
+    /// Every record, ordered by relation, subject and target.
+    pub fn iter(&self) -> impl Iterator<Item = &RelationRecord> {
+        self.by_relation
+            .values()
+            .flat_map(|records| records.values())
+    }
+}
+
+impl FromIterator<RelationRecord> for RelationIndex {
+    fn from_iter<I: IntoIterator<Item = RelationRecord>>(records: I) -> Self {
+        let mut index = Self::default();
+        for record in records {
+            index.insert(record);
+        }
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn key(kind: &str, id: &str) -> RelationEndpoint {
+        RelationEndpoint::stable_key(kind, id)
+    }
+
+    fn record(subject: &str, relation: &str, target: &str) -> RelationRecord {
+        RelationRecord {
+            subject: key("test.subject", subject),
+            relation: relation.to_owned(),
+            target: key("test.target", target),
+            metadata: HashMap::new(),
+            created_at_unix_ms: 1,
+            updated_at_unix_ms: 1,
+            owner: String::new(),
+            expires_at_unix_ms: 0,
+            revision: 0,
+        }
+    }
+
+    #[test]
+    fn lookups_follow_inserts_and_removes() {
+        let mut index = [
+            record("a", "rel.one", "x"),
+            record("a", "rel.one", "y"),
+            record("b", "rel.one", "x"),
+            record("a", "rel.two", "x"),
+        ]
+        .into_iter()
+        .collect::<RelationIndex>();
+
+        assert_eq!(index.len(), 4);
+        assert_eq!(
+            index
+                .targets(&key("test.subject", "a"), "rel.one")
+                .collect::<Vec<_>>(),
+            [&key("test.target", "x"), &key("test.target", "y")]
+        );
+        assert_eq!(
+            index
+                .subjects("rel.one", &key("test.target", "x"))
+                .collect::<Vec<_>>(),
+            [&key("test.subject", "a"), &key("test.subject", "b")]
+        );
+        assert_eq!(
+            index.relations().collect::<Vec<_>>(),
+            ["rel.one", "rel.two"]
+        );
+
+        let removed = index.remove(
+            &key("test.subject", "a"),
+            "rel.two",
+            &key("test.target", "x"),
+        );
+        assert_eq!(removed, Some(record("a", "rel.two", "x")));
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.relations().collect::<Vec<_>>(), ["rel.one"]);
+        assert_eq!(
+            index.targets(&key("test.subject", "a"), "rel.two").count(),
+            0
+        );
+    }
+
+    #[test]
+    fn replacing_a_record_keeps_one_entry() {
+        let mut index = RelationIndex::default();
+        index.insert(record("a", "rel.one", "x"));
+        let mut updated = record("a", "rel.one", "x");
+        updated.updated_at_unix_ms = 2;
+
+        let previous = index.insert(updated.clone());
+
+        assert_eq!(previous, Some(record("a", "rel.one", "x")));
+        assert_eq!(index.len(), 1);
+        assert_eq!(index.iter().collect::<Vec<_>>(), [&updated]);
+    }
+}
@@ -0,0 +1,164 @@
+//! Append-only journal of committed changes on top of the relation snapshot.
+//!
+//! Each commit is one JSON line holding the records it wrote and the keys it
+//! removed. A line only counts once its trailing newline is on disk, so a
+//! commit interrupted mid-write is dropped on replay instead of half applied.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use locus::{RelationEndpoint, RelationRecord};
+
+use crate::index::RelationIndex;
+
+/// Entries appended before the journal is folded into a new snapshot, unless
+/// the store is larger, in which case compaction waits for as many entries as
+/// there are records.
+const COMPACT_MIN_ENTRIES: usize = 1_000;
+
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct JournalEntry {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub put: Vec<RelationRecord>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub delete: Vec<RecordKey>,
+    /// The store's revision counter after this commit, so revisions of
+    /// removed records are not handed out again after a restart.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub revision: u64,
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct RecordKey {
+    pub subject: RelationEndpoint,
+    pub relation: String,
+    pub target: RelationEndpoint,
+}
+
+impl JournalEntry {
+    fn apply_to(self, records: &mut RelationIndex) {
+        for key in self.delete {
+            records.remove(&key.subject, &key.relation, &key.target);
+        }
+        for record in self.put {
+            records.insert(record);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    /// Length of the journal up to its last complete entry.
+    len: u64,
+    entries: usize,
+    /// Highest revision counter recorded by any entry.
+    revision: u64,
+}
+
+impl Journal {
+    /// The journal lives next to the snapshot, e.g. `relations.journal`.
+    pub fn path_for(snapshot: &Path) -> PathBuf {
+        snapshot.with_extension("journal")
+    }
+
+    /// Replays complete entries into `records`. A torn final entry is cut off
+    /// so later appends start on a clean line.
+    pub fn replay(path: PathBuf, records: &mut RelationIndex) -> io::Result<Self> {
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                return Ok(Self {
+                    path,
+                    len: 0,
+                    entries: 0,
+                    revision: 0,
+                });
+            }
+            Err(error) => return Err(error),
+        };
+        let file_len = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        let mut len = 0;
+        let mut entries = 0;
+        let mut revision = 0;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line)?;
+            if read == 0 || !line.ends_with('\n') {
+                break;
+            }
+            let entry: JournalEntry = match serde_json::from_str(&line) {
+                Ok(entry) => entry,
+                Err(_) if len + read as u64 == file_len => break,
+                Err(error) => return Err(io::Error::new(io::ErrorKind::InvalidData, error)),
+            };
+            revision = revision.max(entry.revision);
+            entry.apply_to(records);
+            len += read as u64;
+            entries += 1;
+        }
+        if len != file_len {
+            OpenOptions::new().write(true).open(&path)?.set_len(len)?;
+        }
+        Ok(Self {
+            path,
+            len,
+            entries,
+            revision,
+        })
+    }
+
+    /// Appends one committed change. On failure the journal is cut back to
+    /// its previous length so a partial line never precedes later entries.
+    pub fn append(&mut self, entry: &JournalEntry) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry).map_err(io::Error::other)?;
+        line.push(b'\n');
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        if let Err(error) = file.write_all(&line) {
+            let _ = file.set_len(self.len);
+            return Err(error);
+        }
+        self.len += line.len() as u64;
+        self.entries += 1;
+        self.revision = self.revision.max(entry.revision);
+        Ok(())
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn should_compact(&self, record_count: usize) -> bool {
+        self.entries >= COMPACT_MIN_ENTRIES.max(record_count)
+    }
+
+    /// Drops every entry once a snapshot holding them has been written.
+    pub fn reset(&mut self) -> io::Result<()> {
+        match fs::remove_file(&self.path) {
+            Ok(()) => {}
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => return Err(error),
+        }
+        self.len = 0;
+        self.entries = 0;
+        self.revision = 0;
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt, str::FromStr};
 
 use serde::{
     Deserialize, Deserializer, Serialize, Serializer,
@@ -6,11 +6,74 @@ use serde::{
     ser::SerializeMap,
 };
 use zbus::proxy;
-use zvariant::Type;
+use zvariant::{DeserializeDict, OwnedValue, SerializeDict, Type, Value};
+
+pub mod client;
+
+pub use client::{LocusClient, RecordQuery};
 
 pub const BUS_NAME: &str = "org.rsynapse.Locus";
 pub const OBJECT_PATH: &str = "/org/rsynapse/Locus";
 pub const RELATIONS_INTERFACE: &str = "org.rsynapse.Locus.Relations1";
+pub const WATCH_INTERFACE: &str = "org.rsynapse.Locus.Watch1";
+/// Interface of the per-record objects under [`RECORDS_PATH`].
+pub const RECORD_INTERFACE: &str = "org.rsynapse.Locus.Relation1";
+pub const RECORDS_PATH: &str = "/org/rsynapse/Locus/records";
+/// Parent of the objects `Watch` exports, kept outside the ObjectManager at
+/// [`OBJECT_PATH`] so watches never show up as managed objects.
+pub const WATCHES_PATH: &str = "/org/rsynapse/LocusWatches";
+/// D-Bus error returned by the `*IfRevision` methods when the record changed
+/// since the caller read it.
+pub const REVISION_MISMATCH_ERROR: &str = "org.rsynapse.Locus.Error.RevisionMismatch";
+
+/// Object path of the record `subject` -> `target` under `relation`: one
+/// element below [`RECORDS_PATH`] holding the relation and both endpoints.
+/// Every field is hex-encoded behind an `x`, fields are joined with `_` and
+/// the three parts with `__`, which the encoding never produces. The path is
+/// the same for every revision of the record and across restarts.
+pub fn record_path(
+    subject: &RelationEndpoint,
+    relation: &str,
+    target: &RelationEndpoint,
+) -> zvariant::OwnedObjectPath {
+    let path = format!(
+        "{RECORDS_PATH}/{}__{}__{}",
+        encode_segment(relation),
+        encode_endpoint(subject),
+        encode_endpoint(target)
+    );
+    zvariant::OwnedObjectPath::try_from(path).expect("encoded record paths are valid object paths")
+}
+
+fn encode_endpoint(endpoint: &RelationEndpoint) -> String {
+    let fields = match endpoint {
+        RelationEndpoint::StableKey { kind, id } => vec![kind, id],
+        RelationEndpoint::DBusObject {
+            bus,
+            service,
+            path,
+            interface,
+        } => vec![bus, service, path, interface],
+    };
+    fields
+        .into_iter()
+        .map(|field| encode_segment(field))
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+fn encode_segment(input: &str) -> String {
+    let mut output = String::from("x");
+    for byte in input.bytes() {
+        output.push_str(&format!("{byte:02X}"));
+    }
+    output
+}
+
+/// Whether a conditional write failed because its expected revision was stale.
+pub fn is_revision_mismatch(error: &zbus::Error) -> bool {
+    matches!(error, zbus::Error::MethodError(name, ..) if name.as_str() == REVISION_MISMATCH_ERROR)
+}
 
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Type)]
 #[zvariant(signature = "a{ss}")]
@@ -35,6 +98,14 @@ impl RelationEndpoint {
         }
     }
 
+    /// The kind matched against relation schemas.
+    pub fn kind(&self) -> &str {
+        match self {
+            Self::StableKey { kind, .. } => kind,
+            Self::DBusObject { interface, .. } => interface,
+        }
+    }
+
     pub fn dbus_object(
         bus: impl Into<String>,
         service: impl Into<String>,
@@ -50,6 +121,50 @@ impl RelationEndpoint {
     }
 }
 
+/// Command-line form of an endpoint: `kind=id` for a stable key, and
+/// `bus:service/path#interface` for a D-Bus object, e.g.
+/// `session:org.kde.StatusNotifierItem-1/StatusNotifierItem#org.kde.StatusNotifierItem`.
+impl fmt::Display for RelationEndpoint {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::StableKey { kind, id } => write!(formatter, "{kind}={id}"),
+            Self::DBusObject {
+                bus,
+                service,
+                path,
+                interface,
+            } => write!(formatter, "{bus}:{service}{path}#{interface}"),
+        }
+    }
+}
+
+impl FromStr for RelationEndpoint {
+    type Err = String;
+
+    /// Bus names, object paths, and interfaces never contain `=`, so any
+    /// string with one is a stable key.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Some((kind, id)) = value.split_once('=') {
+            if kind.is_empty() || id.is_empty() {
+                return Err(format!("stable key {value:?} needs a kind and an id"));
+            }
+            return Ok(Self::stable_key(kind, id));
+        }
+        let dbus_object = || {
+            let (bus, rest) = value.split_once(':')?;
+            let (service, rest) = rest.split_at(rest.find('/')?);
+            let (path, interface) = rest.rsplit_once('#')?;
+            [bus, service, interface]
+                .iter()
+                .all(|field| !field.is_empty())
+                .then(|| Self::dbus_object(bus, service, path, interface))
+        };
+        dbus_object().ok_or_else(|| {
+            format!("endpoint {value:?} is neither kind=id nor bus:service/path#interface")
+        })
+    }
+}
+
 impl Serialize for RelationEndpoint {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -93,7 +208,7 @@ struct RelationEndpointVisitor;
 impl<'de> Visitor<'de> for RelationEndpointVisitor {
     type Value = RelationEndpoint;
 
-    fn expecting(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
         formatter.write_str("a relation endpoint dictionary or legacy endpoint string")
     }
 
@@ -123,22 +238,72 @@ impl<'de> Visitor<'de> for RelationEndpointVisitor {
         while let Some((key, value)) = access.next_entry::<String, String>()? {
             fields.insert(key, value);
         }
-        let endpoint_type = take_required(&mut fields, "type")?;
-        match endpoint_type.as_str() {
-            "stable-key" => Ok(RelationEndpoint::StableKey {
-                kind: take_required(&mut fields, "kind")?,
-                id: take_required(&mut fields, "id")?,
-            }),
-            "dbus-object" => Ok(RelationEndpoint::DBusObject {
-                bus: take_required(&mut fields, "bus")?,
-                service: take_required(&mut fields, "service")?,
-                path: take_required(&mut fields, "path")?,
-                interface: take_required(&mut fields, "interface")?,
-            }),
-            value => Err(A::Error::custom(format!(
-                "unknown relation endpoint type {value:?}"
-            ))),
-        }
+        endpoint_from_fields(fields)
+    }
+}
+
+/// Lets records travel inside variants, e.g. as the `Records` property of a
+/// watch.
+impl From<RelationEndpoint> for Value<'static> {
+    fn from(endpoint: RelationEndpoint) -> Self {
+        let fields = match endpoint {
+            RelationEndpoint::StableKey { kind, id } => HashMap::from([
+                ("type".to_owned(), "stable-key".to_owned()),
+                ("kind".to_owned(), kind),
+                ("id".to_owned(), id),
+            ]),
+            RelationEndpoint::DBusObject {
+                bus,
+                service,
+                path,
+                interface,
+            } => HashMap::from([
+                ("type".to_owned(), "dbus-object".to_owned()),
+                ("bus".to_owned(), bus),
+                ("service".to_owned(), service),
+                ("path".to_owned(), path),
+                ("interface".to_owned(), interface),
+            ]),
+        };
+        Value::from(fields)
+    }
+}
+
+impl TryFrom<Value<'_>> for RelationEndpoint {
+    type Error = zvariant::Error;
+
+    fn try_from(value: Value<'_>) -> Result<Self, Self::Error> {
+        endpoint_from_fields(HashMap::<String, String>::try_from(value)?)
+    }
+}
+
+impl TryFrom<OwnedValue> for RelationEndpoint {
+    type Error = zvariant::Error;
+
+    fn try_from(value: OwnedValue) -> Result<Self, Self::Error> {
+        Self::try_from(Value::from(value))
+    }
+}
+
+fn endpoint_from_fields<E>(mut fields: HashMap<String, String>) -> Result<RelationEndpoint, E>
+where
+    E: DeError,
+{
+    let endpoint_type = take_required(&mut fields, "type")?;
+    match endpoint_type.as_str() {
+        "stable-key" => Ok(RelationEndpoint::StableKey {
+            kind: take_required(&mut fields, "kind")?,
+            id: take_required(&mut fields, "id")?,
+        }),
+        "dbus-object" => Ok(RelationEndpoint::DBusObject {
+            bus: take_required(&mut fields, "bus")?,
+            service: take_required(&mut fields, "service")?,
+            path: take_required(&mut fields, "path")?,
+            interface: take_required(&mut fields, "interface")?,
+        }),
+        value => Err(E::custom(format!(
+            "unknown relation endpoint type {value:?}"
+        ))),
     }
 }
 
@@ -170,14 +335,699 @@ where
         .ok_or_else(|| E::custom(format!("missing relation endpoint field {key:?}")))
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Type)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Type, Value, OwnedValue)]
 pub struct RelationRecord {
     pub subject: RelationEndpoint,
     pub relation: String,
     pub target: RelationEndpoint,
-    pub metadata: HashMap<String, String>,
+    pub metadata: Metadata,
     pub created_at_unix_ms: u64,
     pub updated_at_unix_ms: u64,
+    /// Unique bus name of the client this record is scoped to, or empty when
+    /// the record outlives its writer.
+    #[serde(default)]
+    pub owner: String,
+    /// When Locus removes the record, or `0` for records that never expire.
+    #[serde(default)]
+    pub expires_at_unix_ms: u64,
+    /// Store-wide write counter stamped on every write of this record, and
+    /// never reused, so it changes whenever the record does. Conditional
+    /// writes compare against it; `0` stands for no record.
+    #[serde(default)]
+    pub revision: u64,
+}
+
+/// Record metadata, sent as `a{sv}`.
+pub type Metadata = HashMap<String, MetadataValue>;
+
+/// One metadata value. On the bus it is a variant holding `b`, `x`, `d`, or
+/// `s`; writers may send any integer type, which reads back as `x`. Snapshots
+/// store it as a plain JSON value, so older string-only metadata still loads.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MetadataValue {
+    Bool(bool),
+    Int(i64),
+    /// Always finite; NaN and infinities are rejected on input.
+    Double(f64),
+    String(String),
+}
+
+impl Eq for MetadataValue {}
+
+impl MetadataValue {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn to_value(&self) -> Value<'_> {
+        match self {
+            Self::Bool(value) => Value::from(*value),
+            Self::Int(value) => Value::from(*value),
+            Self::Double(value) => Value::from(*value),
+            Self::String(value) => Value::from(value.as_str()),
+        }
+    }
+}
+
+impl From<bool> for MetadataValue {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<i64> for MetadataValue {
+    fn from(value: i64) -> Self {
+        Self::Int(value)
+    }
+}
+
+impl From<f64> for MetadataValue {
+    fn from(value: f64) -> Self {
+        Self::Double(value)
+    }
+}
+
+impl From<String> for MetadataValue {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+
+impl From<&str> for MetadataValue {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_owned())
+    }
+}
+
+impl Type for MetadataValue {
+    fn signature() -> zvariant::Signature<'static> {
+        Value::signature()
+    }
+}
+
+impl Serialize for MetadataValue {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if !serializer.is_human_readable() {
+            return self.to_value().serialize(serializer);
+        }
+        match self {
+            Self::Bool(value) => serializer.serialize_bool(*value),
+            Self::Int(value) => serializer.serialize_i64(*value),
+            Self::Double(value) => serializer.serialize_f64(*value),
+            Self::String(value) => serializer.serialize_str(value),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for MetadataValue {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        if !deserializer.is_human_readable() {
+            let value = Value::from(OwnedValue::deserialize(deserializer)?);
+            return Self::try_from(value).map_err(D::Error::custom);
+        }
+        deserializer.deserialize_any(MetadataValueVisitor)
+    }
+}
+
+struct MetadataValueVisitor;
+
+impl<'de> Visitor<'de> for MetadataValueVisitor {
+    type Value = MetadataValue;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("a boolean, integer, finite number, or string")
+    }
+
+    fn visit_bool<E>(self, value: bool) -> Result<Self::Value, E>
+    where
+        E: DeError,
+    {
+        Ok(MetadataValue::Bool(value))
+    }
+
+    fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E>
+    where
+        E: DeError,
+    {
+        Ok(MetadataValue::Int(value))
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+    where
+        E: DeError,
+    {
+        i64::try_from(value)
+            .map(MetadataValue::Int)
+            .map_err(|_| E::custom(format!("metadata integer {value} is out of range")))
+    }
+
+    fn visit_f64<E>(self, value: f64) -> Result<Self::Value, E>
+    where
+        E: DeError,
+    {
+        finite(value).map_err(E::custom)
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: DeError,
+    {
+        Ok(MetadataValue::from(value))
+    }
+
+    fn visit_string<E>(self, value: String) -> Result<Self::Value, E>
+    where
+        E: DeError,
+    {
+        Ok(MetadataValue::String(value))
+    }
+}
+
+impl From<MetadataValue> for Value<'static> {
+    fn from(value: MetadataValue) -> Self {
+        match value {
+            MetadataValue::Bool(value) => Value::from(value),
+            MetadataValue::Int(value) => Value::from(value),
+            MetadataValue::Double(value) => Value::from(value),
+            MetadataValue::String(value) => Value::from(value),
+        }
+    }
+}
+
+impl TryFrom<Value<'_>> for MetadataValue {
+    type Error = zvariant::Error;
+
+    fn try_from(value: Value<'_>) -> Result<Self, Self::Error> {
+        let out_of_range = |value: u64| {
+            zvariant::Error::Message(format!("metadata integer {value} is out of range"))
+        };
+        Ok(match value {
+            Value::Bool(value) => Self::Bool(value),
+            Value::U8(value) => Self::Int(value.into()),
+            Value::I16(value) => Self::Int(value.into()),
+            Value::U16(value) => Self::Int(value.into()),
+            Value::I32(value) => Self::Int(value.into()),
+            Value::U32(value) => Self::Int(value.into()),
+            Value::I64(value) => Self::Int(value),
+            Value::U64(value) => Self::Int(i64::try_from(value).map_err(|_| out_of_range(value))?),
+            Value::F64(value) => finite(value).map_err(zvariant::Error::Message)?,
+            Value::Str(value) => Self::String(value.to_string()),
+            Value::Value(value) => Self::try_from(*value)?,
+            value => {
+                return Err(zvariant::Error::Message(format!(
+                    "unsupported metadata value type {}",
+                    value.value_signature()
+                )));
+            }
+        })
+    }
+}
+
+fn finite(value: f64) -> Result<MetadataValue, String> {
+    if value.is_finite() {
+        Ok(MetadataValue::Double(value))
+    } else {
+        Err(format!("metadata number {value} is not finite"))
+    }
+}
+
+/// Optional write behavior for `SetWithOptions`, `SetOneWithOptions`, and
+/// `Apply` set operations. Sent as an `a{sv}` dictionary; unknown keys are
+/// ignored.
+#[derive(Clone, Debug, Default, Eq, PartialEq, SerializeDict, DeserializeDict, Type)]
+#[zvariant(signature = "dict", rename_all = "kebab-case")]
+pub struct SetOptions {
+    /// Tie the record to the caller's unique bus name. Locus removes it when
+    /// that client disconnects, and never persists it.
+    pub owned: Option<bool>,
+    /// Remove the record this many milliseconds after the write. Expiry is
+    /// persisted, so it survives Locus restarts.
+    pub ttl_ms: Option<u64>,
+}
+
+impl SetOptions {
+    pub fn owned() -> Self {
+        Self {
+            owned: Some(true),
+            ..Self::default()
+        }
+    }
+
+    pub fn expiring_after(ttl_ms: u64) -> Self {
+        Self {
+            ttl_ms: Some(ttl_ms),
+            ..Self::default()
+        }
+    }
+
+    pub fn is_owned(&self) -> bool {
+        self.owned.unwrap_or(false)
+    }
+}
+
+/// How many targets a subject and how many subjects a target may have within
+/// one relation. Single-valued relations behave like `SetOne` on every write.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "kebab-case")]
+#[zvariant(signature = "s")]
+pub enum RelationCardinality {
+    /// Each subject has at most one target and each target at most one subject.
+    OneToOne,
+    /// Each subject has at most one target; targets may be shared.
+    ManyToOne,
+    #[default]
+    ManyToMany,
+}
+
+impl RelationCardinality {
+    pub fn single_target(self) -> bool {
+        matches!(self, Self::OneToOne | Self::ManyToOne)
+    }
+
+    pub fn single_subject(self) -> bool {
+        matches!(self, Self::OneToOne)
+    }
+}
+
+/// Declared shape of a relation name.
+///
+/// Empty kind lists accept any endpoint kind. A stable key's kind is its
+/// `kind`; a D-Bus object's kind is its `interface`.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Type)]
+pub struct RelationSchema {
+    pub relation: String,
+    pub cardinality: RelationCardinality,
+    pub subject_kinds: Vec<String>,
+    pub target_kinds: Vec<String>,
+}
+
+impl RelationSchema {
+    pub fn new(relation: impl Into<String>, cardinality: RelationCardinality) -> Self {
+        Self {
+            relation: relation.into(),
+            cardinality,
+            subject_kinds: Vec::new(),
+            target_kinds: Vec::new(),
+        }
+    }
+
+    pub fn with_subject_kind(mut self, kind: impl Into<String>) -> Self {
+        self.subject_kinds.push(kind.into());
+        self
+    }
+
+    pub fn with_target_kind(mut self, kind: impl Into<String>) -> Self {
+        self.target_kinds.push(kind.into());
+        self
+    }
+}
+
+/// One hop of a `Traverse` path.
+///
+/// On the bus a step is the relation name, prefixed with `^` to walk from
+/// target back to subject, e.g. `^org.rsynapse.workspace.project`.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Type)]
+#[serde(into = "String", from = "String")]
+#[zvariant(signature = "s")]
+pub enum TraversalStep {
+    /// Follow the relation from subject to target.
+    Forward(String),
+    /// Follow the relation from target to subject.
+    Inverse(String),
+}
+
+impl TraversalStep {
+    pub const INVERSE_PREFIX: char = '^';
+
+    pub fn forward(relation: impl Into<String>) -> Self {
+        Self::Forward(relation.into())
+    }
+
+    pub fn inverse(relation: impl Into<String>) -> Self {
+        Self::Inverse(relation.into())
+    }
+
+    pub fn relation(&self) -> &str {
+        match self {
+            Self::Forward(relation) | Self::Inverse(relation) => relation,
+        }
+    }
+}
+
+impl From<String> for TraversalStep {
+    fn from(step: String) -> Self {
+        match step.strip_prefix(Self::INVERSE_PREFIX) {
+            Some(relation) => Self::Inverse(relation.to_owned()),
+            None => Self::Forward(step),
+        }
+    }
+}
+
+impl From<TraversalStep> for String {
+    fn from(step: TraversalStep) -> Self {
+        match step {
+            TraversalStep::Forward(relation) => relation,
+            TraversalStep::Inverse(relation) => {
+                format!("{}{relation}", TraversalStep::INVERSE_PREFIX)
+            }
+        }
+    }
+}
+
+/// Result of `Traverse`: where the path ends and what it walked through.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Type)]
+pub struct Traversal {
+    /// Endpoints reached by the last step, each once, in endpoint order.
+    pub endpoints: Vec<RelationEndpoint>,
+    /// Every record followed, step by step.
+    pub records: Vec<RelationRecord>,
+}
+
+/// How `Import` combines imported records with the store.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "kebab-case")]
+#[zvariant(signature = "s")]
+pub enum ImportMode {
+    /// Durable records missing from the import are removed; owned and
+    /// live-scoped records are left alone.
+    #[default]
+    Replace,
+    /// Existing records are overwritten only by a newer `updated_at_unix_ms`.
+    MergeNewer,
+    /// Existing records are never overwritten.
+    MergeKeepExisting,
+}
+
+/// What an `Import` changed.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Type)]
+pub struct ImportSummary {
+    pub added: u32,
+    pub updated: u32,
+    pub removed: u32,
+}
+
+/// A store file Locus could not read at startup, and what it did instead.
+///
+/// The unreadable file is kept at `moved_to`. `restored_from` names the
+/// backup loaded in place of a broken snapshot, or is empty when Locus
+/// started without one.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Type, Value, OwnedValue)]
+pub struct StoreProblem {
+    pub path: String,
+    pub error: String,
+    pub moved_to: String,
+    pub restored_from: String,
+    pub at_unix_ms: u64,
+}
+
+/// The `Health` property: `"ok"`, or `"recovered"` when startup had to set
+/// files aside, with one problem per file.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Type, Value, OwnedValue)]
+pub struct StoreHealth {
+    pub status: String,
+    pub problems: Vec<StoreProblem>,
+}
+
+impl StoreHealth {
+    pub const OK: &str = "ok";
+    pub const RECOVERED: &str = "recovered";
+
+    pub fn from_problems(problems: Vec<StoreProblem>) -> Self {
+        let status = if problems.is_empty() {
+            Self::OK
+        } else {
+            Self::RECOVERED
+        };
+        Self {
+            status: status.to_owned(),
+            problems,
+        }
+    }
+}
+
+/// One committed record change in the audit log returned by `History`.
+///
+/// `before` and `after` are zero-or-one arrays: an added record has no
+/// `before`, a removed record no `after`.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Type)]
+pub struct HistoryEntry {
+    /// The method that made the change, e.g. `SetOne` or `Apply`, or
+    /// `Expire`, `Disconnect`, or `Prune` for removals Locus made itself.
+    pub operation: String,
+    pub before: Vec<RelationRecord>,
+    pub after: Vec<RelationRecord>,
+    pub at_unix_ms: u64,
+    /// Unique bus name of the writer, empty for removals Locus made itself.
+    pub caller: String,
+    /// Writer pid from the bus credentials, or `0` when unknown.
+    pub caller_pid: u32,
+    /// Writer executable read through `/proc`, or empty when unknown.
+    pub caller_exe: String,
+}
+
+/// Which entries `History` returns. Sent as an `a{sv}` dictionary; unset
+/// keys match everything. Endpoint and relation keys match either side of
+/// the change.
+#[derive(Clone, Debug, Default, Eq, PartialEq, SerializeDict, DeserializeDict, Type)]
+#[zvariant(signature = "dict", rename_all = "kebab-case")]
+pub struct HistoryFilter {
+    pub subject: Option<RelationEndpoint>,
+    pub relation: Option<String>,
+    pub target: Option<RelationEndpoint>,
+    pub caller: Option<String>,
+    pub since_unix_ms: Option<u64>,
+}
+
+/// One mutation inside an atomic `Apply` batch.
+///
+/// On the bus each operation is a `(sa{ss}saa{ss}a{sv}a{sv})` struct of
+/// operation name, subject, relation, zero-or-one target, metadata, and set
+/// options. `Unset` and `Clear` ignore metadata and options; `Clear` takes no
+/// target.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(into = "RelationOperationWire", try_from = "RelationOperationWire")]
+pub enum RelationOperation {
+    Set {
+        subject: RelationEndpoint,
+        relation: String,
+        target: RelationEndpoint,
+        metadata: Metadata,
+        options: SetOptions,
+    },
+    SetOne {
+        subject: RelationEndpoint,
+        relation: String,
+        target: RelationEndpoint,
+        metadata: Metadata,
+        options: SetOptions,
+    },
+    Unset {
+        subject: RelationEndpoint,
+        relation: String,
+        target: RelationEndpoint,
+    },
+    Clear {
+        subject: RelationEndpoint,
+        relation: String,
+    },
+}
+
+impl RelationOperation {
+    pub fn set(
+        subject: RelationEndpoint,
+        relation: impl Into<String>,
+        target: RelationEndpoint,
+        metadata: Metadata,
+    ) -> Self {
+        Self::Set {
+            subject,
+            relation: relation.into(),
+            target,
+            metadata,
+            options: SetOptions::default(),
+        }
+    }
+
+    pub fn set_one(
+        subject: RelationEndpoint,
+        relation: impl Into<String>,
+        target: RelationEndpoint,
+        metadata: Metadata,
+    ) -> Self {
+        Self::SetOne {
+            subject,
+            relation: relation.into(),
+            target,
+            metadata,
+            options: SetOptions::default(),
+        }
+    }
+
+    pub fn unset(
+        subject: RelationEndpoint,
+        relation: impl Into<String>,
+        target: RelationEndpoint,
+    ) -> Self {
+        Self::Unset {
+            subject,
+            relation: relation.into(),
+            target,
+        }
+    }
+
+    pub fn clear(subject: RelationEndpoint, relation: impl Into<String>) -> Self {
+        Self::Clear {
+            subject,
+            relation: relation.into(),
+        }
+    }
+
+    pub fn relation(&self) -> &str {
+        match self {
+            Self::Set { relation, .. }
+            | Self::SetOne { relation, .. }
+            | Self::Unset { relation, .. }
+            | Self::Clear { relation, .. } => relation,
+        }
+    }
+
+    /// Replaces the options of a set operation; other operations are
+    /// returned unchanged.
+    pub fn with_options(mut self, new_options: SetOptions) -> Self {
+        if let Self::Set { options, .. } | Self::SetOne { options, .. } = &mut self {
+            *options = new_options;
+        }
+        self
+    }
+}
+
+impl Type for RelationOperation {
+    fn signature() -> zvariant::Signature<'static> {
+        RelationOperationWire::signature()
+    }
+}
+
+#[derive(Serialize, Deserialize, Type)]
+struct RelationOperationWire {
+    operation: String,
+    subject: RelationEndpoint,
+    relation: String,
+    target: Vec<RelationEndpoint>,
+    metadata: Metadata,
+    options: SetOptions,
+}
+
+impl From<RelationOperation> for RelationOperationWire {
+    fn from(operation: RelationOperation) -> Self {
+        let (name, subject, relation, target, metadata, options) = match operation {
+            RelationOperation::Set {
+                subject,
+                relation,
+                target,
+                metadata,
+                options,
+            } => ("set", subject, relation, vec![target], metadata, options),
+            RelationOperation::SetOne {
+                subject,
+                relation,
+                target,
+                metadata,
+                options,
+            } => (
+                "set-one",
+                subject,
+                relation,
+                vec![target],
+                metadata,
+                options,
+            ),
+            RelationOperation::Unset {
+                subject,
+                relation,
+                target,
+            } => (
+                "unset",
+                subject,
+                relation,
+                vec![target],
+                HashMap::new(),
+                SetOptions::default(),
+            ),
+            RelationOperation::Clear { subject, relation } => (
+                "clear",
+                subject,
+                relation,
+                Vec::new(),
+                HashMap::new(),
+                SetOptions::default(),
+            ),
+        };
+        Self {
+            operation: name.to_owned(),
+            subject,
+            relation,
+            target,
+            metadata,
+            options,
+        }
+    }
+}
+
+impl TryFrom<RelationOperationWire> for RelationOperation {
+    type Error = String;
+
+    fn try_from(wire: RelationOperationWire) -> Result<Self, Self::Error> {
+        let RelationOperationWire {
+            operation,
+            subject,
+            relation,
+            target,
+            metadata,
+            options,
+        } = wire;
+        if operation == "clear" {
+            if !target.is_empty() {
+                return Err("clear operation must not carry a target".to_owned());
+            }
+            return Ok(Self::Clear { subject, relation });
+        }
+
+        let mut target = target.into_iter();
+        let (Some(target), None) = (target.next(), target.next()) else {
+            return Err(format!("{operation} operation requires exactly one target"));
+        };
+        match operation.as_str() {
+            "set" => Ok(Self::Set {
+                subject,
+                relation,
+                target,
+                metadata,
+                options,
+            }),
+            "set-one" => Ok(Self::SetOne {
+                subject,
+                relation,
+                target,
+                metadata,
+                options,
+            }),
+            "unset" => Ok(Self::Unset {
+                subject,
+                relation,
+                target,
+            }),
+            value => Err(format!("unknown relation operation {value:?}")),
+        }
+    }
 }
 
 #[proxy(
@@ -192,12 +1042,15 @@ pub trait Relations {
     #[zbus(property)]
     fn relations(&self) -> zbus::Result<Vec<String>>;
 
+    #[zbus(property)]
+    fn health(&self) -> zbus::Result<StoreHealth>;
+
     async fn set(
         &self,
         subject: RelationEndpoint,
         relation: &str,
         target: RelationEndpoint,
-        metadata: HashMap<String, String>,
+        metadata: Metadata,
     ) -> zbus::Result<RelationRecord>;
 
     async fn set_one(
@@ -205,7 +1058,34 @@ pub trait Relations {
         subject: RelationEndpoint,
         relation: &str,
         target: RelationEndpoint,
-        metadata: HashMap<String, String>,
+        metadata: Metadata,
+    ) -> zbus::Result<RelationRecord>;
+
+    async fn set_with_options(
+        &self,
+        subject: RelationEndpoint,
+        relation: &str,
+        target: RelationEndpoint,
+        metadata: Metadata,
+        options: SetOptions,
+    ) -> zbus::Result<RelationRecord>;
+
+    async fn set_one_with_options(
+        &self,
+        subject: RelationEndpoint,
+        relation: &str,
+        target: RelationEndpoint,
+        metadata: Metadata,
+        options: SetOptions,
+    ) -> zbus::Result<RelationRecord>;
+
+    async fn patch_metadata(
+        &self,
+        subject: RelationEndpoint,
+        relation: &str,
+        target: RelationEndpoint,
+        set: Metadata,
+        remove: &[&str],
     ) -> zbus::Result<RelationRecord>;
 
     async fn unset(
@@ -215,8 +1095,40 @@ pub trait Relations {
         target: RelationEndpoint,
     ) -> zbus::Result<bool>;
 
+    /// Like `set`, but fails with [`REVISION_MISMATCH_ERROR`] unless the
+    /// record's revision is `expected_revision`; `0` expects no record.
+    async fn set_if_revision(
+        &self,
+        subject: RelationEndpoint,
+        relation: &str,
+        target: RelationEndpoint,
+        metadata: Metadata,
+        expected_revision: u64,
+    ) -> zbus::Result<RelationRecord>;
+
+    /// Like `set_one`, but compares `expected_revision` with the newest of
+    /// the subject's records under `relation`; `0` expects none.
+    async fn set_one_if_revision(
+        &self,
+        subject: RelationEndpoint,
+        relation: &str,
+        target: RelationEndpoint,
+        metadata: Metadata,
+        expected_revision: u64,
+    ) -> zbus::Result<RelationRecord>;
+
+    async fn unset_if_revision(
+        &self,
+        subject: RelationEndpoint,
+        relation: &str,
+        target: RelationEndpoint,
+        expected_revision: u64,
+    ) -> zbus::Result<bool>;
+
     async fn clear(&self, subject: RelationEndpoint, relation: &str) -> zbus::Result<u32>;
 
+    async fn apply(&self, operations: Vec<RelationOperation>) -> zbus::Result<Vec<RelationRecord>>;
+
     async fn targets(
         &self,
         subject: RelationEndpoint,
@@ -229,27 +1141,282 @@ pub trait Relations {
         target: RelationEndpoint,
     ) -> zbus::Result<Vec<RelationEndpoint>>;
 
+    /// One (subject, targets) pair per distinct subject, in request order,
+    /// so a client can resolve many subjects in one call.
+    async fn targets_many(
+        &self,
+        subjects: Vec<RelationEndpoint>,
+        relation: &str,
+    ) -> zbus::Result<Vec<(RelationEndpoint, Vec<RelationEndpoint>)>>;
+
+    /// One (target, subjects) pair per distinct target, in request order.
+    async fn subjects_many(
+        &self,
+        relation: &str,
+        targets: Vec<RelationEndpoint>,
+    ) -> zbus::Result<Vec<(RelationEndpoint, Vec<RelationEndpoint>)>>;
+
+    /// Records of `relation`, or of every relation when it is empty.
     async fn list(&self, relation: &str) -> zbus::Result<Vec<RelationRecord>>;
+
+    /// Like `list`, keeping only records whose metadata holds every entry of
+    /// `metadata`.
+    async fn list_matching(
+        &self,
+        relation: &str,
+        metadata: Metadata,
+    ) -> zbus::Result<Vec<RelationRecord>>;
+
+    async fn traverse(
+        &self,
+        start: RelationEndpoint,
+        path: Vec<TraversalStep>,
+    ) -> zbus::Result<Traversal>;
+
+    /// Every durable record, in the shape of the relation snapshot file.
+    async fn export(&self) -> zbus::Result<Vec<RelationRecord>>;
+
+    async fn import(
+        &self,
+        records: Vec<RelationRecord>,
+        mode: ImportMode,
+    ) -> zbus::Result<ImportSummary>;
+
+    /// Newest entries first; a `limit` of `0` returns every retained entry.
+    async fn history(&self, filter: HistoryFilter, limit: u32) -> zbus::Result<Vec<HistoryEntry>>;
+
+    /// Takes zero-or-one subject and target endpoints and a relation that
+    /// matches every relation when empty. Returns the path of a `Watch1`
+    /// object that lives until this connection closes or unwatches it.
+    async fn watch(
+        &self,
+        subject: Vec<RelationEndpoint>,
+        relation: &str,
+        target: Vec<RelationEndpoint>,
+    ) -> zbus::Result<zvariant::OwnedObjectPath>;
+
+    /// Removes a watch this connection opened.
+    async fn unwatch(&self, path: &zvariant::ObjectPath<'_>) -> zbus::Result<()>;
+
+    async fn schemas(&self) -> zbus::Result<Vec<RelationSchema>>;
+
+    async fn register_schema(&self, schema: RelationSchema) -> zbus::Result<()>;
+
+    async fn unregister_schema(&self, relation: &str) -> zbus::Result<bool>;
+
+    #[zbus(signal)]
+    fn relation_added(&self, record: RelationRecord) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn relation_updated(&self, record: RelationRecord) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn relation_removed(&self, record: RelationRecord) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn relation_cleared(
+        &self,
+        subject: RelationEndpoint,
+        relation: String,
+        removed_count: u32,
+    ) -> zbus::Result<()>;
+
+    /// Emitted once per problem found while opening the store.
+    #[zbus(signal)]
+    fn store_error(&self, problem: StoreProblem) -> zbus::Result<()>;
+}
+
+/// One exported record, at [`record_path`] under the relations object, which
+/// is also an `org.freedesktop.DBus.ObjectManager`.
+#[proxy(
+    interface = "org.rsynapse.Locus.Relation1",
+    default_service = "org.rsynapse.Locus"
+)]
+pub trait Record {
+    #[zbus(property)]
+    fn subject(&self) -> zbus::Result<RelationEndpoint>;
+
+    #[zbus(property)]
+    fn relation(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn target(&self) -> zbus::Result<RelationEndpoint>;
+
+    #[zbus(property)]
+    fn metadata(&self) -> zbus::Result<Metadata>;
+
+    #[zbus(property)]
+    fn created_at_unix_ms(&self) -> zbus::Result<u64>;
+
+    #[zbus(property)]
+    fn updated_at_unix_ms(&self) -> zbus::Result<u64>;
+
+    #[zbus(property)]
+    fn owner(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn expires_at_unix_ms(&self) -> zbus::Result<u64>;
+
+    #[zbus(property)]
+    fn revision(&self) -> zbus::Result<u64>;
+}
+
+pub use watch::{WatchProxy, WatchProxyBlocking};
+
+/// Kept apart from the relations proxy because both generate types named
+/// after the same signals.
+pub mod watch {
+    use zbus::proxy;
+
+    use crate::RelationRecord;
+
+    /// A filtered view returned by `Relations::watch`. Signals mirror the
+    /// relations object but carry only matching records.
+    #[proxy(
+        interface = "org.rsynapse.Locus.Watch1",
+        default_service = "org.rsynapse.Locus"
+    )]
+    pub trait Watch {
+        #[zbus(property)]
+        fn records(&self) -> zbus::Result<Vec<RelationRecord>>;
+
+        #[zbus(signal)]
+        fn relation_added(&self, record: RelationRecord) -> zbus::Result<()>;
+
+        #[zbus(signal)]
+        fn relation_updated(&self, record: RelationRecord) -> zbus::Result<()>;
+
+        #[zbus(signal)]
+        fn relation_removed(&self, record: RelationRecord) -> zbus::Result<()>;
+    }
 }
 
 pub mod keys {
     pub const APP_INSTANCE_ID: &str = "org.rsynapse.app-instance.id";
     pub const BAZEL_INVOCATION_ID: &str = "org.rsynapse.bazel.invocation.id";
+    pub const ICON_GLYPH: &str = "org.rsynapse.icon.glyph";
     pub const NIRI_OUTPUT_NAME: &str = "org.rsynapse.niri.output.name";
     pub const NIRI_WORKSPACE_ID: &str = "org.rsynapse.niri.workspace.id";
     pub const NIRI_WORKSPACE_NAME: &str = "org.rsynapse.niri.workspace.name";
     pub const NIRI_WINDOW_ID: &str = "org.rsynapse.niri.window.id";
     pub const PROJECT_PATH: &str = "org.rsynapse.project.path";
     pub const AGENT_SESSION_ID: &str = "org.rsynapse.agent.session.id";
+
+    /// A registered stable-key kind and the ids it accepts.
+    #[derive(Clone, Copy, Debug)]
+    pub struct KeyKind {
+        pub kind: &'static str,
+        /// What a valid id looks like, for error messages.
+        pub format: &'static str,
+        accepts: fn(&str) -> bool,
+    }
+
+    impl KeyKind {
+        pub fn accepts(&self, id: &str) -> bool {
+            (self.accepts)(id)
+        }
+    }
+
+    /// Every kind Locus knows the id format of.
+    pub const KINDS: &[KeyKind] = &[
+        KeyKind {
+            kind: APP_INSTANCE_ID,
+            format: "a lowercase hyphenated UUID",
+            accepts: is_uuid,
+        },
+        KeyKind {
+            kind: BAZEL_INVOCATION_ID,
+            format: "a lowercase hyphenated UUID",
+            accepts: is_uuid,
+        },
+        KeyKind {
+            kind: ICON_GLYPH,
+            format: "a non-blank string",
+            accepts: is_nonblank,
+        },
+        KeyKind {
+            kind: NIRI_OUTPUT_NAME,
+            format: "a connector name without whitespace",
+            accepts: is_word,
+        },
+        KeyKind {
+            kind: NIRI_WORKSPACE_ID,
+            format: "a decimal number without leading zeros",
+            accepts: is_number,
+        },
+        KeyKind {
+            kind: NIRI_WORKSPACE_NAME,
+            format: "a non-blank string",
+            accepts: is_nonblank,
+        },
+        KeyKind {
+            kind: NIRI_WINDOW_ID,
+            format: "a decimal number without leading zeros",
+            accepts: is_number,
+        },
+        KeyKind {
+            kind: PROJECT_PATH,
+            format: "an absolute path without trailing slashes, empty, . or .. components",
+            accepts: is_normalized_path,
+        },
+        KeyKind {
+            kind: AGENT_SESSION_ID,
+            format: "a string without whitespace",
+            accepts: is_word,
+        },
+    ];
+
+    pub fn lookup(kind: &str) -> Option<&'static KeyKind> {
+        KINDS.iter().find(|known| known.kind == kind)
+    }
+
+    fn is_nonblank(id: &str) -> bool {
+        !id.trim().is_empty()
+    }
+
+    fn is_word(id: &str) -> bool {
+        !id.is_empty() && !id.chars().any(char::is_whitespace)
+    }
+
+    /// Spelled the way `u64::to_string` would, so one number has one key.
+    fn is_number(id: &str) -> bool {
+        id.parse::<u64>()
+            .is_ok_and(|number| number.to_string() == id)
+    }
+
+    fn is_uuid(id: &str) -> bool {
+        let groups = id.split('-').collect::<Vec<_>>();
+        groups.len() == 5
+            && groups.iter().zip([8, 4, 4, 4, 12]).all(|(group, len)| {
+                group.len() == len
+                    && group
+                        .bytes()
+                        .all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+            })
+    }
+
+    fn is_normalized_path(id: &str) -> bool {
+        if id == "/" {
+            return true;
+        }
+        id.strip_prefix('/').is_some_and(|relative| {
+            relative
+                .split('/')
+                .all(|component| !matches!(component, "" | "." | ".."))
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
 
-    use zvariant::{LE, Type, serialized::Context, to_bytes};
+    use zvariant::{LE, OwnedValue, Type, Value, serialized::Context, to_bytes};
 
-    use super::{RelationEndpoint, RelationRecord};
+    use super::{
+        HistoryFilter, ImportMode, Metadata, MetadataValue, RelationCardinality, RelationEndpoint,
+        RelationOperation, RelationRecord, RelationSchema, SetOptions, TraversalStep,
+    };
 
     #[test]
     fn endpoint_uses_dictionary_signature() {
@@ -307,15 +1474,52 @@ mod tests {
                 "/org/example/Object",
                 "org.example.Interface",
             ),
-            metadata: HashMap::from([("name".to_owned(), "value".to_owned())]),
+            metadata: HashMap::from([
+                ("name".to_owned(), "value".into()),
+                ("count".to_owned(), MetadataValue::Int(-3)),
+                ("ratio".to_owned(), MetadataValue::Double(0.5)),
+                ("pinned".to_owned(), MetadataValue::Bool(true)),
+            ]),
             created_at_unix_ms: 1,
             updated_at_unix_ms: 2,
+            owner: ":1.42".to_owned(),
+            expires_at_unix_ms: 3,
+            revision: 4,
         };
         let bytes = to_bytes(Context::new_dbus(LE, 0), &record).expect("serialize record");
         let decoded: RelationRecord = bytes.deserialize().expect("deserialize record").0;
         assert_eq!(decoded, record);
     }
 
+    #[test]
+    fn metadata_widens_integers_and_stores_plain_json() {
+        let sent = HashMap::from([
+            ("small".to_owned(), Value::from(7u32)),
+            ("signed".to_owned(), Value::from(-7i16)),
+        ]);
+        let bytes = to_bytes(Context::new_dbus(LE, 0), &sent).expect("serialize metadata");
+        let received: Metadata = bytes.deserialize().expect("deserialize metadata").0;
+        assert_eq!(
+            received,
+            HashMap::from([
+                ("small".to_owned(), MetadataValue::Int(7)),
+                ("signed".to_owned(), MetadataValue::Int(-7)),
+            ])
+        );
+
+        let json = r#"{"branch":"main","count":3,"pinned":false,"ratio":0.25}"#;
+        let metadata: Metadata = serde_json::from_str(json).expect("metadata json");
+        assert_eq!(metadata["count"], MetadataValue::Int(3));
+        assert_eq!(metadata["branch"].as_str(), Some("main"));
+        let mut entries = serde_json::to_value(&metadata).expect("metadata to json");
+        entries.sort_all_objects();
+        assert_eq!(entries.to_string(), json);
+
+        assert!(MetadataValue::try_from(Value::from(f64::NAN)).is_err());
+        assert!(MetadataValue::try_from(Value::from(u64::MAX)).is_err());
+        assert!(MetadataValue::try_from(Value::from(vec!["a"])).is_err());
+    }
+
     #[test]
     fn record_reads_legacy_endpoint_strings() {
         let record: RelationRecord = serde_json::from_str(
@@ -339,4 +1543,223 @@ mod tests {
             RelationEndpoint::stable_key("org.rsynapse.agent.session.id", "codex/session")
         );
     }
+
+    #[test]
+    fn operation_uses_struct_signature() {
+        assert_eq!(RelationOperation::signature(), "(sa{ss}saa{ss}a{sv}a{sv})");
+    }
+
+    #[test]
+    fn operations_roundtrip_over_zvariant() {
+        let operations = vec![
+            RelationOperation::set_one(
+                RelationEndpoint::stable_key("org.rsynapse.niri.workspace.id", "5"),
+                "org.rsynapse.workspace.project",
+                RelationEndpoint::stable_key("org.rsynapse.project.path", "/tmp/project"),
+                HashMap::from([("name".to_owned(), "project".into())]),
+            )
+            .with_options(SetOptions::owned()),
+            RelationOperation::unset(
+                RelationEndpoint::stable_key("org.rsynapse.niri.workspace.id", "5"),
+                "org.rsynapse.workspace.tag",
+                RelationEndpoint::stable_key("org.rsynapse.tag", "old"),
+            ),
+            RelationOperation::clear(
+                RelationEndpoint::stable_key("org.rsynapse.niri.workspace.id", "6"),
+                "org.rsynapse.workspace.project",
+            ),
+        ];
+        let bytes = to_bytes(Context::new_dbus(LE, 0), &operations).expect("serialize operations");
+        let decoded: Vec<RelationOperation> =
+            bytes.deserialize().expect("deserialize operations").0;
+        assert_eq!(decoded, operations);
+    }
+
+    #[test]
+    fn schema_roundtrips_over_zvariant() {
+        assert_eq!(RelationSchema::signature(), "(ssasas)");
+        let schema = RelationSchema::new(
+            "org.rsynapse.workspace.project",
+            RelationCardinality::ManyToOne,
+        )
+        .with_subject_kind("org.rsynapse.niri.workspace.id")
+        .with_target_kind("org.rsynapse.project.path");
+        let bytes = to_bytes(Context::new_dbus(LE, 0), &schema).expect("serialize schema");
+        let decoded: RelationSchema = bytes.deserialize().expect("deserialize schema").0;
+        assert_eq!(decoded, schema);
+
+        let cardinality: String = to_bytes(Context::new_dbus(LE, 0), &schema.cardinality)
+            .expect("serialize cardinality")
+            .deserialize()
+            .expect("deserialize cardinality as string")
+            .0;
+        assert_eq!(cardinality, "many-to-one");
+    }
+
+    #[test]
+    fn traversal_steps_use_inverse_prefix_on_the_wire() {
+        assert_eq!(TraversalStep::signature(), "s");
+        let path = vec![
+            TraversalStep::forward("org.rsynapse.window.app-instance"),
+            TraversalStep::inverse("org.rsynapse.workspace.project"),
+        ];
+        let bytes = to_bytes(Context::new_dbus(LE, 0), &path).expect("serialize path");
+        let wire: Vec<String> = bytes.deserialize().expect("deserialize path as strings").0;
+        assert_eq!(
+            wire,
+            [
+                "org.rsynapse.window.app-instance",
+                "^org.rsynapse.workspace.project"
+            ]
+        );
+        let decoded: Vec<TraversalStep> = bytes.deserialize().expect("deserialize path").0;
+        assert_eq!(decoded, path);
+    }
+
+    #[test]
+    fn records_roundtrip_through_variants() {
+        let records = vec![RelationRecord {
+            subject: RelationEndpoint::stable_key("org.rsynapse.niri.window.id", "7"),
+            relation: "org.rsynapse.window.app-instance".to_owned(),
+            target: RelationEndpoint::dbus_object(
+                "session",
+                "org.rsynapse.Niri",
+                "/org/rsynapse/Niri/Windows/window_7",
+                "org.rsynapse.Niri1.Window",
+            ),
+            metadata: HashMap::from([
+                ("source".to_owned(), "test".into()),
+                ("count".to_owned(), MetadataValue::Int(2)),
+            ]),
+            created_at_unix_ms: 1,
+            updated_at_unix_ms: 2,
+            owner: ":1.42".to_owned(),
+            expires_at_unix_ms: 3,
+            revision: 4,
+        }];
+
+        let value = OwnedValue::try_from(Value::from(records.clone())).expect("owned value");
+        let decoded = Vec::<RelationRecord>::try_from(value).expect("records from value");
+
+        assert_eq!(decoded, records);
+    }
+
+    #[test]
+    fn endpoint_parses_and_prints_command_line_syntax() {
+        let key: RelationEndpoint = "org.rsynapse.project.path=/home/me/src/a=b"
+            .parse()
+            .expect("stable key");
+        assert_eq!(
+            key,
+            RelationEndpoint::stable_key("org.rsynapse.project.path", "/home/me/src/a=b")
+        );
+
+        let object = RelationEndpoint::dbus_object(
+            "session",
+            ":1.42",
+            "/StatusNotifierItem",
+            "org.kde.StatusNotifierItem",
+        );
+        assert_eq!(
+            object.to_string(),
+            "session::1.42/StatusNotifierItem#org.kde.StatusNotifierItem"
+        );
+        assert_eq!(object.to_string().parse(), Ok(object));
+        assert_eq!(key.to_string().parse(), Ok(key));
+
+        for invalid in ["=7", "kind=", "session:org.kde.Tray", "org.kde.Tray/x#i"] {
+            assert!(invalid.parse::<RelationEndpoint>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn import_mode_uses_kebab_case_strings() {
+        assert_eq!(ImportMode::signature(), "s");
+        let bytes = to_bytes(Context::new_dbus(LE, 0), &ImportMode::MergeKeepExisting)
+            .expect("serialize mode");
+        let decoded: String = bytes.deserialize().expect("deserialize mode").0;
+        assert_eq!(decoded, "merge-keep-existing");
+    }
+
+    #[test]
+    fn history_filter_roundtrips_endpoints_in_a_dictionary() {
+        assert_eq!(HistoryFilter::signature(), "a{sv}");
+        let filter = HistoryFilter {
+            subject: Some(RelationEndpoint::stable_key(
+                "org.rsynapse.niri.workspace.id",
+                "3",
+            )),
+            relation: Some("org.rsynapse.workspace.project".to_owned()),
+            since_unix_ms: Some(10),
+            ..HistoryFilter::default()
+        };
+        let bytes = to_bytes(Context::new_dbus(LE, 0), &filter).expect("serialize filter");
+        let decoded: HistoryFilter = bytes.deserialize().expect("deserialize filter").0;
+        assert_eq!(decoded, filter);
+    }
+
+    #[test]
+    fn registered_kinds_check_id_formats() {
+        let accepts = |kind, id| super::keys::lookup(kind).expect("registered").accepts(id);
+
+        assert!(accepts(super::keys::PROJECT_PATH, "/home/me/src/rsynapse"));
+        assert!(accepts(super::keys::PROJECT_PATH, "/"));
+        for path in [
+            "/home/me/",
+            "home/me",
+            "/home//me",
+            "/home/./me",
+            "/home/me/..",
+        ] {
+            assert!(!accepts(super::keys::PROJECT_PATH, path), "{path}");
+        }
+        assert!(accepts(super::keys::NIRI_WORKSPACE_ID, "0"));
+        assert!(accepts(super::keys::NIRI_WORKSPACE_ID, "42"));
+        for id in ["042", "-1", "+4", "4a", ""] {
+            assert!(!accepts(super::keys::NIRI_WORKSPACE_ID, id), "{id}");
+        }
+        assert!(accepts(
+            super::keys::APP_INSTANCE_ID,
+            "0d9c3b6a-2f4e-4b8d-a1c7-5e6f8a9b0c12"
+        ));
+        for id in [
+            "kitty-1",
+            "0D9C3B6A-2F4E-4B8D-A1C7-5E6F8A9B0C12",
+            "0d9c3b6a2f4e4b8da1c75e6f8a9b0c12",
+            "0d9c3b6a-2f4e-4b8d-a1c7-5e6f8a9b0c1",
+        ] {
+            assert!(!accepts(super::keys::APP_INSTANCE_ID, id), "{id}");
+        }
+        assert!(!accepts(super::keys::NIRI_OUTPUT_NAME, "eDP 1"));
+        assert!(accepts(super::keys::NIRI_WORKSPACE_NAME, "my code"));
+        assert!(super::keys::lookup("org.rsynapse.tag").is_none());
+    }
+
+    #[test]
+    fn record_paths_are_single_distinct_elements() {
+        let key = RelationEndpoint::stable_key;
+        let object = RelationEndpoint::dbus_object("session", "org.a", "/a", "org.a.I");
+        let paths = [
+            super::record_path(&key("k", "1"), "r", &key("k", "2")),
+            super::record_path(&key("k", "2"), "r", &key("k", "1")),
+            super::record_path(&key("k", "1"), "r", &object),
+            super::record_path(&object, "r", &key("k", "1")),
+            super::record_path(&key("k", "1"), "r_x", &key("k", "2")),
+        ];
+        assert_eq!(
+            paths[0].as_str(),
+            "/org/rsynapse/Locus/records/x72__x6B_x31__x6B_x32"
+        );
+        for path in &paths {
+            let element = path
+                .strip_prefix("/org/rsynapse/Locus/records/")
+                .expect("under records");
+            assert!(!element.contains('/'), "{path}");
+        }
+        let distinct = paths
+            .iter()
+            .map(|path| path.as_str())
+            .collect::<std::collections::BTreeSet<_>>();
+        assert_eq!(distinct.len(), paths.len());
+    }
 }
@@ -0,0 +1,416 @@
+//! Prunes relations whose live endpoints went away.
+//!
+//! Locus watches bus ownership and the `InterfacesRemoved` signals of every
+//! service its D-Bus object endpoints name, plus the niri bridge. A relation
+//! is removed when a D-Bus object endpoint loses its interface or its service
+//! loses its owner, and when the niri bridge drops the window behind a
+//! `NIRI_WINDOW_ID` key. Window keys outlive the bridge itself: a restarted
+//! `niri-dbus` serves the same windows under the same ids, so only a removed
+//! window object prunes them.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Arc,
+};
+
+use futures_util::{
+    StreamExt,
+    stream::{AbortHandle, BoxStream, SelectAll, abortable},
+};
+use tokio::sync::Notify;
+use tracing::warn;
+use zbus::{
+    Connection, MatchRule, Message, MessageStream,
+    fdo::{self, NameOwnerChangedStream},
+    message::Type as MessageType,
+    names::BusName,
+    zvariant::OwnedObjectPath,
+};
+
+use locus::{OBJECT_PATH, RelationEndpoint, keys};
+
+use crate::service::RelationsService;
+
+const SESSION_BUS: &str = "session";
+const OBJECT_MANAGER_INTERFACE: &str = "org.freedesktop.DBus.ObjectManager";
+
+/// Something live that relation endpoints may point at and that has gone.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Vanished {
+    /// `interfaces` were removed from `path` on the service known by any of
+    /// `services`.
+    Object {
+        services: Vec<String>,
+        path: String,
+        interfaces: Vec<String>,
+    },
+    /// The bus name `name` lost its owner.
+    Service { name: String },
+}
+
+impl Vanished {
+    pub fn matches(&self, endpoint: &RelationEndpoint) -> bool {
+        match (self, endpoint) {
+            (
+                Self::Object {
+                    services,
+                    path,
+                    interfaces,
+                },
+                RelationEndpoint::DBusObject {
+                    bus,
+                    service,
+                    path: endpoint_path,
+                    interface,
+                },
+            ) => {
+                bus == SESSION_BUS
+                    && services.contains(service)
+                    && path == endpoint_path
+                    && interfaces.contains(interface)
+            }
+            (
+                Self::Object {
+                    services,
+                    path,
+                    interfaces,
+                },
+                RelationEndpoint::StableKey { kind, id },
+            ) => {
+                kind == keys::NIRI_WINDOW_ID
+                    && services
+                        .iter()
+                        .any(|service| service == niri_dbus::BUS_NAME)
+                    && interfaces
+                        .iter()
+                        .any(|interface| interface == niri_dbus::WINDOW_INTERFACE)
+                    && niri_dbus::window_id(path).is_some_and(|window| window.to_string() == *id)
+            }
+            (Self::Service { name }, RelationEndpoint::DBusObject { bus, service, .. }) => {
+                bus == SESSION_BUS && service == name
+            }
+            (Self::Service { .. }, RelationEndpoint::StableKey { .. }) => false,
+        }
+    }
+}
+
+/// `InterfacesRemoved` signals, each tagged with the watched service whose
+/// match rule let it through.
+type RemovedObjects = SelectAll<BoxStream<'static, (String, Message)>>;
+
+pub struct Subscriptions {
+    owner_changes: NameOwnerChangedStream<'static>,
+    removed_objects: RemovedObjects,
+    /// Stops the stream of each watched service, dropping its match rule.
+    watched: HashMap<String, AbortHandle>,
+    /// Well-known name to unique owner, so a signal is only taken from the
+    /// current owner of the name it was watched for.
+    owners: HashMap<String, String>,
+}
+
+impl Subscriptions {
+    /// Watches `services` and the niri bridge, and stops watching services
+    /// no longer named.
+    async fn sync(&mut self, connection: &Connection, mut services: BTreeSet<String>) {
+        services.insert(niri_dbus::BUS_NAME.to_owned());
+        self.watched.retain(|service, handle| {
+            let keep = services.contains(service);
+            if !keep {
+                handle.abort();
+            }
+            keep
+        });
+        for service in services {
+            if self.watched.contains_key(&service) {
+                continue;
+            }
+            if let Err(error) = self.watch(connection, service.clone()).await {
+                warn!("failed to watch objects of {service}: {error}");
+            }
+        }
+    }
+
+    async fn watch(&mut self, connection: &Connection, service: String) -> zbus::Result<()> {
+        let Ok(name) = BusName::try_from(service.as_str()) else {
+            return Ok(());
+        };
+        let mut rule = MatchRule::builder()
+            .msg_type(MessageType::Signal)
+            .sender(name.clone())?
+            .interface(OBJECT_MANAGER_INTERFACE)?
+            .member("InterfacesRemoved")?;
+        if service == niri_dbus::BUS_NAME {
+            rule = rule.path_namespace(niri_dbus::ROOT_PATH)?;
+        }
+        let messages = MessageStream::for_match_rule(rule.build(), connection, None).await?;
+        if let BusName::WellKnown(name) = &name {
+            // Unowned names have nothing to remove; ownership changes keep
+            // the cache current from here on.
+            if let Ok(owner) = fdo::DBusProxy::new(connection)
+                .await?
+                .get_name_owner(BusName::WellKnown(name.clone()))
+                .await
+            {
+                self.owners.insert(service.clone(), owner.to_string());
+            }
+        }
+        let tag = service.clone();
+        let (messages, handle) = abortable(messages.filter_map(move |message| {
+            let tagged = message.ok().map(|message| (tag.clone(), message));
+            async move { tagged }
+        }));
+        self.removed_objects.push(messages.boxed());
+        self.watched.insert(service, handle);
+        Ok(())
+    }
+}
+
+/// Subscribes to the bus signals the pruner needs for the D-Bus services in
+/// `services`. Call before owning the locus name so no write can outrun the
+/// watcher.
+pub async fn subscribe(
+    connection: &Connection,
+    services: BTreeSet<String>,
+) -> zbus::Result<Subscriptions> {
+    let owner_changes = fdo::DBusProxy::new(connection)
+        .await?
+        .receive_name_owner_changed()
+        .await?;
+    let mut subscriptions = Subscriptions {
+        owner_changes,
+        removed_objects: SelectAll::new(),
+        watched: HashMap::new(),
+        owners: HashMap::new(),
+    };
+    subscriptions.sync(connection, services).await;
+    Ok(subscriptions)
+}
+
+/// Prunes vanished endpoints until the connection closes. `services_changed`
+/// fires when writes may have changed which services records name.
+pub async fn prune_vanished(
+    connection: Connection,
+    mut subscriptions: Subscriptions,
+    services_changed: Arc<Notify>,
+) {
+    loop {
+        tokio::select! {
+            change = subscriptions.owner_changes.next() => {
+                let Some(change) = change else {
+                    return;
+                };
+                let Ok(args) = change.args() else {
+                    continue;
+                };
+                let name = args.name();
+                match (name, args.new_owner().as_ref()) {
+                    (BusName::WellKnown(name), Some(owner)) => {
+                        subscriptions.owners.insert(name.to_string(), owner.to_string());
+                    }
+                    (_, Some(_)) => {}
+                    (name, None) => {
+                        subscriptions.owners.remove(name.as_str());
+                        if let Err(error) = prune_service(&connection, name).await {
+                            warn!("failed to prune relations of departed {name}: {error}");
+                        }
+                    }
+                }
+            }
+            Some((service, message)) = subscriptions.removed_objects.next() => {
+                if let Err(error) =
+                    prune_object(&connection, &subscriptions.owners, &service, &message).await
+                {
+                    warn!("failed to prune relations of a removed object: {error}");
+                }
+            }
+            () = services_changed.notified() => {
+                match relations_service(&connection).await {
+                    Ok(service) => {
+                        let services = service.get().await.dbus_services().await;
+                        subscriptions.sync(&connection, services).await;
+                    }
+                    Err(error) => warn!("failed to look up watched services: {error}"),
+                }
+            }
+        }
+    }
+}
+
+async fn relations_service(
+    connection: &Connection,
+) -> zbus::Result<zbus::object_server::InterfaceRef<RelationsService>> {
+    connection
+        .object_server()
+        .interface::<_, RelationsService>(OBJECT_PATH)
+        .await
+}
+
+async fn prune_service(connection: &Connection, name: &BusName<'_>) -> fdo::Result<()> {
+    let iface = relations_service(connection).await?;
+    let ctxt = iface.signal_context().clone();
+    let service = iface.get().await;
+    if let BusName::Unique(name) = name {
+        service.remove_owner(name.as_str(), &ctxt).await?;
+        service.close_watches(name.as_str(), connection).await?;
+        service.forget_caller(name.as_str()).await;
+    }
+    let vanished = Vanished::Service {
+        name: name.to_string(),
+    };
+    service.remove_vanished(&vanished, &ctxt).await
+}
+
+/// Prunes what an `InterfacesRemoved` from the watched `service` removed.
+/// The bus matches well-known senders but zbus cannot, so a signal from
+/// anyone but the current owner of `service` is dropped.
+async fn prune_object(
+    connection: &Connection,
+    owners: &HashMap<String, String>,
+    service: &str,
+    message: &Message,
+) -> fdo::Result<()> {
+    let header = message.header();
+    let Some(sender) = header.sender() else {
+        return Ok(());
+    };
+    if !sent_by(owners, service, sender.as_str()) {
+        return Ok(());
+    }
+    let (path, interfaces): (OwnedObjectPath, Vec<String>) = message.body().deserialize()?;
+    let iface = relations_service(connection).await?;
+    let ctxt = iface.signal_context().clone();
+    let vanished = Vanished::Object {
+        services: vec![service.to_owned(), sender.to_string()],
+        path: path.to_string(),
+        interfaces,
+    };
+    iface.get().await.remove_vanished(&vanished, &ctxt).await
+}
+
+/// Whether `sender` is `service` or its current owner.
+fn sent_by(owners: &HashMap<String, String>, service: &str, sender: &str) -> bool {
+    sender == service || owners.get(service).is_some_and(|owner| owner == sender)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::RelationStore;
+
+    fn tray(service: &str) -> RelationEndpoint {
+        RelationEndpoint::DBusObject {
+            bus: "session".to_string(),
+            service: service.to_string(),
+            path: "/StatusNotifierItem".to_string(),
+            interface: "org.kde.StatusNotifierItem".to_string(),
+        }
+    }
+
+    fn window(id: &str) -> RelationEndpoint {
+        RelationEndpoint::StableKey {
+            kind: keys::NIRI_WINDOW_ID.to_string(),
+            id: id.to_string(),
+        }
+    }
+
+    #[test]
+    fn removed_object_matches_its_service_path_and_interface() {
+        let vanished = Vanished::Object {
+            services: vec![":1.42".to_string(), "org.kde.Tray".to_string()],
+            path: "/StatusNotifierItem".to_string(),
+            interfaces: vec!["org.kde.StatusNotifierItem".to_string()],
+        };
+
+        assert!(vanished.matches(&tray("org.kde.Tray")));
+        assert!(vanished.matches(&tray(":1.42")));
+        assert!(!vanished.matches(&tray("org.kde.Other")));
+        assert!(!vanished.matches(&RelationEndpoint::DBusObject {
+            bus: "system".to_string(),
+            service: "org.kde.Tray".to_string(),
+            path: "/StatusNotifierItem".to_string(),
+            interface: "org.kde.StatusNotifierItem".to_string(),
+        }));
+        assert!(!vanished.matches(&window("7")));
+    }
+
+    #[test]
+    fn removed_niri_window_matches_its_window_key() {
+        let vanished = Vanished::Object {
+            services: vec![":1.7".to_string(), niri_dbus::BUS_NAME.to_string()],
+            path: "/org/rsynapse/Niri/Windows/window_7".to_string(),
+            interfaces: vec![niri_dbus::WINDOW_INTERFACE.to_string()],
+        };
+
+        assert!(vanished.matches(&window("7")));
+        assert!(!vanished.matches(&window("70")));
+        assert!(!vanished.matches(&RelationEndpoint::StableKey {
+            kind: keys::NIRI_WORKSPACE_ID.to_string(),
+            id: "7".to_string(),
+        }));
+    }
+
+    #[test]
+    fn departed_service_matches_its_endpoints() {
+        let tray_service = Vanished::Service {
+            name: "org.kde.Tray".to_string(),
+        };
+        assert!(tray_service.matches(&tray("org.kde.Tray")));
+        assert!(!tray_service.matches(&window("7")));
+
+        let niri = Vanished::Service {
+            name: niri_dbus::BUS_NAME.to_string(),
+        };
+        assert!(!niri.matches(&window("7")));
+        assert!(!niri.matches(&tray("org.kde.Tray")));
+    }
+
+    #[test]
+    fn removals_count_only_from_the_watched_owner() {
+        let owners = HashMap::from([("org.kde.Tray".to_string(), ":1.42".to_string())]);
+
+        assert!(sent_by(&owners, "org.kde.Tray", ":1.42"));
+        assert!(!sent_by(&owners, "org.kde.Tray", ":1.43"));
+        assert!(sent_by(&owners, ":1.43", ":1.43"));
+        assert!(!sent_by(&owners, "org.kde.Other", ":1.42"));
+    }
+
+    #[test]
+    fn window_relations_survive_a_niri_bridge_restart() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let mut store =
+            RelationStore::open(temp.path().join("relations.json")).expect("open store");
+        store
+            .set(
+                window("7"),
+                "org.rsynapse.window.project".to_string(),
+                RelationEndpoint::StableKey {
+                    kind: keys::PROJECT_PATH.to_string(),
+                    id: "/src/rsynapse".to_string(),
+                },
+                HashMap::new(),
+            )
+            .expect("set window relation");
+
+        for name in [":1.7", niri_dbus::BUS_NAME] {
+            let departed = Vanished::Service {
+                name: name.to_string(),
+            };
+            let removed = store
+                .remove_referencing(|endpoint| departed.matches(endpoint))
+                .expect("prune departed bridge");
+            assert!(removed.is_empty());
+        }
+        assert_eq!(store.len(), 1);
+
+        let closed = Vanished::Object {
+            services: vec![":1.8".to_string(), niri_dbus::BUS_NAME.to_string()],
+            path: "/org/rsynapse/Niri/Windows/window_7".to_string(),
+            interfaces: vec![niri_dbus::WINDOW_INTERFACE.to_string()],
+        };
+        let removed = store
+            .remove_referencing(|endpoint| closed.matches(endpoint))
+            .expect("prune closed window");
+        assert_eq!(removed.len(), 1);
+        assert_eq!(store.len(), 0);
+    }
+}
@@ -1,5 +1,12 @@
+mod history;
+mod index;
+mod journal;
+mod liveness;
+mod objects;
+mod policy;
 mod service;
 mod store;
+mod watch;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -0,0 +1,146 @@
+//! One object per record under `/org/rsynapse/Locus/records`, so generic
+//! ObjectManager clients can follow the store without Locus-specific calls.
+
+use locus::{Metadata, RelationEndpoint, RelationRecord, record_path};
+use tracing::warn;
+use zbus::{ObjectServer, interface};
+
+use crate::store::RelationChange;
+
+pub struct RelationObject {
+    record: RelationRecord,
+}
+
+impl RelationObject {
+    fn new(record: RelationRecord) -> Self {
+        Self { record }
+    }
+}
+
+#[interface(name = "org.rsynapse.Locus.Relation1")]
+impl RelationObject {
+    #[zbus(property)]
+    fn subject(&self) -> RelationEndpoint {
+        self.record.subject.clone()
+    }
+
+    #[zbus(property)]
+    fn relation(&self) -> String {
+        self.record.relation.clone()
+    }
+
+    #[zbus(property)]
+    fn target(&self) -> RelationEndpoint {
+        self.record.target.clone()
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> Metadata {
+        self.record.metadata.clone()
+    }
+
+    #[zbus(property)]
+    fn created_at_unix_ms(&self) -> u64 {
+        self.record.created_at_unix_ms
+    }
+
+    #[zbus(property)]
+    fn updated_at_unix_ms(&self) -> u64 {
+        self.record.updated_at_unix_ms
+    }
+
+    #[zbus(property)]
+    fn owner(&self) -> String {
+        self.record.owner.clone()
+    }
+
+    #[zbus(property)]
+    fn expires_at_unix_ms(&self) -> u64 {
+        self.record.expires_at_unix_ms
+    }
+
+    #[zbus(property)]
+    fn revision(&self) -> u64 {
+        self.record.revision
+    }
+}
+
+/// Exports the records already in the store at startup.
+pub async fn export_all(
+    object_server: &ObjectServer,
+    records: Vec<RelationRecord>,
+) -> zbus::Result<()> {
+    for record in records {
+        export(object_server, record).await?;
+    }
+    Ok(())
+}
+
+/// Mirrors committed changes. Adding and removing objects emits
+/// `InterfacesAdded` and `InterfacesRemoved`; updates emit
+/// `PropertiesChanged` for the properties that differ. The store has
+/// already committed the changes, so a change that fails to mirror is logged
+/// and the rest still apply. Writes call this before the next write commits,
+/// so an object always ends at its record's latest revision.
+pub async fn apply_changes(object_server: &ObjectServer, changes: &[RelationChange]) {
+    for change in changes {
+        let (result, record) = match change {
+            RelationChange::Added(record) => (export(object_server, record.clone()).await, record),
+            RelationChange::Updated { record, .. } => {
+                (update(object_server, record.clone()).await, record)
+            }
+            RelationChange::Removed(record) => (
+                object_server
+                    .remove::<RelationObject, _>(path_of(record))
+                    .await
+                    .map(|_| ()),
+                record,
+            ),
+            RelationChange::Cleared { .. } => continue,
+        };
+        if let Err(error) = result {
+            warn!("failed to mirror {} as an object: {error}", path_of(record));
+        }
+    }
+}
+
+async fn export(object_server: &ObjectServer, record: RelationRecord) -> zbus::Result<()> {
+    object_server
+        .at(path_of(&record), RelationObject::new(record))
+        .await?;
+    Ok(())
+}
+
+async fn update(object_server: &ObjectServer, record: RelationRecord) -> zbus::Result<()> {
+    let path = path_of(&record);
+    let Ok(object) = object_server.interface::<_, RelationObject>(&path).await else {
+        return export(object_server, record).await;
+    };
+    let ctxt = object.signal_context();
+    let mut object = object.get_mut().await;
+    let previous = std::mem::replace(&mut object.record, record);
+    let record = &object.record;
+    if previous.metadata != record.metadata {
+        object.metadata_changed(ctxt).await?;
+    }
+    if previous.created_at_unix_ms != record.created_at_unix_ms {
+        object.created_at_unix_ms_changed(ctxt).await?;
+    }
+    if previous.updated_at_unix_ms != record.updated_at_unix_ms {
+        object.updated_at_unix_ms_changed(ctxt).await?;
+    }
+    if previous.owner != record.owner {
+        object.owner_changed(ctxt).await?;
+    }
+    if previous.expires_at_unix_ms != record.expires_at_unix_ms {
+        object.expires_at_unix_ms_changed(ctxt).await?;
+    }
+    if previous.revision != record.revision {
+        object.revision_changed(ctxt).await?;
+    }
+    Ok(())
+}
+
+fn path_of(record: &RelationRecord) -> zbus::zvariant::OwnedObjectPath {
+    record_path(&record.subject, &record.relation, &record.target)
+}
@@ -0,0 +1,247 @@
+//! Which callers may write which relations.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::history::Caller;
+
+/// Writers allowed for a relation, or for every relation under a namespace
+/// when `relation` ends in `.*`.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PolicyRule {
+    pub relation: String,
+    /// Absolute executable paths. Bare names are rejected, since any process
+    /// of the same user can run a binary of that name from anywhere.
+    #[serde(default)]
+    pub executables: Vec<String>,
+    #[serde(default)]
+    pub uids: Vec<u32>,
+}
+
+impl PolicyRule {
+    /// Whether the caller runs a listed executable or as a listed uid. A rule
+    /// that lists neither admits no one.
+    pub fn allows(&self, caller: &Caller) -> bool {
+        caller.uid.is_some_and(|uid| self.uids.contains(&uid))
+            || self
+                .executables
+                .iter()
+                .any(|executable| executable_matches(executable, &caller.exe))
+    }
+
+    /// How closely the rule names `relation`, or `None` when it does not
+    /// cover it. An exact name beats any namespace, and a longer namespace
+    /// beats a shorter one.
+    fn specificity(&self, relation: &str) -> Option<usize> {
+        match self.relation.strip_suffix('*') {
+            Some(namespace) => relation.starts_with(namespace).then_some(namespace.len()),
+            None => (self.relation == relation).then_some(usize::MAX),
+        }
+    }
+}
+
+/// Write rules loaded at startup. Relations no rule covers are open to every
+/// writer, and reads are never restricted.
+#[derive(Clone, Debug, Default)]
+pub struct WritePolicy {
+    rules: Vec<PolicyRule>,
+}
+
+impl WritePolicy {
+    /// Reads a JSON list of rules. A missing file means an open policy.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let rules = match fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str::<Vec<PolicyRule>>(&contents)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(error) => return Err(error),
+        };
+        Self::new(rules)
+    }
+
+    pub fn new(rules: Vec<PolicyRule>) -> io::Result<Self> {
+        for (index, rule) in rules.iter().enumerate() {
+            let namespace = rule.relation.strip_suffix(".*");
+            if rule.relation.trim().is_empty()
+                || namespace.is_some_and(|namespace| namespace.is_empty())
+                || namespace.unwrap_or(&rule.relation).contains('*')
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "policy relation {:?} must be a relation name or a namespace ending in .*",
+                        rule.relation
+                    ),
+                ));
+            }
+            if let Some(executable) = rule
+                .executables
+                .iter()
+                .find(|executable| !Path::new(executable).is_absolute())
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "policy executable {executable:?} for {:?} must be an absolute path",
+                        rule.relation
+                    ),
+                ));
+            }
+            if rules[..index]
+                .iter()
+                .any(|earlier| earlier.relation == rule.relation)
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("policy relation {:?} is listed twice", rule.relation),
+                ));
+            }
+        }
+        Ok(Self { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The most specific rule covering `relation`, if any.
+    pub fn rule_for(&self, relation: &str) -> Option<&PolicyRule> {
+        self.rules
+            .iter()
+            .filter_map(|rule| Some((rule.specificity(relation)?, rule)))
+            .max_by_key(|(specificity, _)| *specificity)
+            .map(|(_, rule)| rule)
+    }
+}
+
+pub fn default_policy_path() -> PathBuf {
+    if let Some(path) = std::env::var_os("LOCUS_POLICY_PATH") {
+        return PathBuf::from(path);
+    }
+
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .unwrap_or_else(|| PathBuf::from("."));
+
+    config_home.join("rsynapse/locus/policy.json")
+}
+
+fn executable_matches(allowed: &str, exe: &str) -> bool {
+    // The kernel marks executables replaced on disk since the process started,
+    // e.g. by a package upgrade.
+    let exe = exe.strip_suffix(" (deleted)").unwrap_or(exe);
+    !exe.is_empty() && allowed == exe
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(relation: &str, executables: &[&str], uids: &[u32]) -> PolicyRule {
+        PolicyRule {
+            relation: relation.to_owned(),
+            executables: executables.iter().map(|exe| (*exe).to_owned()).collect(),
+            uids: uids.to_vec(),
+        }
+    }
+
+    fn caller(exe: &str, uid: Option<u32>) -> Caller {
+        Caller {
+            name: ":1.7".to_owned(),
+            pid: 42,
+            exe: exe.to_owned(),
+            uid,
+        }
+    }
+
+    #[test]
+    fn most_specific_rule_applies() {
+        let policy = WritePolicy::new(vec![
+            rule("org.rsynapse.workspace.*", &["/usr/bin/proj"], &[]),
+            rule(
+                "org.rsynapse.workspace.icon-override",
+                &["/usr/bin/rsynapse-shell"],
+                &[],
+            ),
+            rule("org.rsynapse.*", &[], &[1000]),
+        ])
+        .expect("valid policy");
+
+        let relation_of = |relation| policy.rule_for(relation).map(|rule| rule.relation.as_str());
+        assert_eq!(
+            relation_of("org.rsynapse.workspace.icon-override"),
+            Some("org.rsynapse.workspace.icon-override")
+        );
+        assert_eq!(
+            relation_of("org.rsynapse.workspace.project"),
+            Some("org.rsynapse.workspace.*")
+        );
+        assert_eq!(
+            relation_of("org.rsynapse.window.app-instance"),
+            Some("org.rsynapse.*")
+        );
+        assert_eq!(relation_of("org.example.other"), None);
+        assert_eq!(
+            relation_of("org.rsynapse.workspace"),
+            Some("org.rsynapse.*")
+        );
+    }
+
+    #[test]
+    fn rules_match_executable_paths_and_uids() {
+        let shell_only = rule(
+            "org.rsynapse.workspace.icon-override",
+            &["/usr/bin/rsynapse-shell", "/usr/local/bin/proj"],
+            &[0],
+        );
+
+        assert!(shell_only.allows(&caller("/usr/bin/rsynapse-shell", Some(1000))));
+        assert!(shell_only.allows(&caller("/usr/bin/rsynapse-shell (deleted)", None)));
+        assert!(shell_only.allows(&caller("/usr/local/bin/proj", None)));
+        assert!(!shell_only.allows(&caller("/home/me/bin/rsynapse-shell", Some(1000))));
+        assert!(!shell_only.allows(&caller("/home/me/bin/proj", Some(1000))));
+        assert!(!shell_only.allows(&caller("/usr/bin/rsynapse-shell-helper", None)));
+        assert!(shell_only.allows(&caller("/usr/bin/busctl", Some(0))));
+        assert!(!shell_only.allows(&caller("", None)));
+        assert!(!rule("org.rsynapse.locked", &[], &[]).allows(&caller("/usr/bin/proj", Some(0))));
+    }
+
+    #[test]
+    fn load_reads_rules_and_rejects_bad_patterns() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("policy.json");
+        assert!(
+            WritePolicy::load(&path)
+                .expect("missing policy is open")
+                .is_empty()
+        );
+
+        fs::write(
+            &path,
+            r#"[{"relation": "org.rsynapse.workspace.*", "executables": ["/usr/bin/proj"]}]"#,
+        )
+        .expect("write policy");
+        let policy = WritePolicy::load(&path).expect("load policy");
+        assert!(policy.rule_for("org.rsynapse.workspace.project").is_some());
+
+        for relation in ["", ".*", "org.*.project", "org.rsynapse*"] {
+            let error =
+                WritePolicy::new(vec![rule(relation, &[], &[])]).expect_err("bad pattern rejected");
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+        let error = WritePolicy::new(vec![rule("a.b", &[], &[]), rule("a.b", &[], &[1])])
+            .expect_err("duplicate rejected");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        for executable in ["proj", "bin/proj"] {
+            let error = WritePolicy::new(vec![rule("a.b", &[executable], &[])])
+                .expect_err("relative executable rejected");
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
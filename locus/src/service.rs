@@ -1,22 +1,75 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::BTreeSet, fs, io, sync::Arc, time::Duration};
 
-use tokio::sync::Mutex;
-use tracing::info;
-use zbus::{connection::Builder, fdo, interface, object_server::SignalContext};
+use tokio::sync::{Mutex, MutexGuard, Notify};
+use tracing::{info, warn};
+use zbus::{
+    Connection, DBusError, Message,
+    connection::Builder,
+    fdo, interface,
+    message::Header,
+    names::{BusName, ErrorName, UniqueName},
+    object_server::SignalContext,
+    zvariant::OwnedObjectPath,
+};
 
-use locus::{BUS_NAME, OBJECT_PATH, RelationEndpoint, RelationRecord};
+use locus::{
+    BUS_NAME, HistoryEntry, HistoryFilter, ImportMode, ImportSummary, Metadata, OBJECT_PATH,
+    REVISION_MISMATCH_ERROR, RelationEndpoint, RelationOperation, RelationRecord, RelationSchema,
+    SetOptions, StoreHealth, StoreProblem, Traversal, TraversalStep,
+};
 
-use crate::store::{RelationStore, SetOutcome, default_store_path};
+use crate::{
+    history::{Caller, History},
+    liveness::{self, Vanished},
+    objects,
+    policy::{WritePolicy, default_policy_path},
+    store::{
+        RelationChange, RelationStore, RevisionMismatch, accept_unknown_kinds_from_env,
+        default_store_path, unix_ms,
+    },
+    watch::{RelationWatch, WatchFilter, Watches},
+};
+
+const EXPIRY_RETRY_DELAY: Duration = Duration::from_secs(5);
 
 pub async fn run() -> anyhow::Result<()> {
-    let store = RelationStore::open(default_store_path())?;
-    let service = RelationsService::new(store);
+    let mut store = RelationStore::open(default_store_path())?;
+    if accept_unknown_kinds_from_env() {
+        info!("accepting endpoint kinds outside the key registry");
+        store.accept_unknown_kinds(true);
+    }
+    let policy_path = default_policy_path();
+    let policy = WritePolicy::load(&policy_path)?;
+    if !policy.is_empty() {
+        info!("restricting writes by {}", policy_path.display());
+    }
+    let records = store.list("");
+    let problems = store.problems().to_vec();
+    let services = store.dbus_services();
+    let service = RelationsService::new(store, policy);
+    let expiry_changed = service.expiry_changed.clone();
+    let services_changed = service.services_changed.clone();
 
-    let _connection = Builder::session()?
+    let connection = Builder::session()?
         .serve_at(OBJECT_PATH, service)?
-        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, fdo::ObjectManager)?
         .build()
         .await?;
+    objects::export_all(&connection.object_server(), records).await?;
+    // Subscribe before owning the name so no owned write can outrun the
+    // disconnect watcher.
+    let subscriptions = liveness::subscribe(&connection, services).await?;
+    connection.request_name(BUS_NAME).await?;
+    let ctxt = SignalContext::new(&connection, OBJECT_PATH)?;
+    for problem in problems {
+        RelationsService::store_error(&ctxt, problem).await?;
+    }
+    tokio::spawn(liveness::prune_vanished(
+        connection.clone(),
+        subscriptions,
+        services_changed,
+    ));
+    tokio::spawn(remove_expired_records(connection.clone(), expiry_changed));
 
     info!("owning {BUS_NAME} at {OBJECT_PATH}");
     tokio::signal::ctrl_c().await?;
@@ -25,12 +78,30 @@ pub async fn run() -> anyhow::Result<()> {
 
 pub struct RelationsService {
     store: Arc<Mutex<RelationStore>>,
+    /// Wakes the expiry task when a write may have moved the next expiry.
+    expiry_changed: Arc<Notify>,
+    /// Wakes the pruner when a write may have changed the D-Bus services
+    /// records name.
+    services_changed: Arc<Notify>,
+    /// Held by a write from before its store guard is released until its
+    /// changes are announced, so history, signals, and the mirrored record
+    /// objects see writes in the order they committed.
+    writing: Mutex<()>,
+    watches: Mutex<Watches>,
+    history: Mutex<History>,
+    policy: WritePolicy,
 }
 
 impl RelationsService {
-    pub fn new(store: RelationStore) -> Self {
+    pub fn new(store: RelationStore, policy: WritePolicy) -> Self {
         Self {
             store: Arc::new(Mutex::new(store)),
+            expiry_changed: Arc::new(Notify::new()),
+            services_changed: Arc::new(Notify::new()),
+            writing: Mutex::new(()),
+            watches: Mutex::new(Watches::default()),
+            history: Mutex::new(History::default()),
+            policy,
         }
     }
 }
@@ -47,23 +118,37 @@ impl RelationsService {
         self.store.lock().await.relations()
     }
 
+    #[zbus(property)]
+    async fn health(&self) -> StoreHealth {
+        StoreHealth::from_problems(self.store.lock().await.problems().to_vec())
+    }
+
     async fn set(
         &self,
         subject: RelationEndpoint,
         relation: String,
         target: RelationEndpoint,
-        metadata: HashMap<String, String>,
+        metadata: Metadata,
+        #[zbus(header)] header: Header<'_>,
         #[zbus(signal_context)] ctxt: SignalContext<'_>,
     ) -> fdo::Result<RelationRecord> {
+        self.authorize(&header, &ctxt, [relation.as_str()]).await?;
+        let writing = self.writing.lock().await;
         let outcome = self
             .store
             .lock()
             .await
             .set(subject, relation, target, metadata)
             .map_err(fdo_error)?;
-        let record = outcome.record.clone();
-        self.emit_store_properties(&ctxt).await?;
-        Self::emit_set_outcome(&ctxt, outcome).await?;
+        let record = outcome.set.record.clone();
+        self.emit_changes(
+            writing,
+            &ctxt,
+            "Set",
+            header.sender(),
+            outcome.into_changes(),
+        )
+        .await?;
         Ok(record)
     }
 
@@ -72,9 +157,12 @@ impl RelationsService {
         subject: RelationEndpoint,
         relation: String,
         target: RelationEndpoint,
-        metadata: HashMap<String, String>,
+        metadata: Metadata,
+        #[zbus(header)] header: Header<'_>,
         #[zbus(signal_context)] ctxt: SignalContext<'_>,
     ) -> fdo::Result<RelationRecord> {
+        self.authorize(&header, &ctxt, [relation.as_str()]).await?;
+        let writing = self.writing.lock().await;
         let outcome = self
             .store
             .lock()
@@ -82,11 +170,150 @@ impl RelationsService {
             .set_one(subject, relation, target, metadata)
             .map_err(fdo_error)?;
         let record = outcome.set.record.clone();
-        self.emit_store_properties(&ctxt).await?;
-        for removed in outcome.removed {
-            Self::relation_removed(&ctxt, removed).await?;
-        }
-        Self::emit_set_outcome(&ctxt, outcome.set).await?;
+        self.emit_changes(
+            writing,
+            &ctxt,
+            "SetOne",
+            header.sender(),
+            outcome.into_changes(),
+        )
+        .await?;
+        Ok(record)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn set_with_options(
+        &self,
+        subject: RelationEndpoint,
+        relation: String,
+        target: RelationEndpoint,
+        metadata: Metadata,
+        options: SetOptions,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+    ) -> fdo::Result<RelationRecord> {
+        let operation =
+            RelationOperation::set(subject, relation, target, metadata).with_options(options);
+        self.commit_single("SetWithOptions", operation, &header, &ctxt)
+            .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn set_one_with_options(
+        &self,
+        subject: RelationEndpoint,
+        relation: String,
+        target: RelationEndpoint,
+        metadata: Metadata,
+        options: SetOptions,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+    ) -> fdo::Result<RelationRecord> {
+        let operation =
+            RelationOperation::set_one(subject, relation, target, metadata).with_options(options);
+        self.commit_single("SetOneWithOptions", operation, &header, &ctxt)
+            .await
+    }
+
+    /// `Set` that only writes while the record's revision is
+    /// `expected_revision`, or while the record is absent when it is `0`.
+    #[allow(clippy::too_many_arguments)]
+    async fn set_if_revision(
+        &self,
+        subject: RelationEndpoint,
+        relation: String,
+        target: RelationEndpoint,
+        metadata: Metadata,
+        expected_revision: u64,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+    ) -> Result<RelationRecord, ConditionalError> {
+        self.authorize(&header, &ctxt, [relation.as_str()]).await?;
+        let writing = self.writing.lock().await;
+        let outcome = {
+            let mut store = self.store.lock().await;
+            store.expect_revision(&subject, &relation, Some(&target), expected_revision)?;
+            store
+                .set(subject, relation, target, metadata)
+                .map_err(fdo_error)?
+        };
+        let record = outcome.set.record.clone();
+        self.emit_changes(
+            writing,
+            &ctxt,
+            "SetIfRevision",
+            header.sender(),
+            outcome.into_changes(),
+        )
+        .await?;
+        Ok(record)
+    }
+
+    /// `SetOne` that only writes while the newest of the subject's records
+    /// under the relation has `expected_revision`, or while the subject has
+    /// none when it is `0`.
+    #[allow(clippy::too_many_arguments)]
+    async fn set_one_if_revision(
+        &self,
+        subject: RelationEndpoint,
+        relation: String,
+        target: RelationEndpoint,
+        metadata: Metadata,
+        expected_revision: u64,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+    ) -> Result<RelationRecord, ConditionalError> {
+        self.authorize(&header, &ctxt, [relation.as_str()]).await?;
+        let writing = self.writing.lock().await;
+        let outcome = {
+            let mut store = self.store.lock().await;
+            store.expect_revision(&subject, &relation, None, expected_revision)?;
+            store
+                .set_one(subject, relation, target, metadata)
+                .map_err(fdo_error)?
+        };
+        let record = outcome.set.record.clone();
+        self.emit_changes(
+            writing,
+            &ctxt,
+            "SetOneIfRevision",
+            header.sender(),
+            outcome.into_changes(),
+        )
+        .await?;
+        Ok(record)
+    }
+
+    /// Sets the entries of `set` and removes the keys in `remove`, leaving
+    /// the record's other metadata as it is.
+    #[allow(clippy::too_many_arguments)]
+    async fn patch_metadata(
+        &self,
+        subject: RelationEndpoint,
+        relation: String,
+        target: RelationEndpoint,
+        set: Metadata,
+        remove: Vec<String>,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+    ) -> fdo::Result<RelationRecord> {
+        self.authorize(&header, &ctxt, [relation.as_str()]).await?;
+        let writing = self.writing.lock().await;
+        let outcome = self
+            .store
+            .lock()
+            .await
+            .patch_metadata(&subject, &relation, &target, set, remove)
+            .map_err(fdo_error)?;
+        let record = outcome.record.clone();
+        self.emit_changes(
+            writing,
+            &ctxt,
+            "PatchMetadata",
+            header.sender(),
+            vec![outcome.into()],
+        )
+        .await?;
         Ok(record)
     }
 
@@ -95,8 +322,11 @@ impl RelationsService {
         subject: RelationEndpoint,
         relation: String,
         target: RelationEndpoint,
+        #[zbus(header)] header: Header<'_>,
         #[zbus(signal_context)] ctxt: SignalContext<'_>,
     ) -> fdo::Result<bool> {
+        self.authorize(&header, &ctxt, [relation.as_str()]).await?;
+        let writing = self.writing.lock().await;
         let removed = self
             .store
             .lock()
@@ -104,20 +334,53 @@ impl RelationsService {
             .unset(&subject, &relation, &target)
             .map_err(fdo_error)?;
         if let Some(record) = removed {
-            self.emit_store_properties(&ctxt).await?;
-            Self::relation_removed(&ctxt, record).await?;
+            let changes = vec![RelationChange::Removed(record)];
+            self.emit_changes(writing, &ctxt, "Unset", header.sender(), changes)
+                .await?;
             Ok(true)
         } else {
             Ok(false)
         }
     }
 
+    /// `Unset` that only removes the record while its revision is
+    /// `expected_revision`.
+    async fn unset_if_revision(
+        &self,
+        subject: RelationEndpoint,
+        relation: String,
+        target: RelationEndpoint,
+        expected_revision: u64,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+    ) -> Result<bool, ConditionalError> {
+        self.authorize(&header, &ctxt, [relation.as_str()]).await?;
+        let writing = self.writing.lock().await;
+        let removed = {
+            let mut store = self.store.lock().await;
+            store.expect_revision(&subject, &relation, Some(&target), expected_revision)?;
+            store
+                .unset(&subject, &relation, &target)
+                .map_err(fdo_error)?
+        };
+        let Some(record) = removed else {
+            return Ok(false);
+        };
+        let changes = vec![RelationChange::Removed(record)];
+        self.emit_changes(writing, &ctxt, "UnsetIfRevision", header.sender(), changes)
+            .await?;
+        Ok(true)
+    }
+
     async fn clear(
         &self,
         subject: RelationEndpoint,
         relation: String,
+        #[zbus(header)] header: Header<'_>,
         #[zbus(signal_context)] ctxt: SignalContext<'_>,
     ) -> fdo::Result<u32> {
+        self.authorize(&header, &ctxt, [relation.as_str()]).await?;
+        let writing = self.writing.lock().await;
         let removed = self
             .store
             .lock()
@@ -126,15 +389,30 @@ impl RelationsService {
             .map_err(fdo_error)?;
         let count = removed.len().try_into().unwrap_or(u32::MAX);
         if count > 0 {
-            self.emit_store_properties(&ctxt).await?;
-            for record in removed {
-                Self::relation_removed(&ctxt, record).await?;
-            }
-            Self::relation_cleared(&ctxt, subject, relation, count).await?;
+            let mut changes = removed
+                .into_iter()
+                .map(RelationChange::Removed)
+                .collect::<Vec<_>>();
+            changes.push(RelationChange::Cleared {
+                subject,
+                relation,
+                removed_count: count,
+            });
+            self.emit_changes(writing, &ctxt, "Clear", header.sender(), changes)
+                .await?;
         }
         Ok(count)
     }
 
+    async fn apply(
+        &self,
+        operations: Vec<RelationOperation>,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+    ) -> fdo::Result<Vec<RelationRecord>> {
+        self.commit("Apply", operations, &header, &ctxt).await
+    }
+
     async fn targets(&self, subject: RelationEndpoint, relation: String) -> Vec<RelationEndpoint> {
         self.store.lock().await.targets(&subject, &relation)
     }
@@ -143,10 +421,195 @@ impl RelationsService {
         self.store.lock().await.subjects(&relation, &target)
     }
 
+    /// `Targets` for several subjects under one lock, as (subject, targets)
+    /// pairs.
+    async fn targets_many(
+        &self,
+        subjects: Vec<RelationEndpoint>,
+        relation: String,
+    ) -> Vec<(RelationEndpoint, Vec<RelationEndpoint>)> {
+        self.store.lock().await.targets_many(&subjects, &relation)
+    }
+
+    /// `Subjects` for several targets under one lock, as (target, subjects)
+    /// pairs.
+    async fn subjects_many(
+        &self,
+        relation: String,
+        targets: Vec<RelationEndpoint>,
+    ) -> Vec<(RelationEndpoint, Vec<RelationEndpoint>)> {
+        self.store.lock().await.subjects_many(&relation, &targets)
+    }
+
     async fn list(&self, relation: String) -> Vec<RelationRecord> {
         self.store.lock().await.list(&relation)
     }
 
+    /// Records of `relation`, or of every relation when it is empty, that
+    /// carry every entry of `metadata`.
+    async fn list_matching(&self, relation: String, metadata: Metadata) -> Vec<RelationRecord> {
+        self.store.lock().await.list_matching(&relation, &metadata)
+    }
+
+    async fn traverse(
+        &self,
+        start: RelationEndpoint,
+        path: Vec<TraversalStep>,
+    ) -> fdo::Result<Traversal> {
+        self.store
+            .lock()
+            .await
+            .traverse(&start, &path)
+            .map_err(fdo_error)
+    }
+
+    async fn export(&self) -> Vec<RelationRecord> {
+        self.store.lock().await.export()
+    }
+
+    async fn import(
+        &self,
+        records: Vec<RelationRecord>,
+        mode: ImportMode,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+    ) -> fdo::Result<ImportSummary> {
+        let mut relations = records
+            .iter()
+            .map(|record| record.relation.clone())
+            .collect::<BTreeSet<_>>();
+        // One guard covers the check and the import, so no record written in
+        // between can be replaced without its relation being authorized.
+        let writing = self.writing.lock().await;
+        let mut store = self.store.lock().await;
+        if mode == ImportMode::Replace {
+            // Replacing removes durable records the import leaves out.
+            relations.extend(store.export().into_iter().map(|record| record.relation));
+        }
+        self.authorize(&header, &ctxt, relations.iter().map(String::as_str))
+            .await?;
+        let expiring = records.iter().any(|record| record.expires_at_unix_ms != 0);
+        let changes = store.import(records, mode).map_err(fdo_error)?;
+        drop(store);
+        if expiring {
+            self.expiry_changed.notify_one();
+        }
+        let mut summary = ImportSummary::default();
+        for change in &changes {
+            let count = match change {
+                RelationChange::Added(_) => &mut summary.added,
+                RelationChange::Updated { .. } => &mut summary.updated,
+                RelationChange::Removed(_) => &mut summary.removed,
+                RelationChange::Cleared { .. } => continue,
+            };
+            *count = count.saturating_add(1);
+        }
+        self.emit_changes(writing, &ctxt, "Import", header.sender(), changes)
+            .await?;
+        Ok(summary)
+    }
+
+    /// Committed record changes, newest first. A `limit` of `0` returns
+    /// every retained entry.
+    async fn history(&self, filter: HistoryFilter, limit: u32) -> Vec<HistoryEntry> {
+        self.history.lock().await.query(&filter, limit)
+    }
+
+    /// Exports an object that reports only the records matching the filter
+    /// and lives until the caller disconnects or unwatches it.
+    async fn watch(
+        &self,
+        subject: Vec<RelationEndpoint>,
+        relation: String,
+        target: Vec<RelationEndpoint>,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+    ) -> fdo::Result<OwnedObjectPath> {
+        let Some(caller) = header.sender().map(|sender| sender.to_owned()) else {
+            return Err(fdo::Error::InvalidArgs(
+                "watches require a caller bus name".to_owned(),
+            ));
+        };
+        let filter = WatchFilter::new(subject, relation, target).map_err(fdo_error)?;
+        let path = self
+            .watches
+            .lock()
+            .await
+            .insert(caller.to_string(), filter.clone());
+        let connection = ctxt.connection();
+        connection
+            .object_server()
+            .at(&path, RelationWatch::new(self.store.clone(), filter))
+            .await?;
+
+        // As with owned writes, the caller may already be gone.
+        let has_owner = fdo::DBusProxy::new(connection)
+            .await?
+            .name_has_owner(BusName::from(caller.clone()))
+            .await?;
+        if !has_owner {
+            self.close_watches(&caller, connection).await?;
+        }
+        Ok(path)
+    }
+
+    /// Removes a watch the caller opened.
+    async fn unwatch(
+        &self,
+        path: OwnedObjectPath,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+    ) -> fdo::Result<()> {
+        let removed = match header.sender() {
+            Some(caller) => self.watches.lock().await.remove(&path, caller.as_str()),
+            None => false,
+        };
+        if !removed {
+            return Err(fdo::Error::UnknownObject(format!(
+                "{path} is not a watch of the caller"
+            )));
+        }
+        ctxt.connection()
+            .object_server()
+            .remove::<RelationWatch, _>(&path)
+            .await?;
+        Ok(())
+    }
+
+    async fn schemas(&self) -> Vec<RelationSchema> {
+        self.store.lock().await.schemas()
+    }
+
+    async fn register_schema(
+        &self,
+        schema: RelationSchema,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+    ) -> fdo::Result<()> {
+        self.authorize(&header, &ctxt, [schema.relation.as_str()])
+            .await?;
+        self.store
+            .lock()
+            .await
+            .register_schema(schema)
+            .map_err(fdo_error)
+    }
+
+    async fn unregister_schema(
+        &self,
+        relation: String,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+    ) -> fdo::Result<bool> {
+        self.authorize(&header, &ctxt, [relation.as_str()]).await?;
+        self.store
+            .lock()
+            .await
+            .unregister_schema(&relation)
+            .map(|removed| removed.is_some())
+            .map_err(fdo_error)
+    }
+
     #[zbus(signal)]
     async fn relation_added(ctxt: &SignalContext<'_>, record: RelationRecord) -> zbus::Result<()>;
 
@@ -165,26 +628,550 @@ impl RelationsService {
         relation: String,
         removed_count: u32,
     ) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn store_error(ctxt: &SignalContext<'_>, problem: StoreProblem) -> zbus::Result<()>;
 }
 
 impl RelationsService {
+    async fn commit(
+        &self,
+        name: &str,
+        operations: Vec<RelationOperation>,
+        header: &Header<'_>,
+        ctxt: &SignalContext<'_>,
+    ) -> fdo::Result<Vec<RelationRecord>> {
+        self.authorize(
+            header,
+            ctxt,
+            operations.iter().map(RelationOperation::relation),
+        )
+        .await?;
+        let caller = header.sender().map(|sender| sender.to_owned());
+        let writing = self.writing.lock().await;
+        let outcome = self
+            .store
+            .lock()
+            .await
+            .apply(operations, caller.as_ref().map(|caller| caller.as_str()))
+            .map_err(fdo_error)?;
+        let owned = outcome
+            .records
+            .iter()
+            .any(|record| !record.owner.is_empty());
+        if outcome
+            .records
+            .iter()
+            .any(|record| record.expires_at_unix_ms != 0)
+        {
+            self.expiry_changed.notify_one();
+        }
+        self.emit_changes(writing, ctxt, name, caller.as_ref(), outcome.changes)
+            .await?;
+
+        // The caller may have disconnected before this write committed, in
+        // which case the disconnect watcher has already run for it.
+        if let Some(caller) = caller.filter(|_| owned) {
+            let has_owner = fdo::DBusProxy::new(ctxt.connection())
+                .await?
+                .name_has_owner(BusName::from(caller.clone()))
+                .await?;
+            if !has_owner {
+                self.remove_owner(&caller, ctxt).await?;
+            }
+        }
+        Ok(outcome.records)
+    }
+
+    async fn commit_single(
+        &self,
+        name: &str,
+        operation: RelationOperation,
+        header: &Header<'_>,
+        ctxt: &SignalContext<'_>,
+    ) -> fdo::Result<RelationRecord> {
+        self.commit(name, vec![operation], header, ctxt)
+            .await?
+            .pop()
+            .ok_or_else(|| fdo::Error::Failed("set operation wrote no record".to_owned()))
+    }
+
+    pub async fn remove_vanished(
+        &self,
+        vanished: &Vanished,
+        ctxt: &SignalContext<'_>,
+    ) -> fdo::Result<()> {
+        self.remove_with(ctxt, "Prune", |store| {
+            store.remove_referencing(|endpoint| vanished.matches(endpoint))
+        })
+        .await
+    }
+
+    pub async fn dbus_services(&self) -> BTreeSet<String> {
+        self.store.lock().await.dbus_services()
+    }
+
+    pub async fn remove_owner(&self, owner: &str, ctxt: &SignalContext<'_>) -> fdo::Result<()> {
+        self.remove_with(ctxt, "Disconnect", |store| store.remove_owned(owner))
+            .await
+    }
+
+    /// Drops the cached credentials of a departed connection.
+    pub async fn forget_caller(&self, name: &str) {
+        self.history.lock().await.forget_caller(name);
+    }
+
+    pub async fn close_watches(&self, owner: &str, connection: &Connection) -> zbus::Result<()> {
+        let paths = self.watches.lock().await.remove_owned(owner);
+        for path in paths {
+            connection
+                .object_server()
+                .remove::<RelationWatch, _>(&path)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn remove_with(
+        &self,
+        ctxt: &SignalContext<'_>,
+        operation: &str,
+        remove: impl FnOnce(&mut RelationStore) -> io::Result<Vec<RelationRecord>>,
+    ) -> fdo::Result<()> {
+        let writing = self.writing.lock().await;
+        let removed = remove(&mut *self.store.lock().await).map_err(fdo_error)?;
+        let changes = removed.into_iter().map(RelationChange::Removed).collect();
+        self.emit_changes(writing, ctxt, operation, None, changes)
+            .await?;
+        Ok(())
+    }
+
+    /// Records committed changes in the history and announces them.
+    /// `writing` is the guard the write took before committing them.
+    /// `caller` is `None` for removals Locus makes itself.
+    async fn emit_changes(
+        &self,
+        _writing: MutexGuard<'_, ()>,
+        ctxt: &SignalContext<'_>,
+        operation: &str,
+        caller: Option<&UniqueName<'_>>,
+        changes: Vec<RelationChange>,
+    ) -> zbus::Result<()> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+        let caller = match caller {
+            Some(name) => self.caller(ctxt.connection(), name).await,
+            None => Caller::default(),
+        };
+        self.history
+            .lock()
+            .await
+            .record(operation, &caller, &changes, unix_ms());
+        if changes.iter().any(names_dbus_object) {
+            self.services_changed.notify_one();
+        }
+        self.emit_store_properties(ctxt).await?;
+        for change in &changes {
+            Self::emit_change(ctxt, change.clone()).await?;
+        }
+        objects::apply_changes(&ctxt.connection().object_server(), &changes).await;
+        self.emit_watch_changes(ctxt, &changes).await
+    }
+
+    /// Fails with `AccessDenied` unless the write policy lets the caller
+    /// write every one of `relations`.
+    async fn authorize<'r>(
+        &self,
+        header: &Header<'_>,
+        ctxt: &SignalContext<'_>,
+        relations: impl IntoIterator<Item = &'r str>,
+    ) -> fdo::Result<()> {
+        let rules = relations
+            .into_iter()
+            .filter_map(|relation| Some((relation, self.policy.rule_for(relation)?)))
+            .collect::<Vec<_>>();
+        if rules.is_empty() {
+            return Ok(());
+        }
+        let caller = match header.sender() {
+            Some(name) => self.caller(ctxt.connection(), name).await,
+            None => Caller::default(),
+        };
+        match rules.into_iter().find(|(_, rule)| !rule.allows(&caller)) {
+            Some((relation, _)) => Err(fdo::Error::AccessDenied(format!(
+                "{} ({}) may not write {relation}",
+                caller.name,
+                if caller.exe.is_empty() {
+                    "unknown executable"
+                } else {
+                    &caller.exe
+                }
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// The writer's pid, executable, and uid, looked up once per connection.
+    /// Each stays unknown when the bus or `/proc` will not say.
+    async fn caller(&self, connection: &Connection, name: &UniqueName<'_>) -> Caller {
+        if let Some(caller) = self.history.lock().await.caller(name.as_str()) {
+            return caller.clone();
+        }
+        let credentials = match fdo::DBusProxy::new(connection).await {
+            Ok(dbus) => dbus
+                .get_connection_credentials(BusName::from(name.clone()))
+                .await
+                .ok(),
+            Err(_) => None,
+        };
+        let pid = credentials
+            .as_ref()
+            .and_then(|credentials| credentials.process_id());
+        let exe = pid
+            .and_then(|pid| fs::read_link(format!("/proc/{pid}/exe")).ok())
+            .map(|exe| exe.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let caller = Caller {
+            name: name.to_string(),
+            pid: pid.unwrap_or_default(),
+            exe,
+            uid: credentials.and_then(|credentials| credentials.unix_user_id()),
+        };
+        self.history.lock().await.remember_caller(caller.clone());
+        caller
+    }
+
+    async fn emit_watch_changes(
+        &self,
+        ctxt: &SignalContext<'_>,
+        changes: &[RelationChange],
+    ) -> zbus::Result<()> {
+        let matching = self.watches.lock().await.matching(changes);
+        for (path, changes) in matching {
+            // A watch closed since the lookup has no one left to tell.
+            let Ok(watch) = ctxt
+                .connection()
+                .object_server()
+                .interface::<_, RelationWatch>(&path)
+                .await
+            else {
+                continue;
+            };
+            watch
+                .get()
+                .await
+                .emit_changes(watch.signal_context(), changes)
+                .await?;
+        }
+        Ok(())
+    }
+
     async fn emit_store_properties(&self, ctxt: &SignalContext<'_>) -> zbus::Result<()> {
         self.record_count_changed(ctxt).await?;
         self.relations_changed(ctxt).await
     }
 
-    async fn emit_set_outcome(ctxt: &SignalContext<'_>, outcome: SetOutcome) -> zbus::Result<()> {
-        if outcome.created {
-            Self::relation_added(ctxt, outcome.record).await
-        } else {
-            Self::relation_updated(ctxt, outcome.record).await
+    async fn emit_change(ctxt: &SignalContext<'_>, change: RelationChange) -> zbus::Result<()> {
+        match change {
+            RelationChange::Added(record) => Self::relation_added(ctxt, record).await,
+            RelationChange::Updated { record, .. } => Self::relation_updated(ctxt, record).await,
+            RelationChange::Removed(record) => Self::relation_removed(ctxt, record).await,
+            RelationChange::Cleared {
+                subject,
+                relation,
+                removed_count,
+            } => Self::relation_cleared(ctxt, subject, relation, removed_count).await,
+        }
+    }
+}
+
+/// Sleeps until the earliest record expiry, removes what expired, and starts
+/// over. Writes that set an expiry wake it so a sooner deadline is not missed.
+async fn remove_expired_records(connection: Connection, expiry_changed: Arc<Notify>) {
+    let iface = match connection
+        .object_server()
+        .interface::<_, RelationsService>(OBJECT_PATH)
+        .await
+    {
+        Ok(iface) => iface,
+        Err(error) => {
+            warn!("relation expiry disabled: {error}");
+            return;
+        }
+    };
+    let ctxt = iface.signal_context().clone();
+    loop {
+        let next_expiry = iface.get().await.store.lock().await.next_expiry();
+        let Some(expires_at) = next_expiry else {
+            expiry_changed.notified().await;
+            continue;
+        };
+        let delay = Duration::from_millis(expires_at.saturating_sub(unix_ms()));
+        tokio::select! {
+            () = tokio::time::sleep(delay) => {}
+            () = expiry_changed.notified() => continue,
+        }
+
+        let service = iface.get().await;
+        let removed = service
+            .remove_with(&ctxt, "Expire", |store| store.remove_expired(unix_ms()))
+            .await;
+        drop(service);
+        if let Err(error) = removed {
+            warn!("failed to remove expired relations: {error}");
+            tokio::time::sleep(EXPIRY_RETRY_DELAY).await;
+        }
+    }
+}
+
+/// Errors of the `*IfRevision` methods: the usual `fdo` errors plus
+/// `org.rsynapse.Locus.Error.RevisionMismatch`.
+#[derive(Debug)]
+enum ConditionalError {
+    Fdo(fdo::Error),
+    RevisionMismatch(String),
+}
+
+impl From<fdo::Error> for ConditionalError {
+    fn from(error: fdo::Error) -> Self {
+        Self::Fdo(error)
+    }
+}
+
+impl From<zbus::Error> for ConditionalError {
+    fn from(error: zbus::Error) -> Self {
+        Self::Fdo(error.into())
+    }
+}
+
+impl From<RevisionMismatch> for ConditionalError {
+    fn from(mismatch: RevisionMismatch) -> Self {
+        Self::RevisionMismatch(mismatch.to_string())
+    }
+}
+
+impl DBusError for ConditionalError {
+    fn create_reply(&self, call: &Header<'_>) -> zbus::Result<Message> {
+        match self {
+            Self::Fdo(error) => error.create_reply(call),
+            // `Message::method_error` needs the whole call; the derive
+            // builds its replies from the header the same way.
+            #[allow(deprecated)]
+            Self::RevisionMismatch(description) => {
+                zbus::message::Builder::error(call, self.name())?.build(description)
+            }
+        }
+    }
+
+    fn name(&self) -> ErrorName<'_> {
+        match self {
+            Self::Fdo(error) => error.name(),
+            Self::RevisionMismatch(_) => {
+                ErrorName::from_static_str_unchecked(REVISION_MISMATCH_ERROR)
+            }
+        }
+    }
+
+    fn description(&self) -> Option<&str> {
+        match self {
+            Self::Fdo(error) => error.description(),
+            Self::RevisionMismatch(description) => Some(description),
         }
     }
 }
 
+fn names_dbus_object(change: &RelationChange) -> bool {
+    match change {
+        RelationChange::Added(record)
+        | RelationChange::Updated { record, .. }
+        | RelationChange::Removed(record) => [&record.subject, &record.target]
+            .into_iter()
+            .any(|endpoint| matches!(endpoint, RelationEndpoint::DBusObject { .. })),
+        RelationChange::Cleared { .. } => false,
+    }
+}
+
 fn fdo_error(error: std::io::Error) -> fdo::Error {
     match error.kind() {
         std::io::ErrorKind::InvalidInput => fdo::Error::InvalidArgs(error.to_string()),
         _ => fdo::Error::Failed(error.to_string()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use locus::{RecordProxy, RelationsProxy, keys, record_path};
+    use zbus::{CacheProperties, Guid, connection};
+
+    use super::*;
+
+    async fn serve(store: RelationStore) -> (Connection, RelationsProxy<'static>) {
+        let (server, client) = tokio::net::UnixStream::pair().expect("socket pair");
+        let service = RelationsService::new(store, WritePolicy::new(Vec::new()).expect("policy"));
+        let server = connection::Builder::unix_stream(server)
+            .server(Guid::generate())
+            .expect("server guid")
+            .p2p()
+            .serve_at(OBJECT_PATH, service)
+            .expect("serve relations")
+            .build();
+        let client = connection::Builder::unix_stream(client).p2p().build();
+        let (server, client) = tokio::try_join!(server, client).expect("p2p connection");
+        let proxy = RelationsProxy::builder(&client)
+            .destination(BUS_NAME)
+            .expect("destination")
+            .build()
+            .await
+            .expect("relations proxy");
+        (server, proxy)
+    }
+
+    #[tokio::test]
+    async fn list_ignores_metadata_and_list_matching_filters_on_it() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let store = RelationStore::open(temp.path().join("relations.json")).expect("open store");
+        let (_server, relations) = serve(store).await;
+        let relation = "org.rsynapse.workspace.project";
+        for (workspace, branch) in [("1", "main"), ("2", "topic")] {
+            relations
+                .set(
+                    RelationEndpoint::stable_key(keys::NIRI_WORKSPACE_ID, workspace),
+                    relation,
+                    RelationEndpoint::stable_key(keys::PROJECT_PATH, "/src/rsynapse"),
+                    Metadata::from([("branch".to_owned(), branch.into())]),
+                )
+                .await
+                .expect("set");
+        }
+
+        assert_eq!(relations.list(relation).await.expect("list").len(), 2);
+        let main = relations
+            .list_matching(
+                relation,
+                Metadata::from([("branch".to_owned(), "main".into())]),
+            )
+            .await
+            .expect("list matching");
+        assert_eq!(main.len(), 1);
+        assert_eq!(
+            main[0].subject,
+            RelationEndpoint::stable_key(keys::NIRI_WORKSPACE_ID, "1")
+        );
+    }
+
+    #[tokio::test]
+    async fn a_write_commits_only_after_the_previous_one_is_in_the_history() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let store = RelationStore::open(temp.path().join("relations.json")).expect("open store");
+        let (server, relations) = serve(store).await;
+        let service = server
+            .object_server()
+            .interface::<_, RelationsService>(OBJECT_PATH)
+            .await
+            .expect("relations interface");
+        let relation = "org.rsynapse.workspace.project";
+        let subject = RelationEndpoint::stable_key(keys::NIRI_WORKSPACE_ID, "1");
+        let write = |branch: &'static str| {
+            let relations = relations.clone();
+            let subject = subject.clone();
+            tokio::spawn(async move {
+                relations
+                    .set_one(
+                        subject,
+                        relation,
+                        RelationEndpoint::stable_key(keys::PROJECT_PATH, "/src/rsynapse"),
+                        Metadata::from([("branch".to_owned(), branch.into())]),
+                    )
+                    .await
+                    .expect("set one")
+            })
+        };
+
+        // Holding the history stalls the first write between its commit
+        // and its history entry.
+        let locus = service.get().await;
+        let history = locus.history.lock().await;
+        let first = write("main");
+        while locus.store.lock().await.len() == 0 {
+            tokio::task::yield_now().await;
+        }
+        let second = write("topic");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let committed = locus.store.lock().await.list(relation);
+        assert_eq!(committed.len(), 1);
+        assert_eq!(committed[0].metadata["branch"], "main".into());
+        drop(history);
+
+        let first = first.await.expect("first write");
+        let second = second.await.expect("second write");
+        let revisions = relations
+            .history(HistoryFilter::default(), 0)
+            .await
+            .expect("history")
+            .into_iter()
+            .map(|entry| entry.after[0].revision)
+            .collect::<Vec<_>>();
+        assert_eq!(revisions, [second.revision, first.revision]);
+    }
+
+    #[tokio::test]
+    async fn a_record_removed_and_readded_stays_mirrored() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let store = RelationStore::open(temp.path().join("relations.json")).expect("open store");
+        let (server, relations) = serve(store).await;
+        let service = server
+            .object_server()
+            .interface::<_, RelationsService>(OBJECT_PATH)
+            .await
+            .expect("relations interface");
+        let subject = RelationEndpoint::stable_key(keys::NIRI_WORKSPACE_ID, "1");
+        let relation = "org.rsynapse.workspace.project";
+        let target = RelationEndpoint::stable_key(keys::PROJECT_PATH, "/src/rsynapse");
+        relations
+            .set(subject.clone(), relation, target.clone(), Metadata::new())
+            .await
+            .expect("set");
+
+        // Holding the history stalls the removal between its commit and
+        // its mirroring; the re-add must not overtake it.
+        let locus = service.get().await;
+        let history = locus.history.lock().await;
+        let removal = tokio::spawn({
+            let relations = relations.clone();
+            let (subject, target) = (subject.clone(), target.clone());
+            async move { relations.unset(subject, relation, target).await }
+        });
+        while locus.store.lock().await.len() == 1 {
+            tokio::task::yield_now().await;
+        }
+        let readd = tokio::spawn({
+            let relations = relations.clone();
+            let (subject, target) = (subject.clone(), target.clone());
+            async move {
+                relations
+                    .set(subject, relation, target, Metadata::new())
+                    .await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(locus.store.lock().await.len(), 0);
+        drop(history);
+
+        assert!(removal.await.expect("removal").expect("unset"));
+        let readded = readd.await.expect("re-add").expect("set");
+        let object = RecordProxy::builder(relations.inner().connection())
+            .destination(BUS_NAME)
+            .expect("destination")
+            .path(record_path(&subject, relation, &target))
+            .expect("record path")
+            .cache_properties(CacheProperties::No)
+            .build()
+            .await
+            .expect("record proxy");
+        assert_eq!(
+            object.revision().await.expect("mirrored record"),
+            readded.revision
+        );
+    }
+}
@@ -1,22 +1,62 @@
 use std::{
-    collections::HashMap,
-    fs, io,
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs, io,
     path::{Path, PathBuf},
     time::{SystemTime, UNIX_EPOCH},
 };
 
-use locus::{RelationEndpoint, RelationRecord, keys};
+use locus::{
+    ImportMode, Metadata, RelationEndpoint, RelationOperation, RelationRecord, RelationSchema,
+    SetOptions, StoreProblem, Traversal, TraversalStep, keys,
+};
+
+use tracing::warn;
+
+use crate::{
+    index::RelationIndex,
+    journal::{Journal, JournalEntry, RecordKey},
+};
+
+/// Snapshots kept next to the store from earlier compactions, newest first
+/// as `relations.1.json`, `relations.2.json`, ...
+const BACKUPS: usize = 3;
 
 #[derive(Debug)]
 pub struct RelationStore {
     path: PathBuf,
-    records: Vec<RelationRecord>,
+    records: RelationIndex,
+    journal: Journal,
+    schemas: BTreeMap<String, RelationSchema>,
+    /// Revision stamped on the most recent write.
+    revision: u64,
+    /// Whether writes may use kinds missing from `keys::KINDS`.
+    accept_unknown_kinds: bool,
+    /// Files that could not be read on open.
+    problems: Vec<StoreProblem>,
+}
+
+/// A conditional write found another revision than the caller expected.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RevisionMismatch {
+    pub expected: u64,
+    pub actual: u64,
+}
+
+impl fmt::Display for RevisionMismatch {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            formatter,
+            "expected revision {}, found {}",
+            self.expected, self.actual
+        )
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct SetOutcome {
     pub record: RelationRecord,
-    pub created: bool,
+    /// The record this write updated, or `None` when it created one.
+    pub previous: Option<RelationRecord>,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -25,36 +65,150 @@ pub struct ReplaceOutcome {
     pub removed: Vec<RelationRecord>,
 }
 
+/// A committed change, in the order signals should be emitted for it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RelationChange {
+    Added(RelationRecord),
+    Updated {
+        previous: Box<RelationRecord>,
+        record: RelationRecord,
+    },
+    Removed(RelationRecord),
+    Cleared {
+        subject: RelationEndpoint,
+        relation: String,
+        removed_count: u32,
+    },
+}
+
+impl From<SetOutcome> for RelationChange {
+    fn from(outcome: SetOutcome) -> Self {
+        match outcome.previous {
+            Some(previous) => Self::Updated {
+                previous: Box::new(previous),
+                record: outcome.record,
+            },
+            None => Self::Added(outcome.record),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ApplyOutcome {
+    /// Records written by set operations, in operation order.
+    pub records: Vec<RelationRecord>,
+    pub changes: Vec<RelationChange>,
+}
+
+impl ReplaceOutcome {
+    /// Replaced records first, then the written record.
+    pub fn into_changes(self) -> Vec<RelationChange> {
+        let mut changes = self
+            .removed
+            .into_iter()
+            .map(RelationChange::Removed)
+            .collect::<Vec<_>>();
+        changes.push(self.set.into());
+        changes
+    }
+}
+
+impl ApplyOutcome {
+    fn push_write(&mut self, write: ReplaceOutcome) {
+        self.records.push(write.set.record.clone());
+        self.changes.extend(write.into_changes());
+    }
+}
+
 impl RelationStore {
+    /// Opens the store, setting aside unreadable files instead of failing.
+    /// A broken snapshot is replaced by the newest backup that parses, or
+    /// by an empty store, and its journal is set aside with it; a broken
+    /// journal keeps the entries before the broken line; broken schemas are
+    /// dropped. Each case is reported by
+    /// [`Self::problems`].
     pub fn open(path: PathBuf) -> io::Result<Self> {
-        let loaded_records = match fs::read_to_string(&path) {
-            Ok(contents) => serde_json::from_str(&contents).map_err(invalid_data)?,
-            Err(error) if error.kind() == io::ErrorKind::NotFound => Vec::new(),
-            Err(error) => return Err(error),
+        let mut problems = Vec::new();
+        let mut records = load_snapshot(&path, &mut problems)?
+            .into_iter()
+            .collect::<RelationIndex>();
+        let journal_path = Journal::path_for(&path);
+        let journal = match Journal::replay(journal_path.clone(), &mut records) {
+            Err(error) if error.kind() == io::ErrorKind::InvalidData => {
+                problems.push(set_aside(&journal_path, &error)?);
+                Journal::replay(journal_path, &mut records)?
+            }
+            journal => journal?,
+        };
+        let now = unix_ms();
+        let dropped = Edits::default().remove_where(&mut records, |record| {
+            !is_persistable_record(record) || is_expired(record, now)
+        });
+        let schemas = match read_json::<Vec<RelationSchema>>(&schema_path(&path))? {
+            Ok(schemas) => schemas.unwrap_or_default(),
+            Err(error) => {
+                problems.push(set_aside(&schema_path(&path), &error)?);
+                Vec::new()
+            }
+        }
+        .into_iter()
+        .map(|schema| (schema.relation.clone(), schema))
+        .collect();
+        let revision = records
+            .iter()
+            .map(|record| record.revision)
+            .chain([journal.revision()])
+            .max()
+            .unwrap_or_default();
+        let mut store = Self {
+            path,
+            records,
+            journal,
+            schemas,
+            revision,
+            accept_unknown_kinds: false,
+            problems,
         };
-        let records = persistent_records(&loaded_records);
-        let store = Self { path, records };
-        if store.records.len() != loaded_records.len() {
-            store.persist_records(&store.records)?;
+        if !dropped.is_empty()
+            || !store.problems.is_empty()
+            || store.journal.should_compact(store.records.len())
+        {
+            store.compact()?;
         }
         Ok(store)
     }
 
+    /// Lets writes use kinds the registry does not know, whose ids are then
+    /// only checked for being non-blank.
+    pub fn accept_unknown_kinds(&mut self, accept: bool) {
+        self.accept_unknown_kinds = accept;
+    }
+
+    /// What `open` had to set aside, oldest first.
+    pub fn problems(&self) -> &[StoreProblem] {
+        &self.problems
+    }
+
     pub fn set(
         &mut self,
         subject: RelationEndpoint,
         relation: String,
         target: RelationEndpoint,
-        metadata: HashMap<String, String>,
-    ) -> io::Result<SetOutcome> {
-        validate_endpoint("subject", &subject)?;
-        validate_relation(&relation)?;
-        validate_endpoint("target", &target)?;
-
-        let mut next = self.records.clone();
-        let outcome = set_in_records(&mut next, subject, relation, target, metadata);
-        self.persist_changed_records(&next)?;
-        self.records = next;
+        metadata: Metadata,
+    ) -> io::Result<ReplaceOutcome> {
+        self.validate_write(&subject, &relation, &target)?;
+
+        let mut edits = Edits::default();
+        let draft = draft_record(
+            subject,
+            relation,
+            target,
+            metadata,
+            &SetOptions::default(),
+            None,
+        );
+        let outcome = self.write_record(&mut edits, false, draft);
+        self.commit(edits)?;
         Ok(outcome)
     }
 
@@ -63,28 +217,59 @@ impl RelationStore {
         subject: RelationEndpoint,
         relation: String,
         target: RelationEndpoint,
-        metadata: HashMap<String, String>,
+        metadata: Metadata,
     ) -> io::Result<ReplaceOutcome> {
-        validate_endpoint("subject", &subject)?;
-        validate_relation(&relation)?;
-        validate_endpoint("target", &target)?;
-
-        let mut removed = Vec::new();
-        let mut next = Vec::with_capacity(self.records.len());
-        for record in &self.records {
-            let should_remove =
-                record.subject == subject && record.relation == relation && record.target != target;
-            if should_remove {
-                removed.push(record.clone());
-            } else {
-                next.push(record.clone());
-            }
+        self.validate_write(&subject, &relation, &target)?;
+
+        let mut edits = Edits::default();
+        let draft = draft_record(
+            subject,
+            relation,
+            target,
+            metadata,
+            &SetOptions::default(),
+            None,
+        );
+        let outcome = self.write_record(&mut edits, true, draft);
+        self.commit(edits)?;
+        Ok(outcome)
+    }
+
+    /// Sets and removes single metadata entries of an existing record and
+    /// keeps the others, stamping it like any other write.
+    pub fn patch_metadata(
+        &mut self,
+        subject: &RelationEndpoint,
+        relation: &str,
+        target: &RelationEndpoint,
+        set: Metadata,
+        remove: Vec<String>,
+    ) -> io::Result<SetOutcome> {
+        validate_endpoint("subject", subject)?;
+        validate_relation(relation)?;
+        validate_endpoint("target", target)?;
+        if let Some(key) = remove.iter().find(|key| set.contains_key(*key)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("metadata key {key:?} is both set and removed"),
+            ));
         }
+        let Some(record) = self.records.get(subject, relation, target) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("no {relation} relation from {subject} to {target}"),
+            ));
+        };
 
-        let set = set_in_records(&mut next, subject, relation, target, metadata);
-        self.persist_changed_records(&next)?;
-        self.records = next;
-        Ok(ReplaceOutcome { set, removed })
+        let mut draft = record.clone();
+        for key in &remove {
+            draft.metadata.remove(key);
+        }
+        draft.metadata.extend(set);
+        let mut edits = Edits::default();
+        let outcome = self.set_record(&mut edits, draft);
+        self.commit(edits)?;
+        Ok(outcome)
     }
 
     pub fn unset(
@@ -97,17 +282,10 @@ impl RelationStore {
         validate_relation(relation)?;
         validate_endpoint("target", target)?;
 
-        let Some(index) = self.records.iter().position(|record| {
-            &record.subject == subject && record.relation == relation && &record.target == target
-        }) else {
-            return Ok(None);
-        };
-
-        let mut next = self.records.clone();
-        let record = next.remove(index);
-        self.persist_changed_records(&next)?;
-        self.records = next;
-        Ok(Some(record))
+        let mut edits = Edits::default();
+        let removed = edits.remove(&mut self.records, subject, relation, target);
+        self.commit(edits)?;
+        Ok(removed)
     }
 
     pub fn clear(
@@ -118,603 +296,2870 @@ impl RelationStore {
         validate_endpoint("subject", subject)?;
         validate_relation(relation)?;
 
-        let mut removed = Vec::new();
-        let mut retained = Vec::with_capacity(self.records.len());
-        for record in &self.records {
-            if &record.subject == subject && record.relation == relation {
-                removed.push(record.clone());
-            } else {
-                retained.push(record.clone());
-            }
+        let mut edits = Edits::default();
+        let removed = self.clear_records(&mut edits, subject, relation);
+        self.commit(edits)?;
+        Ok(removed)
+    }
+
+    /// Applies every operation in place and commits them with a single
+    /// persist. Nothing is committed if any operation is invalid, and every
+    /// edit is rolled back if the persist fails. Owned set operations are
+    /// scoped to `caller`.
+    pub fn apply(
+        &mut self,
+        operations: Vec<RelationOperation>,
+        caller: Option<&str>,
+    ) -> io::Result<ApplyOutcome> {
+        for operation in &operations {
+            self.validate_operation(operation, caller)?;
         }
-        if !removed.is_empty() {
-            self.persist_changed_records(&retained)?;
-            self.records = retained;
+
+        let mut edits = Edits::default();
+        let mut outcome = ApplyOutcome::default();
+        for operation in operations {
+            match operation {
+                RelationOperation::Set {
+                    subject,
+                    relation,
+                    target,
+                    metadata,
+                    options,
+                } => {
+                    let draft = draft_record(subject, relation, target, metadata, &options, caller);
+                    outcome.push_write(self.write_record(&mut edits, false, draft));
+                }
+                RelationOperation::SetOne {
+                    subject,
+                    relation,
+                    target,
+                    metadata,
+                    options,
+                } => {
+                    let draft = draft_record(subject, relation, target, metadata, &options, caller);
+                    outcome.push_write(self.write_record(&mut edits, true, draft));
+                }
+                RelationOperation::Unset {
+                    subject,
+                    relation,
+                    target,
+                } => {
+                    let removed = edits.remove(&mut self.records, &subject, &relation, &target);
+                    outcome
+                        .changes
+                        .extend(removed.into_iter().map(RelationChange::Removed));
+                }
+                RelationOperation::Clear { subject, relation } => {
+                    let removed = self.clear_records(&mut edits, &subject, &relation);
+                    if removed.is_empty() {
+                        continue;
+                    }
+                    let removed_count = removed.len().try_into().unwrap_or(u32::MAX);
+                    outcome
+                        .changes
+                        .extend(removed.into_iter().map(RelationChange::Removed));
+                    outcome.changes.push(RelationChange::Cleared {
+                        subject,
+                        relation,
+                        removed_count,
+                    });
+                }
+            }
         }
-        Ok(removed)
+
+        self.commit(edits)?;
+        Ok(outcome)
     }
 
     pub fn targets(&self, subject: &RelationEndpoint, relation: &str) -> Vec<RelationEndpoint> {
-        let mut targets = self
-            .records
-            .iter()
-            .filter(|record| &record.subject == subject && record.relation == relation)
-            .map(|record| record.target.clone())
-            .collect::<Vec<_>>();
-        targets.sort();
-        targets
+        self.records.targets(subject, relation).cloned().collect()
     }
 
     pub fn subjects(&self, relation: &str, target: &RelationEndpoint) -> Vec<RelationEndpoint> {
-        let mut subjects = self
-            .records
-            .iter()
-            .filter(|record| record.relation == relation && &record.target == target)
-            .map(|record| record.subject.clone())
-            .collect::<Vec<_>>();
-        subjects.sort();
-        subjects
+        self.records.subjects(relation, target).cloned().collect()
     }
 
-    pub fn list(&self, relation: &str) -> Vec<RelationRecord> {
-        let mut records = self
-            .records
-            .iter()
-            .filter(|record| relation.is_empty() || record.relation == relation)
-            .cloned()
-            .collect::<Vec<_>>();
-        records.sort_by(|left, right| {
-            left.relation
-                .cmp(&right.relation)
-                .then_with(|| left.subject.cmp(&right.subject))
-                .then_with(|| left.target.cmp(&right.target))
-        });
-        records
+    /// The targets of each subject, once per distinct subject in the order
+    /// given. Subjects without targets map to an empty list.
+    pub fn targets_many(
+        &self,
+        subjects: &[RelationEndpoint],
+        relation: &str,
+    ) -> Vec<(RelationEndpoint, Vec<RelationEndpoint>)> {
+        distinct_endpoints(subjects)
+            .map(|subject| (subject.clone(), self.targets(subject, relation)))
+            .collect()
     }
 
-    pub fn relations(&self) -> Vec<String> {
-        let mut relations = self
-            .records
-            .iter()
-            .map(|record| record.relation.clone())
-            .collect::<Vec<_>>();
-        relations.sort();
-        relations.dedup();
-        relations
+    /// The subjects pointing at each target, like [`Self::targets_many`].
+    pub fn subjects_many(
+        &self,
+        relation: &str,
+        targets: &[RelationEndpoint],
+    ) -> Vec<(RelationEndpoint, Vec<RelationEndpoint>)> {
+        distinct_endpoints(targets)
+            .map(|target| (target.clone(), self.subjects(relation, target)))
+            .collect()
     }
 
-    pub fn len(&self) -> usize {
-        self.records.len()
+    pub fn list(&self, relation: &str) -> Vec<RelationRecord> {
+        self.list_matching(relation, &Metadata::new())
     }
 
-    fn persist_changed_records(&self, records: &[RelationRecord]) -> io::Result<()> {
-        if persistent_records(&self.records) == persistent_records(records) {
-            return Ok(());
+    /// Records of `relation`, or of every relation when it is empty, whose
+    /// metadata holds each entry of `metadata`.
+    pub fn list_matching(&self, relation: &str, metadata: &Metadata) -> Vec<RelationRecord> {
+        let matches = |record: &&RelationRecord| {
+            metadata
+                .iter()
+                .all(|(key, value)| record.metadata.get(key) == Some(value))
+        };
+        if relation.is_empty() {
+            self.records.iter().filter(matches).cloned().collect()
+        } else {
+            self.records
+                .relation(relation)
+                .filter(matches)
+                .cloned()
+                .collect()
         }
-        self.persist_records(records)
     }
 
-    fn persist_records(&self, records: &[RelationRecord]) -> io::Result<()> {
-        if let Some(parent) = self.path.parent() {
-            fs::create_dir_all(parent)?;
+    /// The revision a conditional write compares against: the record's own,
+    /// or without a `target` the newest among the subject's records under
+    /// `relation`. `0` when there is no such record.
+    pub fn revision(
+        &self,
+        subject: &RelationEndpoint,
+        relation: &str,
+        target: Option<&RelationEndpoint>,
+    ) -> u64 {
+        match target {
+            Some(target) => self
+                .records
+                .get(subject, relation, target)
+                .map_or(0, |record| record.revision),
+            None => self
+                .records
+                .targets(subject, relation)
+                .filter_map(|target| self.records.get(subject, relation, target))
+                .map(|record| record.revision)
+                .max()
+                .unwrap_or_default(),
         }
+    }
 
-        let tmp = self.path.with_extension("json.tmp");
-        let persistent = persistent_records(records);
-        let data = serde_json::to_vec_pretty(&persistent).map_err(io::Error::other)?;
-        fs::write(&tmp, data)?;
-        fs::rename(tmp, &self.path)
+    /// Fails unless [`Self::revision`] is `expected`.
+    pub fn expect_revision(
+        &self,
+        subject: &RelationEndpoint,
+        relation: &str,
+        target: Option<&RelationEndpoint>,
+        expected: u64,
+    ) -> Result<(), RevisionMismatch> {
+        let actual = self.revision(subject, relation, target);
+        if actual == expected {
+            Ok(())
+        } else {
+            Err(RevisionMismatch { expected, actual })
+        }
     }
-}
 
-fn persistent_records(records: &[RelationRecord]) -> Vec<RelationRecord> {
-    records
-        .iter()
-        .filter(|record| is_persistable_record(record))
-        .cloned()
-        .collect()
-}
+    /// Follows `path` from `start`, one step at a time across every endpoint
+    /// the previous step reached.
+    pub fn traverse(
+        &self,
+        start: &RelationEndpoint,
+        path: &[TraversalStep],
+    ) -> io::Result<Traversal> {
+        validate_endpoint("start", start)?;
+        for step in path {
+            validate_relation(step.relation())?;
+        }
 
-fn is_persistable_record(record: &RelationRecord) -> bool {
-    is_persistable_endpoint(&record.subject) && is_persistable_endpoint(&record.target)
-}
+        let mut reached = BTreeSet::from([start.clone()]);
+        let mut records = Vec::new();
+        for step in path {
+            let mut next = BTreeSet::new();
+            for endpoint in &reached {
+                let followed = match step {
+                    TraversalStep::Forward(relation) => self
+                        .records
+                        .targets(endpoint, relation)
+                        .filter_map(|target| self.records.get(endpoint, relation, target))
+                        .map(|record| (record, &record.target))
+                        .collect::<Vec<_>>(),
+                    TraversalStep::Inverse(relation) => self
+                        .records
+                        .subjects(relation, endpoint)
+                        .filter_map(|subject| self.records.get(subject, relation, endpoint))
+                        .map(|record| (record, &record.subject))
+                        .collect(),
+                };
+                for (record, other) in followed {
+                    records.push(record.clone());
+                    next.insert(other.clone());
+                }
+            }
+            reached = next;
+        }
+        Ok(Traversal {
+            endpoints: reached.into_iter().collect(),
+            records,
+        })
+    }
 
-fn is_persistable_endpoint(endpoint: &RelationEndpoint) -> bool {
-    !matches!(
-        endpoint,
-        RelationEndpoint::StableKey { kind, .. } if kind == keys::NIRI_WINDOW_ID
-    )
-}
+    pub fn relations(&self) -> Vec<String> {
+        self.records.relations().cloned().collect()
+    }
 
-pub fn default_store_path() -> PathBuf {
-    if let Some(path) = std::env::var_os("LOCUS_RELATIONS_PATH") {
-        return PathBuf::from(path);
+    pub fn len(&self) -> usize {
+        self.records.len()
     }
 
-    let state_home = std::env::var_os("XDG_STATE_HOME")
-        .map(PathBuf::from)
-        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))
-        .unwrap_or_else(|| PathBuf::from("."));
+    pub fn schemas(&self) -> Vec<RelationSchema> {
+        self.schemas.values().cloned().collect()
+    }
 
-    state_home.join("rsynapse/locus/relations.json")
-}
+    /// Registers or replaces the schema for `schema.relation`. Existing
+    /// records must already conform, so a schema never describes data it
+    /// would have rejected.
+    pub fn register_schema(&mut self, schema: RelationSchema) -> io::Result<()> {
+        validate_schema(&schema)?;
+        for record in self.records.relation(&schema.relation) {
+            validate_kinds(&schema, &record.subject, &record.target)?;
+        }
+        validate_cardinality(&schema, &self.records)?;
 
-fn validate_relation(value: &str) -> io::Result<()> {
-    if value.trim().is_empty() {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidInput,
-            "relation must not be empty",
-        ));
+        let mut next = self.schemas.clone();
+        next.insert(schema.relation.clone(), schema);
+        self.persist_schemas(&next)?;
+        self.schemas = next;
+        Ok(())
     }
-    Ok(())
-}
 
-fn validate_endpoint(name: &str, endpoint: &RelationEndpoint) -> io::Result<()> {
-    match endpoint {
-        RelationEndpoint::StableKey { kind, id } => {
-            validate_nonblank(name, "kind", kind)?;
-            validate_nonblank(name, "id", id)?;
-        }
-        RelationEndpoint::DBusObject {
-            bus,
-            service,
-            path,
-            interface,
-        } => {
-            validate_nonblank(name, "bus", bus)?;
-            validate_nonblank(name, "service", service)?;
-            validate_nonblank(name, "path", path)?;
-            validate_nonblank(name, "interface", interface)?;
-            zvariant::ObjectPath::try_from(path.as_str()).map_err(|error| {
-                io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    format!("{name}.path must be a valid D-Bus object path: {error}"),
-                )
-            })?;
+    pub fn unregister_schema(&mut self, relation: &str) -> io::Result<Option<RelationSchema>> {
+        if !self.schemas.contains_key(relation) {
+            return Ok(None);
         }
+        let mut next = self.schemas.clone();
+        let removed = next.remove(relation);
+        self.persist_schemas(&next)?;
+        self.schemas = next;
+        Ok(removed)
     }
-    Ok(())
-}
 
-fn validate_nonblank(endpoint: &str, field: &str, value: &str) -> io::Result<()> {
-    if value.trim().is_empty() {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidInput,
-            format!("{endpoint}.{field} must not be empty"),
-        ));
+    /// Durable records in `List` order, as the snapshot file holds them.
+    pub fn export(&self) -> Vec<RelationRecord> {
+        self.records
+            .iter()
+            .filter(|record| is_persistable_record(record))
+            .cloned()
+            .collect()
     }
-    Ok(())
-}
 
-fn set_in_records(
-    records: &mut Vec<RelationRecord>,
-    subject: RelationEndpoint,
-    relation: String,
-    target: RelationEndpoint,
-    metadata: HashMap<String, String>,
-) -> SetOutcome {
-    let now = unix_ms();
-    match records.iter_mut().find(|record| {
-        record.subject == subject && record.relation == relation && record.target == target
-    }) {
-        Some(record) => {
-            record.metadata = metadata;
-            record.updated_at_unix_ms = now;
-            SetOutcome {
-                record: record.clone(),
-                created: false,
+    /// Combines exported `records` with the store according to `mode` and
+    /// commits the result with a single persist. Imported records keep their
+    /// timestamps and expiry; ones that already expired are skipped, and a
+    /// record imported twice keeps its last version. Nothing changes if any
+    /// record is invalid or the result breaks a schema's cardinality.
+    pub fn import(
+        &mut self,
+        records: Vec<RelationRecord>,
+        mode: ImportMode,
+    ) -> io::Result<Vec<RelationChange>> {
+        for record in &records {
+            if !record.owner.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "imported records must not be owned",
+                ));
             }
+            self.validate_write(&record.subject, &record.relation, &record.target)?;
         }
-        None => {
-            let record = RelationRecord {
-                subject,
-                relation,
-                target,
-                metadata,
-                created_at_unix_ms: now,
-                updated_at_unix_ms: now,
+        let now = unix_ms();
+        let imported = records
+            .into_iter()
+            .filter(|record| !is_expired(record, now))
+            .collect::<RelationIndex>();
+
+        let mut edits = Edits::default();
+        let mut changes = Vec::new();
+        if mode == ImportMode::Replace {
+            let removed = edits.remove_where(&mut self.records, |record| {
+                is_persistable_record(record)
+                    && imported
+                        .get(&record.subject, &record.relation, &record.target)
+                        .is_none()
+            });
+            changes.extend(removed.into_iter().map(RelationChange::Removed));
+        }
+        for record in imported.iter() {
+            let previous = self
+                .records
+                .get(&record.subject, &record.relation, &record.target);
+            let write = match (mode, previous) {
+                (_, None) => true,
+                (ImportMode::Replace, Some(previous)) => {
+                    previous
+                        != &RelationRecord {
+                            revision: previous.revision,
+                            ..record.clone()
+                        }
+                }
+                (ImportMode::MergeNewer, Some(previous)) => {
+                    record.updated_at_unix_ms > previous.updated_at_unix_ms
+                }
+                (ImportMode::MergeKeepExisting, Some(_)) => false,
             };
-            records.push(record.clone());
-            SetOutcome {
-                record,
-                created: true,
+            if !write {
+                continue;
             }
+            let previous = previous.cloned();
+            let record = RelationRecord {
+                revision: self.next_revision(),
+                ..record.clone()
+            };
+            edits.insert(&mut self.records, record.clone());
+            changes.push(SetOutcome { record, previous }.into());
         }
-    }
-}
-
-fn unix_ms() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_millis()
-        .try_into()
-        .unwrap_or(u64::MAX)
-}
 
-fn invalid_data(error: serde_json::Error) -> io::Error {
-    io::Error::new(io::ErrorKind::InvalidData, error)
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
+        let violation = imported
+            .relations()
+            .filter_map(|relation| self.schemas.get(relation))
+            .find_map(|schema| validate_cardinality(schema, &self.records).err());
+        if let Some(error) = violation {
+            edits.rollback(&mut self.records);
+            return Err(error);
+        }
+        self.commit(edits)?;
+        Ok(changes)
+    }
 
-    fn key(kind: &str, id: &str) -> RelationEndpoint {
-        RelationEndpoint::stable_key(kind, id)
+    /// Earliest expiry among current records, if any record expires.
+    pub fn next_expiry(&self) -> Option<u64> {
+        self.records
+            .iter()
+            .map(|record| record.expires_at_unix_ms)
+            .filter(|expires_at| *expires_at != 0)
+            .min()
     }
 
-    fn workspace(id: u64) -> RelationEndpoint {
-        key("org.rsynapse.niri.workspace.id", &id.to_string())
+    /// Removes every record whose expiry is at or before `now`.
+    pub fn remove_expired(&mut self, now: u64) -> io::Result<Vec<RelationRecord>> {
+        let mut edits = Edits::default();
+        let removed = edits.remove_where(&mut self.records, |record| is_expired(record, now));
+        self.commit(edits)?;
+        Ok(removed)
     }
 
-    fn window(id: u64) -> RelationEndpoint {
-        key("org.rsynapse.niri.window.id", &id.to_string())
+    /// Removes every record scoped to a bus client that has disconnected.
+    pub fn remove_owned(&mut self, owner: &str) -> io::Result<Vec<RelationRecord>> {
+        if owner.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut edits = Edits::default();
+        let removed = edits.remove_where(&mut self.records, |record| record.owner == owner);
+        self.commit(edits)?;
+        Ok(removed)
     }
 
-    fn project(path: &str) -> RelationEndpoint {
-        key("org.rsynapse.project.path", path)
+    /// Removes every record with a subject or target matching `vanished`.
+    pub fn remove_referencing(
+        &mut self,
+        vanished: impl Fn(&RelationEndpoint) -> bool,
+    ) -> io::Result<Vec<RelationRecord>> {
+        let mut edits = Edits::default();
+        let removed = edits.remove_where(&mut self.records, |record| {
+            vanished(&record.subject) || vanished(&record.target)
+        });
+        self.commit(edits)?;
+        Ok(removed)
     }
 
-    fn agent(id: &str) -> RelationEndpoint {
-        key("org.rsynapse.agent.session.id", id)
+    /// Services named by D-Bus object endpoints, without duplicates.
+    pub fn dbus_services(&self) -> BTreeSet<String> {
+        self.records
+            .iter()
+            .flat_map(|record| [&record.subject, &record.target])
+            .filter_map(|endpoint| match endpoint {
+                RelationEndpoint::DBusObject { service, .. } => Some(service.clone()),
+                RelationEndpoint::StableKey { .. } => None,
+            })
+            .collect()
     }
 
-    fn icon(glyph: &str) -> RelationEndpoint {
-        key("org.rsynapse.icon.glyph", glyph)
+    fn validate_operation(
+        &self,
+        operation: &RelationOperation,
+        caller: Option<&str>,
+    ) -> io::Result<()> {
+        match operation {
+            RelationOperation::Set {
+                subject,
+                relation,
+                target,
+                options,
+                ..
+            }
+            | RelationOperation::SetOne {
+                subject,
+                relation,
+                target,
+                options,
+                ..
+            } => {
+                if options.is_owned() && caller.is_none() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "owned relations require a caller bus name",
+                    ));
+                }
+                if options.ttl_ms == Some(0) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "ttl-ms must be greater than zero",
+                    ));
+                }
+                self.validate_write(subject, relation, target)
+            }
+            RelationOperation::Unset {
+                subject,
+                relation,
+                target,
+            } => {
+                validate_endpoint("subject", subject)?;
+                validate_relation(relation)?;
+                validate_endpoint("target", target)
+            }
+            RelationOperation::Clear { subject, relation } => {
+                validate_endpoint("subject", subject)?;
+                validate_relation(relation)
+            }
+        }
     }
 
-    fn record(
-        subject: RelationEndpoint,
+    fn validate_write(
+        &self,
+        subject: &RelationEndpoint,
         relation: &str,
-        target: RelationEndpoint,
-    ) -> RelationRecord {
-        RelationRecord {
-            subject,
-            relation: relation.to_owned(),
+        target: &RelationEndpoint,
+    ) -> io::Result<()> {
+        validate_endpoint("subject", subject)?;
+        validate_relation(relation)?;
+        validate_endpoint("target", target)?;
+        self.validate_key("subject", subject)?;
+        self.validate_key("target", target)?;
+        match self.schemas.get(relation) {
+            Some(schema) => validate_kinds(schema, subject, target),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks a written stable key against its kind's id format. Removals
+    /// skip this so records written before a format existed can be cleaned up.
+    fn validate_key(&self, name: &str, endpoint: &RelationEndpoint) -> io::Result<()> {
+        let RelationEndpoint::StableKey { kind, id } = endpoint else {
+            return Ok(());
+        };
+        match keys::lookup(kind) {
+            Some(known) if !known.accepts(id) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{name} {kind} id {id:?} must be {}", known.format),
+            )),
+            None if !self.accept_unknown_kinds => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{name} kind {kind:?} is not registered"),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Writes one record, first removing whatever the relation's cardinality
+    /// (or an explicit `single_target`) says it replaces.
+    fn write_record(
+        &mut self,
+        edits: &mut Edits,
+        single_target: bool,
+        draft: RelationRecord,
+    ) -> ReplaceOutcome {
+        let cardinality = self
+            .schemas
+            .get(&draft.relation)
+            .map(|schema| schema.cardinality)
+            .unwrap_or_default();
+        let mut replaced = Vec::new();
+        if single_target || cardinality.single_target() {
+            replaced.extend(
+                self.records
+                    .targets(&draft.subject, &draft.relation)
+                    .filter(|target| **target != draft.target)
+                    .map(|target| (draft.subject.clone(), target.clone())),
+            );
+        }
+        if cardinality.single_subject() {
+            replaced.extend(
+                self.records
+                    .subjects(&draft.relation, &draft.target)
+                    .filter(|subject| **subject != draft.subject)
+                    .map(|subject| (subject.clone(), draft.target.clone())),
+            );
+        }
+        let removed = replaced
+            .into_iter()
+            .filter_map(|(subject, target)| {
+                edits.remove(&mut self.records, &subject, &draft.relation, &target)
+            })
+            .collect();
+        let set = self.set_record(edits, draft);
+        ReplaceOutcome { set, removed }
+    }
+
+    /// Inserts `draft` or updates the existing record for the same subject,
+    /// relation and target, stamping creation and update times.
+    fn set_record(&mut self, edits: &mut Edits, mut draft: RelationRecord) -> SetOutcome {
+        let now = unix_ms();
+        let previous = self
+            .records
+            .get(&draft.subject, &draft.relation, &draft.target)
+            .cloned();
+        draft.created_at_unix_ms = previous
+            .as_ref()
+            .map_or(now, |record| record.created_at_unix_ms);
+        draft.updated_at_unix_ms = now;
+        draft.revision = self.next_revision();
+        edits.insert(&mut self.records, draft.clone());
+        SetOutcome {
+            record: draft,
+            previous,
+        }
+    }
+
+    fn clear_records(
+        &mut self,
+        edits: &mut Edits,
+        subject: &RelationEndpoint,
+        relation: &str,
+    ) -> Vec<RelationRecord> {
+        let targets = self
+            .records
+            .targets(subject, relation)
+            .cloned()
+            .collect::<Vec<_>>();
+        targets
+            .iter()
+            .filter_map(|target| edits.remove(&mut self.records, subject, relation, target))
+            .collect()
+    }
+
+    /// Journals `edits` if they touched a durable record, rolling them back
+    /// if that fails. Compaction runs after the commit and cannot undo it.
+    fn commit(&mut self, edits: Edits) -> io::Result<()> {
+        if !edits.persist {
+            return Ok(());
+        }
+        let entry = JournalEntry {
+            revision: self.revision,
+            ..edits.journal_entry(&self.records)
+        };
+        if let Err(error) = self.journal.append(&entry) {
+            edits.rollback(&mut self.records);
+            return Err(error);
+        }
+        if self.journal.should_compact(self.records.len())
+            && let Err(error) = self.compact()
+        {
+            warn!("failed to compact relation journal: {error}");
+        }
+        Ok(())
+    }
+
+    /// Writes every durable record to the snapshot and empties the journal.
+    fn compact(&mut self) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let tmp = self.path.with_extension("json.tmp");
+        let persistent = self
+            .records
+            .iter()
+            .filter(|record| is_persistable_record(record))
+            .collect::<Vec<_>>();
+        let data = serde_json::to_vec_pretty(&persistent).map_err(io::Error::other)?;
+        fs::write(&tmp, data)?;
+        rotate_backups(&self.path)?;
+        fs::rename(tmp, &self.path)?;
+        self.journal.reset()?;
+
+        // Keep the counter past revisions that left the snapshot with their
+        // records.
+        let snapshot_revision = persistent
+            .iter()
+            .map(|record| record.revision)
+            .max()
+            .unwrap_or_default();
+        if self.revision > snapshot_revision {
+            self.journal.append(&JournalEntry {
+                revision: self.revision,
+                ..JournalEntry::default()
+            })?;
+        }
+        Ok(())
+    }
+
+    fn next_revision(&mut self) -> u64 {
+        self.revision += 1;
+        self.revision
+    }
+
+    fn persist_schemas(&self, schemas: &BTreeMap<String, RelationSchema>) -> io::Result<()> {
+        let path = schema_path(&self.path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let tmp = path.with_extension("json.tmp");
+        let schemas = schemas.values().collect::<Vec<_>>();
+        let data = serde_json::to_vec_pretty(&schemas).map_err(io::Error::other)?;
+        fs::write(&tmp, data)?;
+        fs::rename(tmp, path)
+    }
+}
+
+/// In-place index edits, kept so a failed persist can roll them back.
+#[derive(Debug, Default)]
+struct Edits {
+    undo: Vec<Undo>,
+    /// Whether any edit touched a record that belongs on disk.
+    persist: bool,
+}
+
+#[derive(Debug)]
+enum Undo {
+    Inserted {
+        subject: RelationEndpoint,
+        relation: String,
+        target: RelationEndpoint,
+    },
+    /// Also records the previous version of a replaced record, pushed before
+    /// the `Inserted` that replaced it.
+    Removed(RelationRecord),
+}
+
+impl Edits {
+    fn insert(&mut self, records: &mut RelationIndex, record: RelationRecord) {
+        self.persist |= is_persistable_record(&record);
+        let subject = record.subject.clone();
+        let relation = record.relation.clone();
+        let target = record.target.clone();
+        if let Some(previous) = records.insert(record) {
+            self.persist |= is_persistable_record(&previous);
+            self.undo.push(Undo::Removed(previous));
+        }
+        self.undo.push(Undo::Inserted {
+            subject,
+            relation,
             target,
-            metadata: HashMap::new(),
-            created_at_unix_ms: 1,
-            updated_at_unix_ms: 1,
+        });
+    }
+
+    fn remove(
+        &mut self,
+        records: &mut RelationIndex,
+        subject: &RelationEndpoint,
+        relation: &str,
+        target: &RelationEndpoint,
+    ) -> Option<RelationRecord> {
+        let removed = records.remove(subject, relation, target)?;
+        self.persist |= is_persistable_record(&removed);
+        self.undo.push(Undo::Removed(removed.clone()));
+        Some(removed)
+    }
+
+    fn remove_where(
+        &mut self,
+        records: &mut RelationIndex,
+        predicate: impl Fn(&RelationRecord) -> bool,
+    ) -> Vec<RelationRecord> {
+        let matching = records
+            .iter()
+            .filter(|record| predicate(record))
+            .map(|record| {
+                (
+                    record.subject.clone(),
+                    record.relation.clone(),
+                    record.target.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        matching
+            .into_iter()
+            .filter_map(|(subject, relation, target)| {
+                self.remove(records, &subject, &relation, &target)
+            })
+            .collect()
+    }
+
+    /// The net effect of the edits on durable records: the current version
+    /// of every touched record that belongs on disk, and the keys of the rest.
+    fn journal_entry(&self, records: &RelationIndex) -> JournalEntry {
+        let touched = self
+            .undo
+            .iter()
+            .map(|undo| match undo {
+                Undo::Inserted {
+                    subject,
+                    relation,
+                    target,
+                } => (subject, relation, target),
+                Undo::Removed(record) => (&record.subject, &record.relation, &record.target),
+            })
+            .collect::<BTreeSet<_>>();
+        let mut entry = JournalEntry::default();
+        for (subject, relation, target) in touched {
+            match records.get(subject, relation, target) {
+                Some(record) if is_persistable_record(record) => entry.put.push(record.clone()),
+                _ => entry.delete.push(RecordKey {
+                    subject: subject.clone(),
+                    relation: relation.clone(),
+                    target: target.clone(),
+                }),
+            }
+        }
+        entry
+    }
+
+    fn rollback(self, records: &mut RelationIndex) {
+        for undo in self.undo.into_iter().rev() {
+            match undo {
+                Undo::Inserted {
+                    subject,
+                    relation,
+                    target,
+                } => {
+                    records.remove(&subject, &relation, &target);
+                }
+                Undo::Removed(record) => {
+                    records.insert(record);
+                }
+            }
+        }
+    }
+}
+
+/// `endpoints` in order, skipping repeats.
+fn distinct_endpoints(endpoints: &[RelationEndpoint]) -> impl Iterator<Item = &RelationEndpoint> {
+    let mut seen = BTreeSet::new();
+    endpoints
+        .iter()
+        .filter(move |endpoint| seen.insert(*endpoint))
+}
+
+/// Schemas live next to the relation file, e.g. `relations.schemas.json`.
+fn schema_path(path: &Path) -> PathBuf {
+    path.with_extension("schemas.json")
+}
+
+fn backup_path(path: &Path, generation: usize) -> PathBuf {
+    path.with_extension(format!("{generation}.json"))
+}
+
+/// Shifts every backup one generation older and keeps the current snapshot
+/// as the newest. The snapshot is linked rather than moved so that it stays
+/// in place until the new one is renamed over it.
+fn rotate_backups(path: &Path) -> io::Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    for generation in (1..BACKUPS).rev() {
+        match fs::rename(
+            backup_path(path, generation),
+            backup_path(path, generation + 1),
+        ) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+            _ => {}
+        }
+    }
+    let newest = backup_path(path, 1);
+    match fs::remove_file(&newest) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+        _ => {}
+    }
+    if fs::hard_link(path, &newest).is_err() {
+        fs::copy(path, &newest)?;
+    }
+    Ok(())
+}
+
+/// Reads the snapshot, or when it does not parse, sets it aside and falls
+/// back to the newest backup that does.
+fn load_snapshot(path: &Path, problems: &mut Vec<StoreProblem>) -> io::Result<Vec<RelationRecord>> {
+    let error = match read_json::<Vec<RelationRecord>>(path)? {
+        Ok(records) => return Ok(records.unwrap_or_default()),
+        Err(error) => error,
+    };
+    let mut problem = set_aside(path, &error)?;
+    let mut records = Vec::new();
+    for generation in 1..=BACKUPS {
+        let backup = backup_path(path, generation);
+        match read_json(&backup)? {
+            Ok(Some(backup_records)) => {
+                warn!("restored {} from {}", path.display(), backup.display());
+                problem.restored_from = backup.display().to_string();
+                records = backup_records;
+                break;
+            }
+            Ok(None) => {}
+            Err(error) => warn!("skipping unreadable backup {}: {error}", backup.display()),
+        }
+    }
+    problems.push(problem);
+
+    // The journal extends the snapshot just set aside, so replaying it onto
+    // a backup or an empty store would mix two states.
+    let journal = Journal::path_for(path);
+    if fs::metadata(&journal).is_ok_and(|metadata| metadata.len() > 0) {
+        let extends = format!("extends {}, which was set aside", path.display());
+        problems.push(set_aside(&journal, &extends)?);
+    }
+    Ok(records)
+}
+
+/// Parses a JSON file. The outer error is a failed read; the inner one means
+/// the file exists but does not parse. A missing file reads as `None`.
+fn read_json<T>(path: &Path) -> io::Result<Result<Option<T>, serde_json::Error>>
+where
+    T: serde::de::DeserializeOwned,
+{
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(serde_json::from_str(&contents).map(Some)),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Ok(None)),
+        Err(error) => Err(error),
+    }
+}
+
+/// Moves an unreadable file to a timestamped name next to it, where nothing
+/// overwrites it, and describes what happened.
+fn set_aside(path: &Path, error: &dyn fmt::Display) -> io::Result<StoreProblem> {
+    let at_unix_ms = unix_ms();
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = format!("{stem}.corrupt-{at_unix_ms}");
+    if let Some(extension) = path.extension() {
+        name = format!("{name}.{}", extension.to_string_lossy());
+    }
+    let moved_to = path.with_file_name(name);
+    fs::rename(path, &moved_to)?;
+    warn!(
+        "moved unreadable {} to {}: {error}",
+        path.display(),
+        moved_to.display()
+    );
+    Ok(StoreProblem {
+        path: path.display().to_string(),
+        error: error.to_string(),
+        moved_to: moved_to.display().to_string(),
+        restored_from: String::new(),
+        at_unix_ms,
+    })
+}
+
+fn is_persistable_record(record: &RelationRecord) -> bool {
+    record.owner.is_empty()
+        && is_persistable_endpoint(&record.subject)
+        && is_persistable_endpoint(&record.target)
+}
+
+fn is_persistable_endpoint(endpoint: &RelationEndpoint) -> bool {
+    !matches!(
+        endpoint,
+        RelationEndpoint::StableKey { kind, .. } if kind == keys::NIRI_WINDOW_ID
+    )
+}
+
+pub fn default_store_path() -> PathBuf {
+    if let Some(path) = std::env::var_os("LOCUS_RELATIONS_PATH") {
+        return PathBuf::from(path);
+    }
+
+    let state_home = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))
+        .unwrap_or_else(|| PathBuf::from("."));
+
+    state_home.join("rsynapse/locus/relations.json")
+}
+
+/// Whether `LOCUS_ACCEPT_UNKNOWN_KINDS` asks for writes with unregistered
+/// kinds to be accepted.
+pub fn accept_unknown_kinds_from_env() -> bool {
+    std::env::var("LOCUS_ACCEPT_UNKNOWN_KINDS").is_ok_and(|value| {
+        matches!(
+            value.trim().to_ascii_lowercase().as_str(),
+            "1" | "true" | "yes"
+        )
+    })
+}
+
+pub fn validate_relation(value: &str) -> io::Result<()> {
+    if value.trim().is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "relation must not be empty",
+        ));
+    }
+    Ok(())
+}
+
+pub fn validate_endpoint(name: &str, endpoint: &RelationEndpoint) -> io::Result<()> {
+    match endpoint {
+        RelationEndpoint::StableKey { kind, id } => {
+            validate_nonblank(name, "kind", kind)?;
+            validate_nonblank(name, "id", id)?;
+        }
+        RelationEndpoint::DBusObject {
+            bus,
+            service,
+            path,
+            interface,
+        } => {
+            validate_nonblank(name, "bus", bus)?;
+            validate_nonblank(name, "service", service)?;
+            validate_nonblank(name, "path", path)?;
+            validate_nonblank(name, "interface", interface)?;
+            zvariant::ObjectPath::try_from(path.as_str()).map_err(|error| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{name}.path must be a valid D-Bus object path: {error}"),
+                )
+            })?;
+        }
+    }
+    Ok(())
+}
+
+fn validate_schema(schema: &RelationSchema) -> io::Result<()> {
+    validate_relation(&schema.relation)?;
+    for (side, kinds) in [
+        ("subject", &schema.subject_kinds),
+        ("target", &schema.target_kinds),
+    ] {
+        for kind in kinds {
+            validate_nonblank("schema", &format!("{side}_kinds"), kind)?;
+        }
+    }
+    Ok(())
+}
+
+fn validate_kinds(
+    schema: &RelationSchema,
+    subject: &RelationEndpoint,
+    target: &RelationEndpoint,
+) -> io::Result<()> {
+    for (side, kinds, endpoint) in [
+        ("subject", &schema.subject_kinds, subject),
+        ("target", &schema.target_kinds, target),
+    ] {
+        if !kinds.is_empty() && !kinds.iter().any(|kind| kind == endpoint.kind()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{side} kind {:?} is not allowed by the {} schema; expected one of {kinds:?}",
+                    endpoint.kind(),
+                    schema.relation
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the relation's records give no subject more than one target,
+/// and no target more than one subject, where the schema forbids it.
+fn validate_cardinality(schema: &RelationSchema, records: &RelationIndex) -> io::Result<()> {
+    let relation_records = records.relation(&schema.relation);
+    if schema.cardinality.single_target() {
+        reject_shared(
+            schema,
+            relation_records.clone().map(|record| &record.subject),
+        )?;
+    }
+    if schema.cardinality.single_subject() {
+        reject_shared(schema, relation_records.map(|record| &record.target))?;
+    }
+    Ok(())
+}
+
+fn reject_shared<'a>(
+    schema: &RelationSchema,
+    endpoints: impl Iterator<Item = &'a RelationEndpoint>,
+) -> io::Result<()> {
+    let mut seen = BTreeSet::new();
+    for endpoint in endpoints {
+        if !seen.insert(endpoint) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} records violate {:?} cardinality at {endpoint:?}",
+                    schema.relation, schema.cardinality
+                ),
+            ));
         }
     }
+    Ok(())
+}
+
+fn validate_nonblank(endpoint: &str, field: &str, value: &str) -> io::Result<()> {
+    if value.trim().is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{endpoint}.{field} must not be empty"),
+        ));
+    }
+    Ok(())
+}
+
+/// Builds a record to be written by `set_record`, which stamps creation
+/// and update times.
+fn draft_record(
+    subject: RelationEndpoint,
+    relation: String,
+    target: RelationEndpoint,
+    metadata: Metadata,
+    options: &SetOptions,
+    caller: Option<&str>,
+) -> RelationRecord {
+    let owner = match caller {
+        Some(caller) if options.is_owned() => caller.to_owned(),
+        _ => String::new(),
+    };
+    let expires_at_unix_ms = options
+        .ttl_ms
+        .map(|ttl_ms| unix_ms().saturating_add(ttl_ms))
+        .unwrap_or(0);
+    RelationRecord {
+        subject,
+        relation,
+        target,
+        metadata,
+        created_at_unix_ms: 0,
+        updated_at_unix_ms: 0,
+        owner,
+        expires_at_unix_ms,
+        revision: 0,
+    }
+}
+
+fn is_expired(record: &RelationRecord, now: u64) -> bool {
+    record.expires_at_unix_ms != 0 && record.expires_at_unix_ms <= now
+}
+
+pub fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+        .try_into()
+        .unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use locus::RelationCardinality;
+
+    use super::*;
+
+    fn key(kind: &str, id: &str) -> RelationEndpoint {
+        RelationEndpoint::stable_key(kind, id)
+    }
+
+    fn workspace(id: u64) -> RelationEndpoint {
+        key("org.rsynapse.niri.workspace.id", &id.to_string())
+    }
+
+    fn window(id: u64) -> RelationEndpoint {
+        key("org.rsynapse.niri.window.id", &id.to_string())
+    }
+
+    fn project(path: &str) -> RelationEndpoint {
+        key("org.rsynapse.project.path", path)
+    }
+
+    fn agent(id: &str) -> RelationEndpoint {
+        key("org.rsynapse.agent.session.id", id)
+    }
+
+    fn icon(glyph: &str) -> RelationEndpoint {
+        key(keys::ICON_GLYPH, glyph)
+    }
+
+    fn record(
+        subject: RelationEndpoint,
+        relation: &str,
+        target: RelationEndpoint,
+    ) -> RelationRecord {
+        RelationRecord {
+            subject,
+            relation: relation.to_owned(),
+            target,
+            metadata: HashMap::new(),
+            created_at_unix_ms: 1,
+            updated_at_unix_ms: 1,
+            owner: String::new(),
+            expires_at_unix_ms: 0,
+            revision: 0,
+        }
+    }
+
+    #[test]
+    fn set_query_unset_and_reload() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path.clone()).expect("open store");
+        let outcome = store
+            .set(
+                workspace(5),
+                "org.rsynapse.WorkspaceProject".to_owned(),
+                project("/src/rsynapse"),
+                HashMap::from([("source".to_owned(), "test".into())]),
+            )
+            .expect("set");
+        let record = outcome.set.record;
+
+        assert!(outcome.set.previous.is_none());
+        assert_eq!(record.created_at_unix_ms, record.updated_at_unix_ms);
+        assert_eq!(
+            store.targets(&workspace(5), "org.rsynapse.WorkspaceProject"),
+            vec![project("/src/rsynapse")]
+        );
+
+        let store = RelationStore::open(path.clone()).expect("reload store");
+        assert_eq!(store.list("org.rsynapse.WorkspaceProject").len(), 1);
+
+        let mut store = RelationStore::open(path).expect("reload mutable store");
+        assert!(
+            store
+                .unset(
+                    &workspace(5),
+                    "org.rsynapse.WorkspaceProject",
+                    &project("/src/rsynapse"),
+                )
+                .expect("unset")
+                .is_some()
+        );
+        assert!(
+            store
+                .targets(&workspace(5), "org.rsynapse.WorkspaceProject")
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn named_workspace_icon_override_survives_reload() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let subject = key(keys::NIRI_WORKSPACE_NAME, "coding");
+        let relation = "org.rsynapse.workspace.icon-override";
+        let target = icon("glyph");
+        let mut store = RelationStore::open(path.clone()).expect("open store");
+
+        store
+            .set_one(
+                subject.clone(),
+                relation.to_owned(),
+                target.clone(),
+                HashMap::from([("pick-icon-input".to_owned(), "rust".into())]),
+            )
+            .expect("set icon override");
+
+        let store = RelationStore::open(path).expect("reload store");
+        assert_eq!(store.targets(&subject, relation), vec![target]);
+    }
+
+    #[test]
+    fn set_updates_existing_record_without_duplicating_it() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path).expect("open store");
+        let first = store
+            .set(
+                window(1),
+                "org.rsynapse.WindowAgent".to_owned(),
+                agent("codex"),
+                HashMap::from([("state".to_owned(), "thinking".into())]),
+            )
+            .expect("first set");
+        let second = store
+            .set(
+                window(1),
+                "org.rsynapse.WindowAgent".to_owned(),
+                agent("codex"),
+                HashMap::from([("state".to_owned(), "idle".into())]),
+            )
+            .expect("second set");
+
+        assert!(first.set.previous.is_none());
+        assert_eq!(second.set.previous, Some(first.set.record.clone()));
+        assert_eq!(store.list("").len(), 1);
+        assert_eq!(
+            second.set.record.metadata,
+            HashMap::from([("state".to_owned(), "idle".into())])
+        );
+        assert_eq!(
+            first.set.record.created_at_unix_ms,
+            second.set.record.created_at_unix_ms
+        );
+    }
+
+    #[test]
+    fn patch_metadata_keeps_other_entries_and_list_filters_on_them() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path.clone()).expect("open store");
+        let relation = "org.rsynapse.workspace.project";
+        for (id, branch) in [(1, "main"), (2, "feature")] {
+            store
+                .set(
+                    workspace(id),
+                    relation.to_owned(),
+                    project("/src/rsynapse"),
+                    HashMap::from([
+                        ("branch".to_owned(), branch.into()),
+                        ("name".to_owned(), "rsynapse".into()),
+                    ]),
+                )
+                .expect("set project");
+        }
+
+        let patched = store
+            .patch_metadata(
+                &workspace(1),
+                relation,
+                &project("/src/rsynapse"),
+                HashMap::from([("dirty-files".to_owned(), locus::MetadataValue::Int(3))]),
+                vec!["name".to_owned()],
+            )
+            .expect("patch metadata");
+        let expected = HashMap::from([
+            ("branch".to_owned(), "main".into()),
+            ("dirty-files".to_owned(), locus::MetadataValue::Int(3)),
+        ]);
+        assert_eq!(patched.record.metadata, expected);
+        assert_eq!(
+            patched.previous.map(|previous| previous.revision),
+            Some(patched.record.revision - 2)
+        );
+
+        let store = RelationStore::open(path).expect("reload store");
+        let main = HashMap::from([("branch".to_owned(), "main".into())]);
+        let records = store.list_matching(relation, &main);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].subject, workspace(1));
+        assert_eq!(records[0].metadata, expected);
+        let named = HashMap::from([("name".to_owned(), "rsynapse".into())]);
+        assert_eq!(store.list_matching("", &named)[0].subject, workspace(2));
+        let typed = HashMap::from([("dirty-files".to_owned(), "3".into())]);
+        assert!(store.list_matching(relation, &typed).is_empty());
+    }
+
+    #[test]
+    fn patch_metadata_rejects_missing_records_and_conflicting_keys() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path).expect("open store");
+        let relation = "org.rsynapse.workspace.project";
+        let error = store
+            .patch_metadata(
+                &workspace(1),
+                relation,
+                &project("/src/rsynapse"),
+                HashMap::new(),
+                Vec::new(),
+            )
+            .expect_err("missing record rejected");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+        store
+            .set(
+                workspace(1),
+                relation.to_owned(),
+                project("/src/rsynapse"),
+                HashMap::new(),
+            )
+            .expect("set project");
+        let error = store
+            .patch_metadata(
+                &workspace(1),
+                relation,
+                &project("/src/rsynapse"),
+                HashMap::from([("branch".to_owned(), "main".into())]),
+                vec!["branch".to_owned()],
+            )
+            .expect_err("conflicting keys rejected");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn window_relations_are_memory_only() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path.clone()).expect("open store");
+        store
+            .set(
+                window(24),
+                "org.rsynapse.window.agent-session".to_owned(),
+                agent("codex/session"),
+                HashMap::new(),
+            )
+            .expect("set transient window relation");
+
+        assert_eq!(
+            store.targets(&window(24), "org.rsynapse.window.agent-session"),
+            vec![agent("codex/session")]
+        );
+
+        let store = RelationStore::open(path).expect("reload store");
+        assert!(
+            store
+                .targets(&window(24), "org.rsynapse.window.agent-session")
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn loading_drops_persisted_window_relations() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let records = vec![
+            record(
+                window(24),
+                "org.rsynapse.window.agent-session",
+                agent("codex/session"),
+            ),
+            record(
+                workspace(3),
+                "org.rsynapse.workspace.project",
+                project("/src/rsynapse"),
+            ),
+        ];
+        fs::write(
+            &path,
+            serde_json::to_vec(&records).expect("serialize records"),
+        )
+        .expect("write records");
+
+        let store = RelationStore::open(path.clone()).expect("open store");
+
+        assert!(store.list("org.rsynapse.window.agent-session").is_empty());
+        assert_eq!(store.list("org.rsynapse.workspace.project").len(), 1);
+
+        let persisted: Vec<RelationRecord> =
+            serde_json::from_slice(&fs::read(path).expect("read cleaned persistent store"))
+                .expect("parse cleaned persistent store");
+        assert_eq!(persisted.len(), 1);
+        assert_eq!(persisted[0].relation, "org.rsynapse.workspace.project");
+    }
+
+    #[test]
+    fn transient_window_relations_do_not_require_persistence() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path.clone()).expect("open store");
+        fs::create_dir(&path).expect("replace store path with directory");
+
+        store
+            .set(
+                window(24),
+                "org.rsynapse.window.agent-session".to_owned(),
+                agent("codex/session"),
+                HashMap::new(),
+            )
+            .expect("set transient window relation");
+
+        assert_eq!(
+            store.targets(&window(24), "org.rsynapse.window.agent-session"),
+            vec![agent("codex/session")]
+        );
+        assert!(
+            store
+                .unset(
+                    &window(24),
+                    "org.rsynapse.window.agent-session",
+                    &agent("codex/session"),
+                )
+                .expect("unset transient window relation")
+                .is_some()
+        );
+        store
+            .set(
+                window(24),
+                "org.rsynapse.window.agent-session".to_owned(),
+                agent("codex/session"),
+                HashMap::new(),
+            )
+            .expect("set transient window relation again");
+        assert_eq!(
+            store
+                .clear(&window(24), "org.rsynapse.window.agent-session")
+                .expect("clear transient window relation")
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn set_one_replaces_other_targets_for_subject_relation() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path).expect("open store");
+        store
+            .set(
+                workspace(1),
+                "org.rsynapse.WorkspaceProject".to_owned(),
+                project("/src/old"),
+                HashMap::new(),
+            )
+            .expect("set old");
+
+        let outcome = store
+            .set_one(
+                workspace(1),
+                "org.rsynapse.WorkspaceProject".to_owned(),
+                project("/src/new"),
+                HashMap::new(),
+            )
+            .expect("replace");
+
+        assert_eq!(outcome.removed.len(), 1);
+        assert_eq!(
+            store.targets(&workspace(1), "org.rsynapse.WorkspaceProject"),
+            vec![project("/src/new")]
+        );
+    }
+
+    #[test]
+    fn batched_queries_answer_each_distinct_endpoint_once() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path).expect("open store");
+        let relation = "org.rsynapse.workspace.project";
+        for (id, name) in [(1, "/src/a"), (2, "/src/a"), (2, "/src/b")] {
+            store
+                .set(
+                    workspace(id),
+                    relation.to_owned(),
+                    project(name),
+                    HashMap::new(),
+                )
+                .expect("set project");
+        }
+
+        assert_eq!(
+            store.targets_many(&[workspace(2), workspace(3), workspace(2)], relation),
+            vec![
+                (workspace(2), vec![project("/src/a"), project("/src/b")]),
+                (workspace(3), Vec::new()),
+            ]
+        );
+        assert_eq!(
+            store.subjects_many(relation, &[project("/src/b"), project("/src/a")]),
+            vec![
+                (project("/src/b"), vec![workspace(2)]),
+                (project("/src/a"), vec![workspace(1), workspace(2)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn relations_are_sorted_and_deduplicated() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path).expect("open store");
+        for relation in [
+            "org.rsynapse.WindowAgent",
+            "org.rsynapse.WorkspaceProject",
+            "org.rsynapse.WindowAgent",
+        ] {
+            store
+                .set(
+                    workspace(1),
+                    relation.to_owned(),
+                    project("/src/one"),
+                    HashMap::new(),
+                )
+                .expect("set relation");
+        }
+
+        assert_eq!(
+            store.relations(),
+            vec!["org.rsynapse.WindowAgent", "org.rsynapse.WorkspaceProject"]
+        );
+    }
+
+    #[test]
+    fn rejects_blank_references() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path).expect("open store");
+        let error = store
+            .set(
+                key(" ", "subject"),
+                "org.rsynapse.WorkspaceProject".to_owned(),
+                project("/src/rsynapse"),
+                HashMap::new(),
+            )
+            .expect_err("blank subject rejected");
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn writes_check_registered_id_formats_but_removals_do_not() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path).expect("open store");
+        let tag = key("org.rsynapse.tag", "pinned");
+        for target in [project("/src/rsynapse/"), tag.clone()] {
+            let error = store
+                .set(
+                    workspace(1),
+                    "org.rsynapse.workspace.project".to_owned(),
+                    target,
+                    HashMap::new(),
+                )
+                .expect_err("invalid target rejected");
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        }
+        store
+            .unset(
+                &workspace(1),
+                "org.rsynapse.workspace.project",
+                &project("/src/rsynapse/"),
+            )
+            .expect("unset tolerates legacy ids");
+
+        store.accept_unknown_kinds(true);
+        store
+            .set(
+                workspace(1),
+                "org.rsynapse.workspace.tag".to_owned(),
+                tag.clone(),
+                HashMap::new(),
+            )
+            .expect("unknown kind accepted when permissive");
+        let error = store
+            .set(
+                workspace(1),
+                "org.rsynapse.workspace.project".to_owned(),
+                project("rsynapse"),
+                HashMap::new(),
+            )
+            .expect_err("registered kinds stay strict");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(
+            store.targets(&workspace(1), "org.rsynapse.workspace.tag"),
+            vec![tag]
+        );
+    }
+
+    #[test]
+    fn failed_set_persistence_does_not_change_memory() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path.clone()).expect("open store");
+        store
+            .set(
+                workspace(1),
+                "org.rsynapse.WorkspaceProject".to_owned(),
+                project("/src/old"),
+                HashMap::new(),
+            )
+            .expect("initial set");
+
+        let journal = Journal::path_for(&path);
+        fs::remove_file(&journal).expect("remove journal");
+        fs::create_dir(&journal).expect("replace journal with directory");
+
+        let error = store
+            .set(
+                workspace(1),
+                "org.rsynapse.WorkspaceProject".to_owned(),
+                project("/src/new"),
+                HashMap::new(),
+            )
+            .expect_err("persist should fail");
+        assert_ne!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(
+            store.targets(&workspace(1), "org.rsynapse.WorkspaceProject"),
+            vec![project("/src/old")]
+        );
+    }
+
+    #[test]
+    fn failed_clear_persistence_does_not_change_memory() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path.clone()).expect("open store");
+        store
+            .set(
+                workspace(1),
+                "org.rsynapse.WorkspaceProject".to_owned(),
+                project("/src/old"),
+                HashMap::new(),
+            )
+            .expect("initial set");
+
+        let journal = Journal::path_for(&path);
+        fs::remove_file(&journal).expect("remove journal");
+        fs::create_dir(&journal).expect("replace journal with directory");
+
+        store
+            .clear(&workspace(1), "org.rsynapse.WorkspaceProject")
+            .expect_err("persist should fail");
+        assert_eq!(
+            store.targets(&workspace(1), "org.rsynapse.WorkspaceProject"),
+            vec![project("/src/old")]
+        );
+    }
+
+    #[test]
+    fn apply_commits_batch_and_reports_changes_in_order() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path.clone()).expect("open store");
+        store
+            .set(
+                workspace(1),
+                "org.rsynapse.workspace.project".to_owned(),
+                project("/src/old"),
+                HashMap::new(),
+            )
+            .expect("set old");
+
+        let outcome = store
+            .apply(
+                vec![
+                    RelationOperation::set(
+                        project("/src/new"),
+                        "org.rsynapse.project.metadata",
+                        project("/src/new"),
+                        HashMap::from([("name".to_owned(), "new".into())]),
+                    ),
+                    RelationOperation::set_one(
+                        workspace(1),
+                        "org.rsynapse.workspace.project",
+                        project("/src/new"),
+                        HashMap::new(),
+                    ),
+                    RelationOperation::clear(workspace(2), "org.rsynapse.workspace.project"),
+                ],
+                None,
+            )
+            .expect("apply");
+
+        assert_eq!(outcome.records.len(), 2);
+        let kinds = outcome
+            .changes
+            .iter()
+            .map(|change| match change {
+                RelationChange::Added(record) => ("added", record.relation.as_str()),
+                RelationChange::Updated { record, .. } => ("updated", record.relation.as_str()),
+                RelationChange::Removed(record) => ("removed", record.relation.as_str()),
+                RelationChange::Cleared { relation, .. } => ("cleared", relation.as_str()),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                ("added", "org.rsynapse.project.metadata"),
+                ("removed", "org.rsynapse.workspace.project"),
+                ("added", "org.rsynapse.workspace.project"),
+            ]
+        );
+
+        let store = RelationStore::open(path).expect("reload store");
+        assert_eq!(
+            store.targets(&workspace(1), "org.rsynapse.workspace.project"),
+            vec![project("/src/new")]
+        );
+        assert_eq!(store.list("org.rsynapse.project.metadata").len(), 1);
+    }
+
+    #[test]
+    fn apply_clear_reports_removed_records_then_completion() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path).expect("open store");
+        for name in ["one", "two"] {
+            store
+                .set(
+                    workspace(1),
+                    "org.rsynapse.workspace.tag".to_owned(),
+                    icon(name),
+                    HashMap::new(),
+                )
+                .expect("set tag");
+        }
+
+        let outcome = store
+            .apply(
+                vec![RelationOperation::clear(
+                    workspace(1),
+                    "org.rsynapse.workspace.tag",
+                )],
+                None,
+            )
+            .expect("apply clear");
+
+        assert!(outcome.records.is_empty());
+        assert_eq!(outcome.changes.len(), 3);
+        assert!(matches!(
+            outcome.changes.last(),
+            Some(RelationChange::Cleared {
+                removed_count: 2,
+                ..
+            })
+        ));
+        assert!(store.list("org.rsynapse.workspace.tag").is_empty());
+    }
+
+    #[test]
+    fn apply_rejects_whole_batch_on_invalid_operation() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path).expect("open store");
+
+        let error = store
+            .apply(
+                vec![
+                    RelationOperation::set(
+                        workspace(1),
+                        "org.rsynapse.workspace.project",
+                        project("/src/rsynapse"),
+                        HashMap::new(),
+                    ),
+                    RelationOperation::unset(workspace(1), " ", project("/src/rsynapse")),
+                ],
+                None,
+            )
+            .expect_err("blank relation rejected");
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(store.len(), 0);
+    }
+
+    #[test]
+    fn failed_apply_persistence_does_not_change_memory() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path.clone()).expect("open store");
+        store
+            .set(
+                workspace(1),
+                "org.rsynapse.WorkspaceProject".to_owned(),
+                project("/src/old"),
+                HashMap::new(),
+            )
+            .expect("initial set");
+
+        let journal = Journal::path_for(&path);
+        fs::remove_file(&journal).expect("remove journal");
+        fs::create_dir(&journal).expect("replace journal with directory");
+
+        store
+            .apply(
+                vec![
+                    RelationOperation::unset(
+                        workspace(1),
+                        "org.rsynapse.WorkspaceProject",
+                        project("/src/old"),
+                    ),
+                    RelationOperation::set(
+                        workspace(2),
+                        "org.rsynapse.WorkspaceProject",
+                        project("/src/new"),
+                        HashMap::new(),
+                    ),
+                ],
+                None,
+            )
+            .expect_err("persist should fail");
+        assert_eq!(
+            store.targets(&workspace(1), "org.rsynapse.WorkspaceProject"),
+            vec![project("/src/old")]
+        );
+        assert!(
+            store
+                .targets(&workspace(2), "org.rsynapse.WorkspaceProject")
+                .is_empty()
+        );
+    }
+
+    fn workspace_project_schema() -> RelationSchema {
+        RelationSchema::new(
+            "org.rsynapse.workspace.project",
+            RelationCardinality::ManyToOne,
+        )
+        .with_subject_kind(keys::NIRI_WORKSPACE_ID)
+        .with_target_kind(keys::PROJECT_PATH)
+    }
 
     #[test]
-    fn set_query_unset_and_reload() {
+    fn schema_rejects_unexpected_endpoint_kinds() {
         let temp = tempfile::tempdir().expect("tempdir");
         let path = temp.path().join("relations.json");
-        let mut store = RelationStore::open(path.clone()).expect("open store");
+        let mut store = RelationStore::open(path).expect("open store");
+        store
+            .register_schema(workspace_project_schema())
+            .expect("register schema");
+
+        let error = store
+            .set(
+                workspace(1),
+                "org.rsynapse.workspace.project".to_owned(),
+                key(keys::NIRI_WORKSPACE_NAME, "rsynapse"),
+                HashMap::new(),
+            )
+            .expect_err("wrong target kind rejected");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+        let error = store
+            .apply(
+                vec![RelationOperation::set(
+                    window(1),
+                    "org.rsynapse.workspace.project",
+                    project("/src/rsynapse"),
+                    HashMap::new(),
+                )],
+                None,
+            )
+            .expect_err("wrong subject kind rejected in batch");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(store.len(), 0);
+    }
+
+    #[test]
+    fn single_valued_schema_makes_set_replace() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path).expect("open store");
+        store
+            .register_schema(workspace_project_schema())
+            .expect("register schema");
+        store
+            .set(
+                workspace(1),
+                "org.rsynapse.workspace.project".to_owned(),
+                project("/src/old"),
+                HashMap::new(),
+            )
+            .expect("set old");
+
         let outcome = store
             .set(
-                workspace(5),
-                "org.rsynapse.WorkspaceProject".to_owned(),
-                project("rsynapse"),
-                HashMap::from([("source".to_owned(), "test".to_owned())]),
+                workspace(1),
+                "org.rsynapse.workspace.project".to_owned(),
+                project("/src/new"),
+                HashMap::new(),
             )
-            .expect("set");
-        let record = outcome.record;
+            .expect("set new");
 
-        assert!(outcome.created);
-        assert_eq!(record.created_at_unix_ms, record.updated_at_unix_ms);
+        assert_eq!(outcome.removed.len(), 1);
+        assert_eq!(outcome.removed[0].target, project("/src/old"));
         assert_eq!(
-            store.targets(&workspace(5), "org.rsynapse.WorkspaceProject"),
-            vec![project("rsynapse")]
+            store.targets(&workspace(1), "org.rsynapse.workspace.project"),
+            vec![project("/src/new")]
         );
 
-        let store = RelationStore::open(path.clone()).expect("reload store");
-        assert_eq!(store.list("org.rsynapse.WorkspaceProject").len(), 1);
+        store
+            .set(
+                workspace(2),
+                "org.rsynapse.workspace.project".to_owned(),
+                project("/src/new"),
+                HashMap::new(),
+            )
+            .expect("share target");
+        assert_eq!(
+            store.subjects("org.rsynapse.workspace.project", &project("/src/new")),
+            vec![workspace(1), workspace(2)]
+        );
+    }
 
-        let mut store = RelationStore::open(path).expect("reload mutable store");
+    #[test]
+    fn one_to_one_schema_replaces_on_both_sides() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path).expect("open store");
+        let relation = "org.rsynapse.workspace.primary-window";
+        store
+            .register_schema(RelationSchema::new(relation, RelationCardinality::OneToOne))
+            .expect("register schema");
+        store
+            .set(workspace(1), relation.to_owned(), window(7), HashMap::new())
+            .expect("set first");
+
+        let outcome = store
+            .set(workspace(2), relation.to_owned(), window(7), HashMap::new())
+            .expect("move window");
+
+        assert_eq!(outcome.removed.len(), 1);
+        assert_eq!(outcome.removed[0].subject, workspace(1));
+        assert!(store.targets(&workspace(1), relation).is_empty());
+    }
+
+    #[test]
+    fn schemas_survive_reload_and_can_be_unregistered() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path.clone()).expect("open store");
+        store
+            .register_schema(workspace_project_schema())
+            .expect("register schema");
+
+        let mut store = RelationStore::open(path.clone()).expect("reload store");
+        assert_eq!(store.schemas(), vec![workspace_project_schema()]);
         assert!(
             store
-                .unset(
-                    &workspace(5),
-                    "org.rsynapse.WorkspaceProject",
-                    &project("rsynapse"),
-                )
-                .expect("unset")
+                .unregister_schema("org.rsynapse.workspace.project")
+                .expect("unregister schema")
                 .is_some()
         );
+
+        let store = RelationStore::open(path).expect("reload store again");
+        assert!(store.schemas().is_empty());
+    }
+
+    #[test]
+    fn register_schema_rejects_conflicting_existing_records() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path).expect("open store");
+        for name in ["/src/one", "/src/two"] {
+            store
+                .set(
+                    workspace(1),
+                    "org.rsynapse.workspace.project".to_owned(),
+                    project(name),
+                    HashMap::new(),
+                )
+                .expect("set project");
+        }
+
+        let error = store
+            .register_schema(workspace_project_schema())
+            .expect_err("multi-valued records conflict with schema");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(store.schemas().is_empty());
+    }
+
+    #[test]
+    fn owned_records_are_removed_with_their_owner_and_never_persisted() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path.clone()).expect("open store");
+        let outcome = store
+            .apply(
+                vec![
+                    RelationOperation::set(
+                        project("/src/rsynapse"),
+                        "org.rsynapse.project.build-invocation",
+                        key(
+                            keys::BAZEL_INVOCATION_ID,
+                            "3f2a9c4e-8d1b-4a6f-9e2c-7b5d0a1c6e84",
+                        ),
+                        HashMap::new(),
+                    )
+                    .with_options(SetOptions::owned()),
+                    RelationOperation::set(
+                        workspace(1),
+                        "org.rsynapse.workspace.project",
+                        project("/src/rsynapse"),
+                        HashMap::new(),
+                    ),
+                ],
+                Some(":1.42"),
+            )
+            .expect("apply");
+        assert_eq!(outcome.records[0].owner, ":1.42");
+        assert!(outcome.records[1].owner.is_empty());
+
+        let reloaded = RelationStore::open(path.clone()).expect("reload store");
+        assert!(
+            reloaded
+                .list("org.rsynapse.project.build-invocation")
+                .is_empty()
+        );
+
+        assert!(store.remove_owned(":1.7").expect("other owner").is_empty());
+        let removed = store.remove_owned(":1.42").expect("remove owned");
+        assert_eq!(removed.len(), 1);
+        assert_eq!(store.list("").len(), 1);
+    }
+
+    #[test]
+    fn unowned_write_releases_ownership() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path).expect("open store");
+        let operation = RelationOperation::set(
+            window(3),
+            "org.rsynapse.window.agent-session",
+            agent("codex"),
+            HashMap::new(),
+        );
+        store
+            .apply(
+                vec![operation.clone().with_options(SetOptions::owned())],
+                Some(":1.42"),
+            )
+            .expect("owned set");
+        store
+            .apply(vec![operation], Some(":1.42"))
+            .expect("unowned set");
+
         assert!(
             store
-                .targets(&workspace(5), "org.rsynapse.WorkspaceProject")
+                .remove_owned(":1.42")
+                .expect("remove owned")
                 .is_empty()
         );
+        assert_eq!(store.len(), 1);
     }
 
     #[test]
-    fn named_workspace_icon_override_survives_reload() {
+    fn owned_write_requires_caller() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path).expect("open store");
+        let error = store
+            .apply(
+                vec![
+                    RelationOperation::set(
+                        window(3),
+                        "org.rsynapse.window.agent-session",
+                        agent("codex"),
+                        HashMap::new(),
+                    )
+                    .with_options(SetOptions::owned()),
+                ],
+                None,
+            )
+            .expect_err("owned write without caller rejected");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn expiring_records_survive_reload_until_removed() {
         let temp = tempfile::tempdir().expect("tempdir");
         let path = temp.path().join("relations.json");
-        let subject = key(keys::NIRI_WORKSPACE_NAME, "coding");
-        let relation = "org.rsynapse.workspace.icon-override";
-        let target = icon("glyph");
         let mut store = RelationStore::open(path.clone()).expect("open store");
+        let outcome = store
+            .apply(
+                vec![
+                    RelationOperation::set(
+                        project("/src/rsynapse"),
+                        "org.rsynapse.project.last-build",
+                        key(
+                            keys::BAZEL_INVOCATION_ID,
+                            "3f2a9c4e-8d1b-4a6f-9e2c-7b5d0a1c6e84",
+                        ),
+                        HashMap::new(),
+                    )
+                    .with_options(SetOptions::expiring_after(60_000)),
+                ],
+                None,
+            )
+            .expect("apply");
+        let expires_at = outcome.records[0].expires_at_unix_ms;
+        assert!(expires_at >= outcome.records[0].updated_at_unix_ms + 60_000);
+
+        let mut store = RelationStore::open(path.clone()).expect("reload store");
+        assert_eq!(store.next_expiry(), Some(expires_at));
+        assert!(
+            store
+                .remove_expired(expires_at - 1)
+                .expect("nothing expired yet")
+                .is_empty()
+        );
+        assert_eq!(
+            store
+                .remove_expired(expires_at)
+                .expect("remove expired")
+                .len(),
+            1
+        );
+        assert_eq!(store.next_expiry(), None);
+
+        let store = RelationStore::open(path).expect("reload store again");
+        assert_eq!(store.len(), 0);
+    }
 
+    #[test]
+    fn rewriting_without_ttl_clears_expiry() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path).expect("open store");
+        let operation = RelationOperation::set(
+            project("/src/rsynapse"),
+            "org.rsynapse.project.agent-session",
+            agent("codex"),
+            HashMap::new(),
+        );
         store
-            .set_one(
-                subject.clone(),
-                relation.to_owned(),
-                target.clone(),
-                HashMap::from([("pick-icon-input".to_owned(), "rust".to_owned())]),
+            .apply(
+                vec![
+                    operation
+                        .clone()
+                        .with_options(SetOptions::expiring_after(1_000)),
+                ],
+                None,
             )
-            .expect("set icon override");
+            .expect("expiring set");
+        store.apply(vec![operation], None).expect("durable set");
 
-        let store = RelationStore::open(path).expect("reload store");
-        assert_eq!(store.targets(&subject, relation), vec![target]);
+        assert_eq!(store.next_expiry(), None);
     }
 
     #[test]
-    fn set_updates_existing_record_without_duplicating_it() {
+    fn loading_drops_expired_records() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut expired = record(
+            project("/src/rsynapse"),
+            "org.rsynapse.project.last-build",
+            key(
+                keys::BAZEL_INVOCATION_ID,
+                "3f2a9c4e-8d1b-4a6f-9e2c-7b5d0a1c6e84",
+            ),
+        );
+        expired.expires_at_unix_ms = 2;
+        let mut pending = expired.clone();
+        pending.target = key(
+            keys::BAZEL_INVOCATION_ID,
+            "b7e41d02-5c9a-4f3e-8a6d-2e1f9c0b4d57",
+        );
+        pending.expires_at_unix_ms = u64::MAX;
+        fs::write(
+            &path,
+            serde_json::to_vec(&vec![expired, pending]).expect("serialize records"),
+        )
+        .expect("write records");
+
+        let store = RelationStore::open(path.clone()).expect("open store");
+
+        assert_eq!(
+            store.targets(&project("/src/rsynapse"), "org.rsynapse.project.last-build"),
+            vec![key(
+                keys::BAZEL_INVOCATION_ID,
+                "b7e41d02-5c9a-4f3e-8a6d-2e1f9c0b4d57"
+            )]
+        );
+        let persisted: Vec<RelationRecord> =
+            serde_json::from_slice(&fs::read(path).expect("read cleaned persistent store"))
+                .expect("parse cleaned persistent store");
+        assert_eq!(persisted.len(), 1);
+    }
+
+    #[test]
+    fn zero_ttl_is_rejected() {
         let temp = tempfile::tempdir().expect("tempdir");
         let path = temp.path().join("relations.json");
         let mut store = RelationStore::open(path).expect("open store");
-        let first = store
+        let error = store
+            .apply(
+                vec![
+                    RelationOperation::set(
+                        project("/src/rsynapse"),
+                        "org.rsynapse.project.last-build",
+                        key(
+                            keys::BAZEL_INVOCATION_ID,
+                            "3f2a9c4e-8d1b-4a6f-9e2c-7b5d0a1c6e84",
+                        ),
+                        HashMap::new(),
+                    )
+                    .with_options(SetOptions::expiring_after(0)),
+                ],
+                None,
+            )
+            .expect_err("zero ttl rejected");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn removing_referenced_endpoints_drops_both_directions() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path.clone()).expect("open store");
+        let tray = RelationEndpoint::DBusObject {
+            bus: "session".to_string(),
+            service: "org.kde.StatusNotifierItem-1-1".to_string(),
+            path: "/StatusNotifierItem".to_string(),
+            interface: "org.kde.StatusNotifierItem".to_string(),
+        };
+        store
             .set(
-                window(1),
-                "org.rsynapse.WindowAgent".to_owned(),
+                project("/src/rsynapse"),
+                "org.rsynapse.project.tray-item".to_string(),
+                tray.clone(),
+                HashMap::new(),
+            )
+            .expect("set tray target");
+        store
+            .set(
+                tray.clone(),
+                "org.rsynapse.tray.project".to_string(),
+                project("/src/rsynapse"),
+                HashMap::new(),
+            )
+            .expect("set tray subject");
+        store
+            .set(
+                project("/src/rsynapse"),
+                "org.rsynapse.project.agent-session".to_string(),
                 agent("codex"),
-                HashMap::from([("state".to_owned(), "thinking".to_owned())]),
+                HashMap::new(),
             )
-            .expect("first set");
-        let second = store
+            .expect("set unrelated");
+        assert_eq!(
+            store.dbus_services(),
+            BTreeSet::from(["org.kde.StatusNotifierItem-1-1".to_string()])
+        );
+
+        let removed = store
+            .remove_referencing(|endpoint| endpoint == &tray)
+            .expect("remove tray");
+
+        assert_eq!(removed.len(), 2);
+        assert_eq!(store.len(), 1);
+        assert!(store.dbus_services().is_empty());
+        let reopened = RelationStore::open(path).expect("reopen store");
+        assert_eq!(reopened.len(), 1);
+    }
+
+    #[test]
+    fn failed_persist_rolls_back_in_place_edits() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path.clone()).expect("open store");
+        store
+            .set_one(
+                workspace(1),
+                "org.rsynapse.workspace.project".to_string(),
+                project("/src/rsynapse"),
+                HashMap::new(),
+            )
+            .expect("set project");
+        let before = store.list("");
+        let journal = Journal::path_for(&path);
+        fs::remove_file(&journal).expect("remove journal");
+        fs::create_dir(&journal).expect("replace journal with directory");
+
+        store
+            .apply(
+                vec![
+                    RelationOperation::set_one(
+                        workspace(1),
+                        "org.rsynapse.workspace.project",
+                        project("/src/other"),
+                        HashMap::new(),
+                    ),
+                    RelationOperation::set(
+                        workspace(2),
+                        "org.rsynapse.workspace.project",
+                        project("/src/rsynapse"),
+                        HashMap::new(),
+                    ),
+                ],
+                None,
+            )
+            .expect_err("persist fails");
+
+        assert_eq!(store.list(""), before);
+        assert_eq!(
+            store.subjects("org.rsynapse.workspace.project", &project("/src/rsynapse")),
+            vec![workspace(1)]
+        );
+    }
+
+    #[test]
+    fn journal_replays_commits_without_rewriting_snapshot() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path.clone()).expect("open store");
+        store
             .set(
-                window(1),
-                "org.rsynapse.WindowAgent".to_owned(),
+                workspace(1),
+                "org.rsynapse.workspace.project".to_owned(),
+                project("/src/old"),
+                HashMap::new(),
+            )
+            .expect("set project");
+        store
+            .set_one(
+                workspace(1),
+                "org.rsynapse.workspace.project".to_owned(),
+                project("/src/new"),
+                HashMap::new(),
+            )
+            .expect("replace project");
+        store
+            .set(
+                project("/src/new"),
+                "org.rsynapse.project.agent-session".to_owned(),
                 agent("codex"),
-                HashMap::from([("state".to_owned(), "idle".to_owned())]),
+                HashMap::new(),
             )
-            .expect("second set");
+            .expect("set agent");
+        store
+            .unset(
+                &project("/src/new"),
+                "org.rsynapse.project.agent-session",
+                &agent("codex"),
+            )
+            .expect("unset agent");
 
-        assert!(first.created);
-        assert!(!second.created);
-        assert_eq!(store.list("").len(), 1);
-        assert_eq!(
-            second.record.metadata,
-            HashMap::from([("state".to_owned(), "idle".to_owned())])
-        );
+        assert!(!path.exists());
+        let reopened = RelationStore::open(path).expect("reopen store");
+        assert_eq!(reopened.list(""), store.list(""));
         assert_eq!(
-            first.record.created_at_unix_ms,
-            second.record.created_at_unix_ms
+            reopened.targets(&workspace(1), "org.rsynapse.workspace.project"),
+            vec![project("/src/new")]
         );
     }
 
     #[test]
-    fn window_relations_are_memory_only() {
+    fn torn_journal_tail_is_dropped_on_open() {
         let temp = tempfile::tempdir().expect("tempdir");
         let path = temp.path().join("relations.json");
         let mut store = RelationStore::open(path.clone()).expect("open store");
         store
             .set(
-                window(24),
-                "org.rsynapse.window.agent-session".to_owned(),
-                agent("codex/session"),
+                workspace(1),
+                "org.rsynapse.workspace.project".to_owned(),
+                project("/src/rsynapse"),
                 HashMap::new(),
             )
-            .expect("set transient window relation");
+            .expect("set project");
+        let mut journal = fs::OpenOptions::new()
+            .append(true)
+            .open(Journal::path_for(&path))
+            .expect("open journal");
+        io::Write::write_all(&mut journal, br#"{"put":[{"subj"#).expect("tear journal");
+
+        let mut reopened = RelationStore::open(path.clone()).expect("reopen store");
+        reopened
+            .set(
+                workspace(2),
+                "org.rsynapse.workspace.project".to_owned(),
+                project("/src/rsynapse"),
+                HashMap::new(),
+            )
+            .expect("append after torn tail");
 
+        let reopened = RelationStore::open(path).expect("reopen store again");
         assert_eq!(
-            store.targets(&window(24), "org.rsynapse.window.agent-session"),
-            vec![agent("codex/session")]
+            reopened.subjects("org.rsynapse.workspace.project", &project("/src/rsynapse")),
+            vec![workspace(1), workspace(2)]
         );
+    }
 
-        let store = RelationStore::open(path).expect("reload store");
-        assert!(
+    #[test]
+    fn journal_compacts_into_snapshot() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path.clone()).expect("open store");
+        for build in 0..1_000 {
             store
-                .targets(&window(24), "org.rsynapse.window.agent-session")
-                .is_empty()
+                .set_one(
+                    project("/src/rsynapse"),
+                    "org.rsynapse.project.last-build".to_owned(),
+                    key(
+                        keys::BAZEL_INVOCATION_ID,
+                        &format!("00000000-0000-4000-8000-{build:012}"),
+                    ),
+                    HashMap::new(),
+                )
+                .expect("set last build");
+        }
+
+        assert!(!Journal::path_for(&path).exists());
+        let persisted: Vec<RelationRecord> =
+            serde_json::from_slice(&fs::read(&path).expect("read snapshot"))
+                .expect("parse snapshot");
+        assert_eq!(persisted, store.list(""));
+        assert_eq!(
+            persisted[0].target,
+            key(
+                keys::BAZEL_INVOCATION_ID,
+                "00000000-0000-4000-8000-000000000999"
+            )
         );
     }
 
     #[test]
-    fn loading_drops_persisted_window_relations() {
+    fn compaction_keeps_rotating_backups() {
         let temp = tempfile::tempdir().expect("tempdir");
         let path = temp.path().join("relations.json");
-        let records = vec![
-            record(
-                window(24),
-                "org.rsynapse.window.agent-session",
-                agent("codex/session"),
-            ),
-            record(
-                workspace(3),
-                "org.rsynapse.workspace.project",
-                project("rsynapse"),
-            ),
-        ];
-        fs::write(
-            &path,
-            serde_json::to_vec(&records).expect("serialize records"),
-        )
-        .expect("write records");
-
-        let store = RelationStore::open(path.clone()).expect("open store");
-
-        assert!(store.list("org.rsynapse.window.agent-session").is_empty());
-        assert_eq!(store.list("org.rsynapse.workspace.project").len(), 1);
+        let mut store = RelationStore::open(path.clone()).expect("open store");
+        let mut snapshots = Vec::new();
+        for id in 1..=BACKUPS as u64 + 2 {
+            store
+                .set(
+                    workspace(id),
+                    "org.rsynapse.workspace.project".to_owned(),
+                    project("/src/rsynapse"),
+                    HashMap::new(),
+                )
+                .expect("set project");
+            store.compact().expect("compact");
+            snapshots.push(fs::read(&path).expect("read snapshot"));
+        }
 
-        let persisted: Vec<RelationRecord> =
-            serde_json::from_slice(&fs::read(path).expect("read cleaned persistent store"))
-                .expect("parse cleaned persistent store");
-        assert_eq!(persisted.len(), 1);
-        assert_eq!(persisted[0].relation, "org.rsynapse.workspace.project");
+        for generation in 1..=BACKUPS {
+            assert_eq!(
+                fs::read(backup_path(&path, generation)).expect("read backup"),
+                snapshots[snapshots.len() - 1 - generation]
+            );
+        }
+        assert!(!backup_path(&path, BACKUPS + 1).exists());
     }
 
     #[test]
-    fn transient_window_relations_do_not_require_persistence() {
+    fn corrupt_snapshot_is_set_aside_and_restored_from_backup() {
         let temp = tempfile::tempdir().expect("tempdir");
         let path = temp.path().join("relations.json");
         let mut store = RelationStore::open(path.clone()).expect("open store");
-        fs::create_dir(&path).expect("replace store path with directory");
-
-        store
-            .set(
-                window(24),
-                "org.rsynapse.window.agent-session".to_owned(),
-                agent("codex/session"),
-                HashMap::new(),
-            )
-            .expect("set transient window relation");
+        for id in [1, 2] {
+            store
+                .set(
+                    workspace(id),
+                    "org.rsynapse.workspace.project".to_owned(),
+                    project("/src/rsynapse"),
+                    HashMap::new(),
+                )
+                .expect("set project");
+            store.compact().expect("compact");
+        }
+        assert!(store.problems().is_empty());
+        fs::write(&path, b"[{\"subject\":").expect("corrupt snapshot");
 
+        let store = RelationStore::open(path.clone()).expect("recover store");
         assert_eq!(
-            store.targets(&window(24), "org.rsynapse.window.agent-session"),
-            vec![agent("codex/session")]
+            store.subjects("org.rsynapse.workspace.project", &project("/src/rsynapse")),
+            vec![workspace(1)]
+        );
+        let [problem] = store.problems() else {
+            panic!("expected one problem, got {:?}", store.problems());
+        };
+        assert_eq!(problem.path, path.display().to_string());
+        assert_eq!(
+            problem.restored_from,
+            backup_path(&path, 1).display().to_string()
+        );
+        assert_eq!(
+            fs::read(&problem.moved_to).expect("read set-aside snapshot"),
+            b"[{\"subject\":"
         );
         assert!(
+            RelationStore::open(path.clone())
+                .expect("reopen store")
+                .problems()
+                .is_empty()
+        );
+
+        fs::write(&path, b"not json").expect("corrupt snapshot");
+        for generation in 1..=BACKUPS {
+            let _ = fs::write(backup_path(&path, generation), b"not json");
+        }
+        let store = RelationStore::open(path).expect("start empty");
+        assert_eq!(store.len(), 0);
+        assert_eq!(store.problems().len(), 1);
+        assert!(store.problems()[0].restored_from.is_empty());
+    }
+
+    #[test]
+    fn journal_is_set_aside_with_a_corrupt_snapshot() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let path = temp.path().join("relations.json");
+        let mut store = RelationStore::open(path.clone()).expect("open store");
+        for id in [1, 2] {
             store
-                .unset(
-                    &window(24),
-                    "org.rsynapse.window.agent-session",
-                    &agent("codex/session"),
+                .set(
+                    workspace(id),
+                    "org.rsynapse.workspace.project".to_owned(),
+                    project("/src/rsynapse"),
+                    HashMap::new(),
                 )
-                .expect("unset transient window relation")
-                .is_some()
-        );
+                .expect("set project");
+            store.compact().expect("compact");
+        }
         store
             .set(
-                window(24),
-                "org.rsynapse.window.agent-session".to_owned(),
-                agent("codex/session"),
+                workspace(3),
+                "org.rsynapse.workspace.project".to_owned(),
+                project("/src/rsynapse"),
                 HashMap::new(),
             )
-            .expect("set transient window relation again");
+            .expect("journal a write");
+        let journal = fs::read(Journal::path_for(&path)).expect("read journal");
+        fs::write(&path, b"[{\"subject\":").expect("corrupt snapshot");
+
+        let store = RelationStore::open(path.clone()).expect("recover store");
         assert_eq!(
-            store
-                .clear(&window(24), "org.rsynapse.window.agent-session")
-                .expect("clear transient window relation")
-                .len(),
-            1
+            store.subjects("org.rsynapse.workspace.project", &project("/src/rsynapse")),
+            vec![workspace(1)]
+        );
+        let [snapshot, set_aside] = store.problems() else {
+            panic!("expected two problems, got {:?}", store.problems());
+        };
+        assert_eq!(
+            snapshot.restored_from,
+            backup_path(&path, 1).display().to_string()
+        );
+        assert_eq!(
+            set_aside.path,
+            Journal::path_for(&path).display().to_string()
+        );
+        assert_eq!(
+            fs::read(&set_aside.moved_to).expect("read set-aside journal"),
+            journal
         );
     }
 
     #[test]
-    fn set_one_replaces_other_targets_for_subject_relation() {
+    fn corrupt_journal_and_schemas_are_set_aside() {
         let temp = tempfile::tempdir().expect("tempdir");
         let path = temp.path().join("relations.json");
-        let mut store = RelationStore::open(path).expect("open store");
+        let mut store = RelationStore::open(path.clone()).expect("open store");
         store
             .set(
                 workspace(1),
-                "org.rsynapse.WorkspaceProject".to_owned(),
-                project("old"),
-                HashMap::new(),
-            )
-            .expect("set old");
-
-        let outcome = store
-            .set_one(
-                workspace(1),
-                "org.rsynapse.WorkspaceProject".to_owned(),
-                project("new"),
+                "org.rsynapse.workspace.project".to_owned(),
+                project("/src/rsynapse"),
                 HashMap::new(),
             )
-            .expect("replace");
-
-        assert_eq!(outcome.removed.len(), 1);
+            .expect("set project");
+        let mut journal = fs::OpenOptions::new()
+            .append(true)
+            .open(Journal::path_for(&path))
+            .expect("open journal");
+        io::Write::write_all(&mut journal, b"not json\n{}\n").expect("corrupt journal");
+        fs::write(schema_path(&path), b"[").expect("corrupt schemas");
+
+        let store = RelationStore::open(path.clone()).expect("recover store");
         assert_eq!(
-            store.targets(&workspace(1), "org.rsynapse.WorkspaceProject"),
-            vec![project("new")]
+            store.subjects("org.rsynapse.workspace.project", &project("/src/rsynapse")),
+            vec![workspace(1)]
+        );
+        assert!(store.schemas().is_empty());
+        let paths = store
+            .problems()
+            .iter()
+            .map(|problem| problem.path.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            vec![
+                Journal::path_for(&path).display().to_string(),
+                schema_path(&path).display().to_string(),
+            ]
+        );
+        assert!(
+            store
+                .problems()
+                .iter()
+                .all(|problem| Path::new(&problem.moved_to).exists())
         );
     }
 
     #[test]
-    fn relations_are_sorted_and_deduplicated() {
+    fn revisions_increase_and_survive_deletes_and_reloads() {
         let temp = tempfile::tempdir().expect("tempdir");
         let path = temp.path().join("relations.json");
-        let mut store = RelationStore::open(path).expect("open store");
-        for relation in [
-            "org.rsynapse.WindowAgent",
-            "org.rsynapse.WorkspaceProject",
-            "org.rsynapse.WindowAgent",
-        ] {
+        let mut store = RelationStore::open(path.clone()).expect("open store");
+        let relation = "org.rsynapse.workspace.project";
+        assert_eq!(store.revision(&workspace(1), relation, None), 0);
+
+        for name in ["/src/a", "/src/b"] {
             store
                 .set(
-                    key("subject", relation),
+                    workspace(1),
                     relation.to_owned(),
-                    key("target", "one"),
+                    project(name),
                     HashMap::new(),
                 )
-                .expect("set relation");
+                .expect("set project");
         }
-
         assert_eq!(
-            store.relations(),
-            vec!["org.rsynapse.WindowAgent", "org.rsynapse.WorkspaceProject"]
+            store.revision(&workspace(1), relation, Some(&project("/src/a"))),
+            1
+        );
+        assert_eq!(store.revision(&workspace(1), relation, None), 2);
+        store
+            .expect_revision(&workspace(1), relation, Some(&project("/src/a")), 1)
+            .expect("current revision");
+        let mismatch = store
+            .expect_revision(&workspace(1), relation, None, 1)
+            .expect_err("stale revision");
+        assert_eq!(
+            mismatch,
+            RevisionMismatch {
+                expected: 1,
+                actual: 2
+            }
         );
+
+        store
+            .unset(&workspace(1), relation, &project("/src/b"))
+            .expect("unset project");
+        let mut store = RelationStore::open(path.clone()).expect("reload store");
+        let record = store
+            .set(
+                workspace(1),
+                relation.to_owned(),
+                project("/src/a"),
+                HashMap::new(),
+            )
+            .expect("rewrite project")
+            .set
+            .record;
+        assert_eq!(record.revision, 3);
+
+        store.compact().expect("compact");
+        store
+            .unset(&workspace(1), relation, &project("/src/a"))
+            .expect("unset project");
+        store.compact().expect("compact");
+        let store = RelationStore::open(path).expect("reload store");
+        assert_eq!(store.revision, 3);
     }
 
     #[test]
-    fn rejects_blank_references() {
+    fn traverse_follows_forward_and_inverse_steps() {
         let temp = tempfile::tempdir().expect("tempdir");
         let path = temp.path().join("relations.json");
         let mut store = RelationStore::open(path).expect("open store");
-        let error = store
-            .set(
-                key(" ", "subject"),
-                "org.rsynapse.WorkspaceProject".to_owned(),
-                project("rsynapse"),
-                HashMap::new(),
+        let app = key(
+            keys::APP_INSTANCE_ID,
+            "0d9c3b6a-2f4e-4b8d-a1c7-5e6f8a9b0c12",
+        );
+        store
+            .apply(
+                vec![
+                    RelationOperation::set(
+                        window(7),
+                        "org.rsynapse.window.app-instance",
+                        app.clone(),
+                        HashMap::new(),
+                    ),
+                    RelationOperation::set(
+                        app.clone(),
+                        "org.rsynapse.app-instance.project",
+                        project("/src/rsynapse"),
+                        HashMap::new(),
+                    ),
+                    RelationOperation::set(
+                        workspace(1),
+                        "org.rsynapse.workspace.project",
+                        project("/src/rsynapse"),
+                        HashMap::new(),
+                    ),
+                    RelationOperation::set(
+                        workspace(2),
+                        "org.rsynapse.workspace.project",
+                        project("/src/rsynapse"),
+                        HashMap::new(),
+                    ),
+                    RelationOperation::set(
+                        workspace(3),
+                        "org.rsynapse.workspace.project",
+                        project("/src/other"),
+                        HashMap::new(),
+                    ),
+                ],
+                None,
             )
-            .expect_err("blank subject rejected");
+            .expect("populate");
+
+        let traversal = store
+            .traverse(
+                &window(7),
+                &[
+                    TraversalStep::forward("org.rsynapse.window.app-instance"),
+                    TraversalStep::forward("org.rsynapse.app-instance.project"),
+                    TraversalStep::inverse("org.rsynapse.workspace.project"),
+                ],
+            )
+            .expect("traverse");
 
+        assert_eq!(traversal.endpoints, vec![workspace(1), workspace(2)]);
+        assert_eq!(
+            traversal
+                .records
+                .iter()
+                .map(|record| (&record.subject, record.relation.as_str(), &record.target))
+                .collect::<Vec<_>>(),
+            [
+                (&window(7), "org.rsynapse.window.app-instance", &app),
+                (
+                    &app,
+                    "org.rsynapse.app-instance.project",
+                    &project("/src/rsynapse")
+                ),
+                (
+                    &workspace(1),
+                    "org.rsynapse.workspace.project",
+                    &project("/src/rsynapse")
+                ),
+                (
+                    &workspace(2),
+                    "org.rsynapse.workspace.project",
+                    &project("/src/rsynapse")
+                ),
+            ]
+        );
+
+        let empty = store.traverse(&window(7), &[]).expect("empty path");
+        assert_eq!(empty.endpoints, vec![window(7)]);
+        assert!(empty.records.is_empty());
+
+        let error = store
+            .traverse(&window(7), &[TraversalStep::inverse(" ")])
+            .expect_err("blank step rejected");
         assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
     }
 
-    #[test]
-    fn failed_set_persistence_does_not_change_memory() {
+    fn stamped(subject: RelationEndpoint, target: RelationEndpoint, at: u64) -> RelationRecord {
+        let mut record = record(subject, "org.rsynapse.workspace.project", target);
+        record.created_at_unix_ms = at;
+        record.updated_at_unix_ms = at;
+        record
+    }
+
+    fn revised(record: RelationRecord, revision: u64) -> RelationRecord {
+        RelationRecord { revision, ..record }
+    }
+
+    fn import_fixture() -> (tempfile::TempDir, RelationStore) {
         let temp = tempfile::tempdir().expect("tempdir");
         let path = temp.path().join("relations.json");
-        let mut store = RelationStore::open(path.clone()).expect("open store");
+        let mut store = RelationStore::open(path).expect("open store");
         store
-            .set(
-                workspace(1),
-                "org.rsynapse.WorkspaceProject".to_owned(),
-                project("old"),
-                HashMap::new(),
+            .import(
+                vec![
+                    stamped(workspace(1), project("/src/a"), 10),
+                    stamped(workspace(2), project("/src/b"), 10),
+                    record(window(7), "org.rsynapse.window.app-instance", agent("s")),
+                ],
+                ImportMode::MergeKeepExisting,
             )
-            .expect("initial set");
+            .expect("seed store");
+        (temp, store)
+    }
 
-        fs::remove_file(&path).expect("remove persisted file");
-        fs::create_dir(&path).expect("replace store path with directory");
+    #[test]
+    fn import_modes_differ_only_for_existing_records() {
+        let incoming = vec![
+            stamped(workspace(1), project("/src/a"), 20),
+            stamped(workspace(2), project("/src/b"), 5),
+            stamped(workspace(3), project("/src/c"), 1),
+        ];
 
-        let error = store
-            .set(
-                workspace(1),
-                "org.rsynapse.WorkspaceProject".to_owned(),
-                project("new"),
-                HashMap::new(),
-            )
-            .expect_err("persist should fail");
-        assert_ne!(error.kind(), io::ErrorKind::InvalidInput);
+        let (_temp, mut store) = import_fixture();
+        let changes = store
+            .import(incoming.clone(), ImportMode::MergeKeepExisting)
+            .expect("keep existing");
         assert_eq!(
-            store.targets(&workspace(1), "org.rsynapse.WorkspaceProject"),
-            vec![project("old")]
+            changes,
+            [RelationChange::Added(revised(incoming[2].clone(), 4))]
+        );
+
+        let (_temp, mut store) = import_fixture();
+        let changes = store
+            .import(incoming.clone(), ImportMode::MergeNewer)
+            .expect("merge newer");
+        assert_eq!(
+            changes,
+            [
+                RelationChange::Updated {
+                    previous: Box::new(revised(stamped(workspace(1), project("/src/a"), 10), 2)),
+                    record: revised(incoming[0].clone(), 4),
+                },
+                RelationChange::Added(revised(incoming[2].clone(), 5)),
+            ]
+        );
+
+        let (_temp, mut store) = import_fixture();
+        let changes = store
+            .import(incoming[1..].to_vec(), ImportMode::Replace)
+            .expect("replace");
+        assert_eq!(
+            changes,
+            [
+                RelationChange::Removed(revised(stamped(workspace(1), project("/src/a"), 10), 2)),
+                RelationChange::Updated {
+                    previous: Box::new(revised(stamped(workspace(2), project("/src/b"), 10), 3)),
+                    record: revised(incoming[1].clone(), 4),
+                },
+                RelationChange::Added(revised(incoming[2].clone(), 5)),
+            ]
+        );
+        // Live-scoped records are not part of a snapshot and survive a replace.
+        assert_eq!(store.len(), 3);
+        assert_eq!(
+            store.export(),
+            [
+                revised(incoming[1].clone(), 4),
+                revised(incoming[2].clone(), 5)
+            ]
         );
     }
 
     #[test]
-    fn failed_clear_persistence_does_not_change_memory() {
+    fn import_persists_and_keeps_timestamps() {
         let temp = tempfile::tempdir().expect("tempdir");
         let path = temp.path().join("relations.json");
         let mut store = RelationStore::open(path.clone()).expect("open store");
+        let mut expired = stamped(workspace(2), project("/src/b"), 10);
+        expired.expires_at_unix_ms = 1;
         store
-            .set(
-                workspace(1),
-                "org.rsynapse.WorkspaceProject".to_owned(),
-                project("old"),
-                HashMap::new(),
+            .import(
+                vec![stamped(workspace(1), project("/src/a"), 10), expired],
+                ImportMode::Replace,
             )
-            .expect("initial set");
-
-        fs::remove_file(&path).expect("remove persisted file");
-        fs::create_dir(&path).expect("replace store path with directory");
+            .expect("import");
 
-        store
-            .clear(&workspace(1), "org.rsynapse.WorkspaceProject")
-            .expect_err("persist should fail");
+        let store = RelationStore::open(path).expect("reload store");
         assert_eq!(
-            store.targets(&workspace(1), "org.rsynapse.WorkspaceProject"),
-            vec![project("old")]
+            store.export(),
+            [revised(stamped(workspace(1), project("/src/a"), 10), 1)]
         );
     }
+
+    #[test]
+    fn import_rejects_owned_records_and_cardinality_violations() {
+        let (_temp, mut store) = import_fixture();
+        store
+            .register_schema(workspace_project_schema())
+            .expect("register schema");
+        let before = store.list("");
+
+        let mut owned = stamped(workspace(4), project("/src/d"), 1);
+        owned.owner = ":1.7".to_owned();
+        let error = store
+            .import(vec![owned], ImportMode::MergeKeepExisting)
+            .expect_err("owned record rejected");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+        let error = store
+            .import(
+                vec![stamped(workspace(1), project("/src/other"), 1)],
+                ImportMode::MergeKeepExisting,
+            )
+            .expect_err("second project for a workspace rejected");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(store.list(""), before);
+    }
 }
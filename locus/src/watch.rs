@@ -0,0 +1,352 @@
+//! Filtered views of the relation store, exported one object per `Watch`
+//! call and removed when the watching client disconnects.
+
+use std::{collections::HashMap, io, sync::Arc};
+
+use tokio::sync::Mutex;
+use zbus::{interface, object_server::SignalContext, zvariant::OwnedObjectPath};
+
+use locus::{RelationEndpoint, RelationRecord, WATCHES_PATH};
+
+use crate::store::{RelationChange, RelationStore, validate_endpoint, validate_relation};
+
+/// Which records a watch reports. Unset fields match anything.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct WatchFilter {
+    subject: Option<RelationEndpoint>,
+    relation: Option<String>,
+    target: Option<RelationEndpoint>,
+}
+
+impl WatchFilter {
+    /// Builds a filter from `Watch` arguments: zero-or-one subject and target
+    /// arrays, and a relation that matches every relation when empty.
+    pub fn new(
+        subject: Vec<RelationEndpoint>,
+        relation: String,
+        target: Vec<RelationEndpoint>,
+    ) -> io::Result<Self> {
+        let subject = zero_or_one("subject", subject)?;
+        let target = zero_or_one("target", target)?;
+        if let Some(subject) = &subject {
+            validate_endpoint("subject", subject)?;
+        }
+        if let Some(target) = &target {
+            validate_endpoint("target", target)?;
+        }
+        let relation = if relation.is_empty() {
+            None
+        } else {
+            validate_relation(&relation)?;
+            Some(relation)
+        };
+        Ok(Self {
+            subject,
+            relation,
+            target,
+        })
+    }
+
+    pub fn matches(&self, record: &RelationRecord) -> bool {
+        self.subject
+            .as_ref()
+            .is_none_or(|subject| &record.subject == subject)
+            && self
+                .relation
+                .as_ref()
+                .is_none_or(|relation| &record.relation == relation)
+            && self
+                .target
+                .as_ref()
+                .is_none_or(|target| &record.target == target)
+    }
+
+    fn records(&self, store: &RelationStore) -> Vec<RelationRecord> {
+        let mut records = store.list(self.relation.as_deref().unwrap_or_default());
+        records.retain(|record| self.matches(record));
+        records
+    }
+}
+
+fn zero_or_one(
+    name: &str,
+    endpoints: Vec<RelationEndpoint>,
+) -> io::Result<Option<RelationEndpoint>> {
+    if endpoints.len() > 1 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{name} takes zero or one endpoints"),
+        ));
+    }
+    Ok(endpoints.into_iter().next())
+}
+
+/// Open watches by object path.
+#[derive(Debug, Default)]
+pub struct Watches {
+    next_id: u64,
+    entries: HashMap<OwnedObjectPath, Watch>,
+}
+
+#[derive(Debug)]
+struct Watch {
+    /// Unique bus name of the client that opened the watch.
+    owner: String,
+    filter: WatchFilter,
+}
+
+impl Watches {
+    pub fn insert(&mut self, owner: String, filter: WatchFilter) -> OwnedObjectPath {
+        self.next_id += 1;
+        let path = OwnedObjectPath::try_from(format!("{WATCHES_PATH}/{}", self.next_id))
+            .expect("watch paths are valid object paths");
+        self.entries.insert(path.clone(), Watch { owner, filter });
+        path
+    }
+
+    /// Forgets the watch at `path` if `owner` opened it, returning whether it
+    /// did.
+    pub fn remove(&mut self, path: &OwnedObjectPath, owner: &str) -> bool {
+        if self
+            .entries
+            .get(path)
+            .is_none_or(|watch| watch.owner != owner)
+        {
+            return false;
+        }
+        self.entries.remove(path);
+        true
+    }
+
+    /// Forgets every watch opened by `owner`, returning their paths.
+    pub fn remove_owned(&mut self, owner: &str) -> Vec<OwnedObjectPath> {
+        let paths = self
+            .entries
+            .iter()
+            .filter(|(_, watch)| watch.owner == owner)
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+        for path in &paths {
+            self.entries.remove(path);
+        }
+        paths
+    }
+
+    /// The record changes each watch should report, skipping watches with
+    /// none. `Cleared` completions are not forwarded; watches see each
+    /// removed record instead.
+    pub fn matching(
+        &self,
+        changes: &[RelationChange],
+    ) -> Vec<(OwnedObjectPath, Vec<RelationChange>)> {
+        self.entries
+            .iter()
+            .filter_map(|(path, watch)| {
+                let matching = changes
+                    .iter()
+                    .filter(|change| match change {
+                        RelationChange::Added(record)
+                        | RelationChange::Updated { record, .. }
+                        | RelationChange::Removed(record) => watch.filter.matches(record),
+                        RelationChange::Cleared { .. } => false,
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>();
+                (!matching.is_empty()).then(|| (path.clone(), matching))
+            })
+            .collect()
+    }
+}
+
+pub struct RelationWatch {
+    store: Arc<Mutex<RelationStore>>,
+    filter: WatchFilter,
+}
+
+impl RelationWatch {
+    pub fn new(store: Arc<Mutex<RelationStore>>, filter: WatchFilter) -> Self {
+        Self { store, filter }
+    }
+
+    /// Emits `Records` first and then each change, matching the order of the
+    /// relations object.
+    pub async fn emit_changes(
+        &self,
+        ctxt: &SignalContext<'_>,
+        changes: Vec<RelationChange>,
+    ) -> zbus::Result<()> {
+        self.records_changed(ctxt).await?;
+        for change in changes {
+            match change {
+                RelationChange::Added(record) => Self::relation_added(ctxt, record).await?,
+                RelationChange::Updated { record, .. } => {
+                    Self::relation_updated(ctxt, record).await?
+                }
+                RelationChange::Removed(record) => Self::relation_removed(ctxt, record).await?,
+                RelationChange::Cleared { .. } => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[interface(name = "org.rsynapse.Locus.Watch1")]
+impl RelationWatch {
+    #[zbus(property)]
+    async fn records(&self) -> Vec<RelationRecord> {
+        self.filter.records(&*self.store.lock().await)
+    }
+
+    #[zbus(signal)]
+    async fn relation_added(ctxt: &SignalContext<'_>, record: RelationRecord) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn relation_updated(ctxt: &SignalContext<'_>, record: RelationRecord)
+    -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn relation_removed(ctxt: &SignalContext<'_>, record: RelationRecord)
+    -> zbus::Result<()>;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use locus::keys;
+
+    use super::*;
+
+    fn key(kind: &str, id: &str) -> RelationEndpoint {
+        RelationEndpoint::stable_key(kind, id)
+    }
+
+    fn record(
+        subject: RelationEndpoint,
+        relation: &str,
+        target: RelationEndpoint,
+    ) -> RelationRecord {
+        RelationRecord {
+            subject,
+            relation: relation.to_owned(),
+            target,
+            metadata: HashMap::new(),
+            created_at_unix_ms: 1,
+            updated_at_unix_ms: 1,
+            owner: String::new(),
+            expires_at_unix_ms: 0,
+            revision: 0,
+        }
+    }
+
+    #[test]
+    fn filter_matches_only_set_fields() {
+        let window = key(keys::NIRI_WINDOW_ID, "7");
+        let app = key(keys::APP_INSTANCE_ID, "kitty-1");
+        let filter = WatchFilter::new(
+            vec![window.clone()],
+            "org.rsynapse.window.app-instance".to_owned(),
+            Vec::new(),
+        )
+        .expect("filter");
+
+        assert!(filter.matches(&record(
+            window.clone(),
+            "org.rsynapse.window.app-instance",
+            app.clone()
+        )));
+        assert!(!filter.matches(&record(
+            window.clone(),
+            "org.rsynapse.window.agent-session",
+            app.clone()
+        )));
+        assert!(!filter.matches(&record(
+            key(keys::NIRI_WINDOW_ID, "8"),
+            "org.rsynapse.window.app-instance",
+            app.clone()
+        )));
+        assert!(
+            WatchFilter::new(Vec::new(), String::new(), Vec::new())
+                .expect("match-all filter")
+                .matches(&record(window, "any", app))
+        );
+    }
+
+    #[test]
+    fn filter_rejects_several_endpoints() {
+        let error = WatchFilter::new(
+            vec![
+                key(keys::NIRI_WINDOW_ID, "7"),
+                key(keys::NIRI_WINDOW_ID, "8"),
+            ],
+            String::new(),
+            Vec::new(),
+        )
+        .expect_err("two subjects rejected");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn watches_receive_matching_changes_until_their_owner_leaves() {
+        let window = key(keys::NIRI_WINDOW_ID, "7");
+        let mut watches = Watches::default();
+        let windows = watches.insert(
+            ":1.7".to_owned(),
+            WatchFilter::new(vec![window.clone()], String::new(), Vec::new()).expect("filter"),
+        );
+        watches.insert(
+            ":1.8".to_owned(),
+            WatchFilter::new(
+                Vec::new(),
+                "org.rsynapse.workspace.project".to_owned(),
+                Vec::new(),
+            )
+            .expect("filter"),
+        );
+        let added = record(
+            window.clone(),
+            "org.rsynapse.window.app-instance",
+            key(keys::APP_INSTANCE_ID, "kitty-1"),
+        );
+        let changes = vec![
+            RelationChange::Added(added.clone()),
+            RelationChange::Cleared {
+                subject: window,
+                relation: "org.rsynapse.window.app-instance".to_owned(),
+                removed_count: 0,
+            },
+        ];
+
+        assert_eq!(
+            watches.matching(&changes),
+            vec![(windows.clone(), vec![RelationChange::Added(added)])]
+        );
+        assert_eq!(watches.remove_owned(":1.7"), vec![windows]);
+        assert!(watches.matching(&changes).is_empty());
+    }
+
+    #[test]
+    fn only_the_owner_removes_a_watch() {
+        let mut watches = Watches::default();
+        let path = watches.insert(
+            ":1.7".to_owned(),
+            WatchFilter::new(Vec::new(), String::new(), Vec::new()).expect("filter"),
+        );
+
+        assert!(!watches.remove(&path, ":1.8"));
+        assert!(watches.remove(&path, ":1.7"));
+        assert!(!watches.remove(&path, ":1.7"));
+        assert!(watches.remove_owned(":1.7").is_empty());
+    }
+
+    #[test]
+    fn watch_paths_stay_outside_the_managed_subtree() {
+        let path = Watches::default().insert(
+            ":1.7".to_owned(),
+            WatchFilter::new(Vec::new(), String::new(), Vec::new()).expect("filter"),
+        );
+
+        assert_eq!(path.as_str(), "/org/rsynapse/LocusWatches/1");
+        assert!(!path.starts_with(&format!("{}/", locus::OBJECT_PATH)));
+    }
+}
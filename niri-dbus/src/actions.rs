@@ -0,0 +1,287 @@
+use std::path::{Path, PathBuf};
+
+use niri_ipc::{Action, LayoutSwitchTarget, WorkspaceReferenceArg};
+use tracing::debug;
+use zbus::{DBusError, interface};
+
+use crate::{dbus::SharedState, ipc, source::NiriSource, state::NiriState};
+
+/// Errors returned by `org.rsynapse.Niri1.Actions`, named
+/// `org.rsynapse.Niri1.Error.<Variant>` on the bus.
+#[derive(Debug, DBusError)]
+#[zbus(prefix = "org.rsynapse.Niri1.Error")]
+pub enum ActionError {
+    #[zbus(error)]
+    ZBus(zbus::Error),
+    /// The service is not connected to niri or is replaying a recording, or
+    /// the action could not be sent.
+    Unavailable(String),
+    /// No live window has the given id.
+    UnknownWindow(String),
+    /// No live workspace has the given id.
+    UnknownWorkspace(String),
+    /// An argument niri cannot act on, such as a missing layout index.
+    InvalidArgs(String),
+    /// niri received the action and refused it; the message is niri's.
+    Rejected(String),
+}
+
+#[derive(Clone)]
+pub struct ActionsInterface {
+    state: SharedState,
+    /// The event source's socket override, so actions reach the same niri.
+    socket: Option<PathBuf>,
+    /// A replay shows a recorded session, which no action can change.
+    replaying: bool,
+}
+
+impl ActionsInterface {
+    pub fn new(state: SharedState, source: &NiriSource) -> Self {
+        Self {
+            state,
+            socket: source.socket().map(Path::to_owned),
+            replaying: !source.is_live(),
+        }
+    }
+
+    /// Builds the action against the current state, so unknown ids fail with
+    /// a typed error instead of a silent no-op in niri, then sends it.
+    async fn run(
+        &self,
+        build: impl FnOnce(&NiriState) -> Result<Action, ActionError>,
+    ) -> Result<(), ActionError> {
+        if self.replaying {
+            return Err(ActionError::Unavailable("replaying a recording".to_owned()));
+        }
+        let action = {
+            let state = self.state.read().await;
+            if !state.connected {
+                return Err(ActionError::Unavailable("not connected to niri".to_owned()));
+            }
+            build(&state)?
+        };
+        debug!(?action, "sending niri action");
+        match ipc::send_action(self.socket.as_deref(), action).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(message)) => Err(ActionError::Rejected(message)),
+            Err(error) => Err(ActionError::Unavailable(error.to_string())),
+        }
+    }
+}
+
+#[interface(name = "org.rsynapse.Niri1.Actions")]
+impl ActionsInterface {
+    async fn focus_window(&self, id: u64) -> Result<(), ActionError> {
+        self.run(|state| {
+            Ok(Action::FocusWindow {
+                id: known_window(state, id)?,
+            })
+        })
+        .await
+    }
+
+    async fn close_window(&self, id: u64) -> Result<(), ActionError> {
+        self.run(|state| {
+            Ok(Action::CloseWindow {
+                id: Some(known_window(state, id)?),
+            })
+        })
+        .await
+    }
+
+    async fn toggle_window_floating(&self, id: u64) -> Result<(), ActionError> {
+        self.run(|state| {
+            Ok(Action::ToggleWindowFloating {
+                id: Some(known_window(state, id)?),
+            })
+        })
+        .await
+    }
+
+    /// Moves a window to a workspace, following it there when `focus` is
+    /// set.
+    async fn move_window_to_workspace(
+        &self,
+        window_id: u64,
+        workspace_id: u64,
+        focus: bool,
+    ) -> Result<(), ActionError> {
+        self.run(|state| {
+            Ok(Action::MoveWindowToWorkspace {
+                window_id: Some(known_window(state, window_id)?),
+                reference: known_workspace(state, workspace_id)?,
+                focus,
+            })
+        })
+        .await
+    }
+
+    async fn focus_workspace(&self, id: u64) -> Result<(), ActionError> {
+        self.run(|state| {
+            Ok(Action::FocusWorkspace {
+                reference: known_workspace(state, id)?,
+            })
+        })
+        .await
+    }
+
+    async fn set_workspace_name(&self, id: u64, name: String) -> Result<(), ActionError> {
+        self.run(|state| {
+            if name.trim().is_empty() {
+                return Err(ActionError::InvalidArgs(
+                    "workspace name must not be blank".to_owned(),
+                ));
+            }
+            Ok(Action::SetWorkspaceName {
+                name,
+                workspace: Some(known_workspace(state, id)?),
+            })
+        })
+        .await
+    }
+
+    async fn unset_workspace_name(&self, id: u64) -> Result<(), ActionError> {
+        self.run(|state| {
+            Ok(Action::UnsetWorkspaceName {
+                reference: Some(known_workspace(state, id)?),
+            })
+        })
+        .await
+    }
+
+    /// Takes `next`, `prev`, or a layout index from `KeyboardLayouts`.
+    async fn switch_keyboard_layout(&self, target: String) -> Result<(), ActionError> {
+        self.run(|state| {
+            Ok(Action::SwitchLayout {
+                layout: layout_target(state, &target)?,
+            })
+        })
+        .await
+    }
+}
+
+fn known_window(state: &NiriState, id: u64) -> Result<u64, ActionError> {
+    state
+        .window(id)
+        .map(|_| id)
+        .ok_or_else(|| ActionError::UnknownWindow(format!("no window with id {id}")))
+}
+
+fn known_workspace(state: &NiriState, id: u64) -> Result<WorkspaceReferenceArg, ActionError> {
+    state
+        .workspace(id)
+        .map(|_| WorkspaceReferenceArg::Id(id))
+        .ok_or_else(|| ActionError::UnknownWorkspace(format!("no workspace with id {id}")))
+}
+
+fn layout_target(state: &NiriState, target: &str) -> Result<LayoutSwitchTarget, ActionError> {
+    match target {
+        "next" => return Ok(LayoutSwitchTarget::Next),
+        "prev" => return Ok(LayoutSwitchTarget::Prev),
+        _ => {}
+    }
+    let count = state
+        .keyboard_layouts()
+        .map(|layouts| layouts.names.len())
+        .unwrap_or(0);
+    match target.parse::<u8>() {
+        Ok(index) if usize::from(index) < count => Ok(LayoutSwitchTarget::Index(index)),
+        Ok(index) => Err(ActionError::InvalidArgs(format!(
+            "keyboard layout {index} does not exist; niri has {count}"
+        ))),
+        Err(_) => Err(ActionError::InvalidArgs(format!(
+            "keyboard layout target {target:?} must be next, prev, or an index"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, io, sync::Arc};
+
+    use niri_ipc::{Event, KeyboardLayouts, Workspace};
+    use tokio::sync::RwLock;
+
+    use super::*;
+    use crate::source::Replay;
+
+    fn state() -> NiriState {
+        let mut state = NiriState::default();
+        state
+            .apply_event(Event::KeyboardLayoutsChanged {
+                keyboard_layouts: KeyboardLayouts {
+                    names: vec!["English (US)".to_owned(), "Russian".to_owned()],
+                    current_idx: 0,
+                },
+            })
+            .expect("layouts apply");
+        state
+            .apply_event(Event::WorkspacesChanged {
+                workspaces: vec![Workspace {
+                    id: 5,
+                    idx: 1,
+                    name: None,
+                    output: Some("eDP-1".to_owned()),
+                    is_urgent: false,
+                    is_active: true,
+                    is_focused: true,
+                    active_window_id: None,
+                }],
+            })
+            .expect("workspaces apply");
+        state
+    }
+
+    #[test]
+    fn layout_targets_accept_directions_and_existing_indices() {
+        let state = state();
+        assert!(matches!(
+            layout_target(&state, "next"),
+            Ok(LayoutSwitchTarget::Next)
+        ));
+        assert!(matches!(
+            layout_target(&state, "prev"),
+            Ok(LayoutSwitchTarget::Prev)
+        ));
+        assert!(matches!(
+            layout_target(&state, "1"),
+            Ok(LayoutSwitchTarget::Index(1))
+        ));
+        for target in ["2", "-1", "russian", ""] {
+            assert!(matches!(
+                layout_target(&state, target),
+                Err(ActionError::InvalidArgs(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn unknown_ids_are_typed_errors() {
+        let state = state();
+        assert!(matches!(
+            known_workspace(&state, 5),
+            Ok(WorkspaceReferenceArg::Id(5))
+        ));
+        assert!(matches!(
+            known_workspace(&state, 6),
+            Err(ActionError::UnknownWorkspace(_))
+        ));
+        assert!(matches!(
+            known_window(&state, 1),
+            Err(ActionError::UnknownWindow(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn replays_refuse_actions() {
+        let mut state = state();
+        state.mark_connected("niri 26.4".to_owned(), HashMap::new());
+        let source = NiriSource::Replay(Replay::new(io::Cursor::new(""), false));
+        let actions = ActionsInterface::new(Arc::new(RwLock::new(state)), &source);
+
+        assert!(matches!(
+            actions.switch_keyboard_layout("next".to_owned()).await,
+            Err(ActionError::Unavailable(message)) if message == "replaying a recording"
+        ));
+    }
+}
@@ -0,0 +1,559 @@
+//! Typed snapshots of the whole niri projection for Rust clients.
+//!
+//! The service sends optional values as zero-or-one arrays; the models here
+//! read them as `Option<T>`. The projection is small and some properties,
+//! such as window titles, change without a signal, so a view reads every
+//! object again after each burst of signals instead of patching a cache.
+
+use std::{collections::HashMap, time::Duration};
+
+use futures_util::{
+    FutureExt, StreamExt, future,
+    stream::{self, BoxStream},
+};
+use tracing::warn;
+use zbus::{
+    Connection, MatchRule, MessageStream, fdo,
+    message::Type,
+    names::{InterfaceName, OwnedInterfaceName},
+    proxy::CacheProperties,
+    zvariant::{OwnedObjectPath, OwnedValue},
+};
+
+use crate::{
+    ActionsProxy, BUS_NAME, OUTPUT_INTERFACE, ROOT_INTERFACE, ROOT_PATH, RootProxy,
+    WINDOW_INTERFACE, WORKSPACE_INTERFACE,
+};
+
+/// Every object the service exports, in the order of the root's `Outputs`,
+/// `Workspaces`, and `Windows` lists. The default is what a client sees
+/// while the service is not running.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NiriSnapshot {
+    pub root: NiriRoot,
+    pub outputs: Vec<NiriOutput>,
+    pub workspaces: Vec<NiriWorkspace>,
+    pub windows: Vec<NiriWindow>,
+}
+
+impl NiriSnapshot {
+    pub fn output(&self, name: &str) -> Option<&NiriOutput> {
+        self.outputs.iter().find(|output| output.name == name)
+    }
+
+    pub fn workspace(&self, id: u64) -> Option<&NiriWorkspace> {
+        self.workspaces.iter().find(|workspace| workspace.id == id)
+    }
+
+    pub fn window(&self, id: u64) -> Option<&NiriWindow> {
+        self.windows.iter().find(|window| window.id == id)
+    }
+
+    pub fn focused_output(&self) -> Option<&NiriOutput> {
+        let path = self.root.focused_output.as_ref()?;
+        self.outputs.iter().find(|output| &output.path == path)
+    }
+
+    pub fn focused_workspace(&self) -> Option<&NiriWorkspace> {
+        let path = self.root.focused_workspace.as_ref()?;
+        self.workspaces
+            .iter()
+            .find(|workspace| &workspace.path == path)
+    }
+
+    pub fn focused_window(&self) -> Option<&NiriWindow> {
+        let path = self.root.focused_window.as_ref()?;
+        self.windows.iter().find(|window| &window.path == path)
+    }
+
+    /// Live windows, most recently focused first.
+    pub fn windows_by_recent_focus(&self) -> impl Iterator<Item = &NiriWindow> {
+        self.root
+            .windows_by_recent_focus
+            .iter()
+            .filter_map(|path| self.windows.iter().find(|window| &window.path == path))
+    }
+}
+
+/// `org.rsynapse.Niri1` without its object lists, which the snapshot holds
+/// as models.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NiriRoot {
+    pub connected: bool,
+    pub compositor_version: String,
+    pub generation: u64,
+    /// Live windows, most recently focused first.
+    pub windows_by_recent_focus: Vec<OwnedObjectPath>,
+    /// `(window, at_unix_ms)` for each focus change, newest first.
+    pub focus_history: Vec<(OwnedObjectPath, u64)>,
+    pub focused_output: Option<OwnedObjectPath>,
+    pub focused_workspace: Option<OwnedObjectPath>,
+    pub focused_window: Option<OwnedObjectPath>,
+    pub keyboard_layouts: Vec<String>,
+    pub keyboard_layout_index: u8,
+    pub overview_open: bool,
+    pub config_load_failed: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct NiriOutput {
+    pub path: OwnedObjectPath,
+    pub name: String,
+    pub make: String,
+    pub model: String,
+    pub serial: Option<String>,
+    pub focused: bool,
+    pub current_workspace: Option<OwnedObjectPath>,
+    pub workspaces: Vec<OwnedObjectPath>,
+    pub physical_width_mm: Option<u32>,
+    pub physical_height_mm: Option<u32>,
+    pub current_mode_width: Option<u16>,
+    pub current_mode_height: Option<u16>,
+    pub current_mode_refresh_mhz: Option<u32>,
+    pub current_mode_preferred: bool,
+    pub custom_mode: bool,
+    pub logical_x: i32,
+    pub logical_y: i32,
+    pub logical_width: u32,
+    pub logical_height: u32,
+    pub scale: f64,
+    pub transform: String,
+    pub vrr_supported: bool,
+    pub vrr_enabled: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct NiriWorkspace {
+    pub path: OwnedObjectPath,
+    pub id: u64,
+    pub name: Option<String>,
+    pub index: u8,
+    pub output: Option<OwnedObjectPath>,
+    pub active: bool,
+    pub focused: bool,
+    pub urgent: bool,
+    pub active_window: Option<OwnedObjectPath>,
+    pub windows: Vec<OwnedObjectPath>,
+    pub windows_by_recent_focus: Vec<OwnedObjectPath>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct NiriWindow {
+    pub path: OwnedObjectPath,
+    pub id: u64,
+    pub title: Option<String>,
+    pub app_id: Option<String>,
+    pub pid: Option<i32>,
+    pub workspace: Option<OwnedObjectPath>,
+    pub output: Option<OwnedObjectPath>,
+    pub focused: bool,
+    pub floating: bool,
+    pub urgent: bool,
+    pub column_index: Option<u64>,
+    pub row_index: Option<u64>,
+    pub tile_width: f64,
+    pub tile_height: f64,
+    pub tile_x: Option<f64>,
+    pub tile_y: Option<f64>,
+    pub window_width: i32,
+    pub window_height: i32,
+    pub window_offset_x: f64,
+    pub window_offset_y: f64,
+    /// niri's monotonic clock reading when the window last had focus.
+    pub focus_timestamp: Option<Duration>,
+}
+
+/// A connection to the niri projection that reads and follows snapshots.
+#[derive(Clone, Debug)]
+pub struct NiriClient {
+    root: RootProxy<'static>,
+    actions: ActionsProxy<'static>,
+    properties: fdo::PropertiesProxy<'static>,
+    objects: fdo::ObjectManagerProxy<'static>,
+}
+
+impl NiriClient {
+    pub async fn new(connection: &Connection) -> zbus::Result<Self> {
+        Ok(Self {
+            root: RootProxy::builder(connection)
+                .cache_properties(CacheProperties::No)
+                .build()
+                .await?,
+            actions: ActionsProxy::builder(connection)
+                .cache_properties(CacheProperties::No)
+                .build()
+                .await?,
+            properties: fdo::PropertiesProxy::builder(connection)
+                .destination(BUS_NAME)?
+                .path(ROOT_PATH)?
+                .cache_properties(CacheProperties::No)
+                .build()
+                .await?,
+            objects: fdo::ObjectManagerProxy::builder(connection)
+                .destination(BUS_NAME)?
+                .path(ROOT_PATH)?
+                .cache_properties(CacheProperties::No)
+                .build()
+                .await?,
+        })
+    }
+
+    pub async fn session() -> zbus::Result<Self> {
+        Self::new(&Connection::session().await?).await
+    }
+
+    /// The root proxy, for one-off property reads.
+    pub fn root(&self) -> &RootProxy<'static> {
+        &self.root
+    }
+
+    /// The proxy for sending actions to niri.
+    pub fn actions(&self) -> &ActionsProxy<'static> {
+        &self.actions
+    }
+
+    /// Reads the whole projection, or the default snapshot when the service
+    /// is not running.
+    pub async fn snapshot(&self) -> zbus::Result<NiriSnapshot> {
+        let root = self
+            .properties
+            .get_all(Some(InterfaceName::from_static_str_unchecked(ROOT_INTERFACE)).into())
+            .await;
+        let Some(root) = available(root)? else {
+            return Ok(NiriSnapshot::default());
+        };
+        let Some(objects) = available(self.objects.get_managed_objects().await)? else {
+            return Ok(NiriSnapshot::default());
+        };
+        NiriSnapshot::from_properties(root, objects)
+    }
+
+    /// Snapshots of the projection: the current one first, then one after
+    /// every burst of changes that alters it. While the service is not
+    /// running the snapshot is the default; it is read again when the
+    /// service returns. The stream ends when the connection closes.
+    pub async fn watch(&self) -> zbus::Result<BoxStream<'static, NiriSnapshot>> {
+        let changes = self.subscribe().await?;
+        let initial = self.snapshot().await?;
+
+        let updates = stream::unfold(
+            (self.clone(), changes, initial.clone()),
+            |(client, mut changes, mut last)| async move {
+                loop {
+                    changes.next().await?;
+                    // One niri event emits a signal per changed property;
+                    // take the ones already queued so it costs one read.
+                    while let Some(Some(())) = changes.next().now_or_never() {}
+                    match client.snapshot().await {
+                        Ok(snapshot) if snapshot != last => {
+                            last = snapshot.clone();
+                            return Some((snapshot, (client, changes, last)));
+                        }
+                        Ok(_) => {}
+                        Err(error) => warn!("failed to read the niri projection: {error}"),
+                    }
+                }
+            },
+        );
+        Ok(stream::once(future::ready(initial)).chain(updates).boxed())
+    }
+
+    async fn subscribe(&self) -> zbus::Result<BoxStream<'static, ()>> {
+        let connection = self.root.inner().connection();
+        let rule = MatchRule::builder()
+            .msg_type(Type::Signal)
+            .sender(BUS_NAME)?
+            .path_namespace(ROOT_PATH)?
+            .build();
+        let signals = MessageStream::for_match_rule(rule, connection, None)
+            .await?
+            .map(|_| ());
+        // A peer-to-peer connection has no bus to report owner changes.
+        if !connection.is_bus() {
+            return Ok(signals.boxed());
+        }
+        let owner = fdo::DBusProxy::new(connection)
+            .await?
+            .receive_name_owner_changed_with_args(&[(0, BUS_NAME)])
+            .await?
+            .map(|_| ());
+        Ok(stream::select(signals, owner).boxed())
+    }
+}
+
+impl NiriSnapshot {
+    fn from_properties(
+        root: HashMap<String, OwnedValue>,
+        mut objects: fdo::ManagedObjects,
+    ) -> zbus::Result<Self> {
+        let mut root = Properties::new(ROOT_INTERFACE, root);
+        let outputs = root.take("Outputs")?;
+        let workspaces = root.take("Workspaces")?;
+        let windows = root.take("Windows")?;
+        Ok(Self {
+            outputs: models(
+                outputs,
+                &mut objects,
+                OUTPUT_INTERFACE,
+                NiriOutput::from_properties,
+            )?,
+            workspaces: models(
+                workspaces,
+                &mut objects,
+                WORKSPACE_INTERFACE,
+                NiriWorkspace::from_properties,
+            )?,
+            windows: models(
+                windows,
+                &mut objects,
+                WINDOW_INTERFACE,
+                NiriWindow::from_properties,
+            )?,
+            root: NiriRoot::from_properties(root)?,
+        })
+    }
+}
+
+impl NiriRoot {
+    fn from_properties(mut properties: Properties) -> zbus::Result<Self> {
+        Ok(Self {
+            connected: properties.take("Connected")?,
+            compositor_version: properties.take("CompositorVersion")?,
+            generation: properties.take("Generation")?,
+            windows_by_recent_focus: properties.take("WindowsByRecentFocus")?,
+            focus_history: properties.take("FocusHistory")?,
+            focused_output: properties.optional("FocusedOutput")?,
+            focused_workspace: properties.optional("FocusedWorkspace")?,
+            focused_window: properties.optional("FocusedWindow")?,
+            keyboard_layouts: properties.take("KeyboardLayouts")?,
+            keyboard_layout_index: properties.take("KeyboardLayoutIndex")?,
+            overview_open: properties.take("OverviewOpen")?,
+            config_load_failed: properties.take("ConfigLoadFailed")?,
+        })
+    }
+}
+
+impl NiriOutput {
+    fn from_properties(path: OwnedObjectPath, mut properties: Properties) -> zbus::Result<Self> {
+        Ok(Self {
+            path,
+            name: properties.take("Name")?,
+            make: properties.take("Make")?,
+            model: properties.take("Model")?,
+            serial: properties.optional("Serial")?,
+            focused: properties.take("Focused")?,
+            current_workspace: properties.optional("CurrentWorkspace")?,
+            workspaces: properties.take("Workspaces")?,
+            physical_width_mm: properties.optional("PhysicalWidthMm")?,
+            physical_height_mm: properties.optional("PhysicalHeightMm")?,
+            current_mode_width: properties.optional("CurrentModeWidth")?,
+            current_mode_height: properties.optional("CurrentModeHeight")?,
+            current_mode_refresh_mhz: properties.optional("CurrentModeRefreshMhz")?,
+            current_mode_preferred: properties.take("CurrentModePreferred")?,
+            custom_mode: properties.take("CustomMode")?,
+            logical_x: properties.take("LogicalX")?,
+            logical_y: properties.take("LogicalY")?,
+            logical_width: properties.take("LogicalWidth")?,
+            logical_height: properties.take("LogicalHeight")?,
+            scale: properties.take("Scale")?,
+            transform: properties.take("Transform")?,
+            vrr_supported: properties.take("VrrSupported")?,
+            vrr_enabled: properties.take("VrrEnabled")?,
+        })
+    }
+}
+
+impl NiriWorkspace {
+    fn from_properties(path: OwnedObjectPath, mut properties: Properties) -> zbus::Result<Self> {
+        Ok(Self {
+            path,
+            id: properties.take("Id")?,
+            name: properties.optional("Name")?,
+            index: properties.take("Index")?,
+            output: properties.optional("Output")?,
+            active: properties.take("Active")?,
+            focused: properties.take("Focused")?,
+            urgent: properties.take("Urgent")?,
+            active_window: properties.optional("ActiveWindow")?,
+            windows: properties.take("Windows")?,
+            windows_by_recent_focus: properties.take("WindowsByRecentFocus")?,
+        })
+    }
+}
+
+impl NiriWindow {
+    fn from_properties(path: OwnedObjectPath, mut properties: Properties) -> zbus::Result<Self> {
+        let focus_secs = properties.optional("FocusTimestampSecs")?;
+        let focus_nanos = properties.optional("FocusTimestampNanos")?;
+        Ok(Self {
+            path,
+            id: properties.take("Id")?,
+            title: properties.optional("Title")?,
+            app_id: properties.optional("AppId")?,
+            pid: properties.optional("Pid")?,
+            workspace: properties.optional("Workspace")?,
+            output: properties.optional("Output")?,
+            focused: properties.take("Focused")?,
+            floating: properties.take("Floating")?,
+            urgent: properties.take("Urgent")?,
+            column_index: properties.optional("ColumnIndex")?,
+            row_index: properties.optional("RowIndex")?,
+            tile_width: properties.take("TileWidth")?,
+            tile_height: properties.take("TileHeight")?,
+            tile_x: properties.optional("TileX")?,
+            tile_y: properties.optional("TileY")?,
+            window_width: properties.take("WindowWidth")?,
+            window_height: properties.take("WindowHeight")?,
+            window_offset_x: properties.take("WindowOffsetX")?,
+            window_offset_y: properties.take("WindowOffsetY")?,
+            focus_timestamp: focus_secs
+                .zip(focus_nanos)
+                .map(|(secs, nanos)| Duration::new(secs, nanos)),
+        })
+    }
+}
+
+/// One interface's properties, taken out by name as they are converted.
+struct Properties {
+    interface: &'static str,
+    values: HashMap<String, OwnedValue>,
+}
+
+impl Properties {
+    fn new(interface: &'static str, values: HashMap<String, OwnedValue>) -> Self {
+        Self { interface, values }
+    }
+
+    fn take<T>(&mut self, name: &str) -> zbus::Result<T>
+    where
+        T: TryFrom<OwnedValue>,
+        T::Error: Into<zbus::Error>,
+    {
+        let value = self.values.remove(name).ok_or_else(|| {
+            zbus::Error::Failure(format!("{} has no {name} property", self.interface))
+        })?;
+        T::try_from(value).map_err(Into::into)
+    }
+
+    /// Reads a zero-or-one array property.
+    fn optional<T>(&mut self, name: &str) -> zbus::Result<Option<T>>
+    where
+        Vec<T>: TryFrom<OwnedValue>,
+        <Vec<T> as TryFrom<OwnedValue>>::Error: Into<zbus::Error>,
+    {
+        Ok(self.take::<Vec<T>>(name)?.into_iter().next())
+    }
+}
+
+/// Converts the objects in `paths` that have `interface`, in that order.
+fn models<T>(
+    paths: Vec<OwnedObjectPath>,
+    objects: &mut fdo::ManagedObjects,
+    interface: &'static str,
+    convert: fn(OwnedObjectPath, Properties) -> zbus::Result<T>,
+) -> zbus::Result<Vec<T>> {
+    let name = OwnedInterfaceName::from(InterfaceName::from_static_str_unchecked(interface));
+    paths
+        .into_iter()
+        .filter_map(|path| {
+            let values = objects.get_mut(&path)?.remove(&name)?;
+            Some(convert(path, Properties::new(interface, values)))
+        })
+        .collect()
+}
+
+/// Turns a reply from a service that is not running into `None`.
+fn available<T>(reply: fdo::Result<T>) -> zbus::Result<Option<T>> {
+    match reply.map_err(zbus::Error::from) {
+        Ok(value) => Ok(Some(value)),
+        Err(error) if is_unavailable(&error) => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+fn is_unavailable(error: &zbus::Error) -> bool {
+    match error {
+        zbus::Error::MethodError(name, _, _) => {
+            name.as_str() == "org.freedesktop.DBus.Error.ServiceUnknown"
+        }
+        zbus::Error::FDO(error) => {
+            matches!(error.as_ref(), fdo::Error::ServiceUnknown(_))
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zbus::zvariant::Value;
+
+    use super::*;
+
+    fn value<'a>(value: impl Into<Value<'a>>) -> OwnedValue {
+        OwnedValue::try_from(value.into()).expect("plain value")
+    }
+
+    fn window_properties(title: Vec<String>, tile_x: Vec<f64>) -> Properties {
+        let mut values = HashMap::from([
+            ("Id".to_owned(), value(7u64)),
+            ("Title".to_owned(), value(title)),
+            ("AppId".to_owned(), value(vec!["foot".to_owned()])),
+            ("Pid".to_owned(), value(Vec::<i32>::new())),
+            (
+                "Workspace".to_owned(),
+                value(vec![crate::workspace_path(3)]),
+            ),
+            ("Output".to_owned(), value(Vec::<OwnedObjectPath>::new())),
+            ("ColumnIndex".to_owned(), value(vec![2u64])),
+            ("RowIndex".to_owned(), value(vec![1u64])),
+            ("TileX".to_owned(), value(tile_x)),
+            ("TileY".to_owned(), value(Vec::<f64>::new())),
+            ("FocusTimestampSecs".to_owned(), value(vec![4u64])),
+            ("FocusTimestampNanos".to_owned(), value(vec![5u32])),
+        ]);
+        for name in ["Focused", "Floating", "Urgent"] {
+            values.insert(name.to_owned(), value(false));
+        }
+        for name in ["TileWidth", "TileHeight", "WindowOffsetX", "WindowOffsetY"] {
+            values.insert(name.to_owned(), value(1.5));
+        }
+        for name in ["WindowWidth", "WindowHeight"] {
+            values.insert(name.to_owned(), value(100i32));
+        }
+        Properties::new(WINDOW_INTERFACE, values)
+    }
+
+    #[test]
+    fn zero_or_one_arrays_become_options() {
+        let window = NiriWindow::from_properties(
+            crate::window_path(7),
+            window_properties(vec!["shell".to_owned()], vec![12.5]),
+        )
+        .expect("window converts");
+
+        assert_eq!(window.title.as_deref(), Some("shell"));
+        assert_eq!(window.pid, None);
+        assert_eq!(window.workspace, Some(crate::workspace_path(3)));
+        assert_eq!(window.output, None);
+        assert_eq!(window.column_index, Some(2));
+        assert_eq!(window.tile_x, Some(12.5));
+        assert_eq!(window.tile_y, None);
+        assert_eq!(window.focus_timestamp, Some(Duration::new(4, 5)));
+
+        let untitled =
+            NiriWindow::from_properties(crate::window_path(7), window_properties(vec![], vec![]))
+                .expect("window converts");
+        assert_eq!(untitled.title, None);
+        assert_eq!(untitled.tile_x, None);
+    }
+
+    #[test]
+    fn missing_properties_are_errors() {
+        let mut properties = window_properties(vec![], vec![]);
+        properties.values.remove("Floating");
+
+        let error = NiriWindow::from_properties(crate::window_path(7), properties)
+            .expect_err("window without Floating");
+        assert!(error.to_string().contains("Floating"), "{error}");
+    }
+}
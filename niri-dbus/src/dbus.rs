@@ -54,6 +54,16 @@ impl RootInterface {
         self.state.read().await.window_paths()
     }
 
+    #[zbus(property)]
+    async fn windows_by_recent_focus(&self) -> Vec<OwnedObjectPath> {
+        self.state.read().await.recent_window_paths()
+    }
+
+    #[zbus(property)]
+    async fn focus_history(&self) -> Vec<(OwnedObjectPath, u64)> {
+        self.state.read().await.focus_history()
+    }
+
     #[zbus(property)]
     async fn focused_output(&self) -> Vec<OwnedObjectPath> {
         optional_path(self.state.read().await.focused_output_path())
@@ -434,6 +444,16 @@ impl WorkspaceInterface {
     async fn windows(&self) -> Vec<OwnedObjectPath> {
         self.state.read().await.windows_for_workspace(self.id)
     }
+
+    #[zbus(property)]
+    async fn windows_by_recent_focus(&self) -> Vec<OwnedObjectPath> {
+        self.state
+            .read()
+            .await
+            .recent_windows_by_workspace()
+            .remove(&self.id)
+            .unwrap_or_default()
+    }
 }
 
 #[derive(Clone)]
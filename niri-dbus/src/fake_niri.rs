@@ -0,0 +1,299 @@
+//! A stand-in for niri's IPC socket, so tests can run the projection without
+//! a compositor, plus fixtures for the niri types they push through it.
+
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use niri_ipc::{
+    Action, Event, LogicalOutput, Mode, Output, Reply, Request, Response, Timestamp, Transform,
+    Window, WindowLayout, Workspace,
+};
+use serde::Serialize;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::{
+        broadcast::{self, error::RecvError},
+        watch,
+    },
+    task::JoinHandle,
+};
+
+/// Listens on a fresh socket path and answers `Version`, `Outputs`, and
+/// `EventStream` requests. Events pushed by the test go to every open event
+/// stream. `Action` requests are recorded and handled, or rejected once the
+/// test asks for that.
+pub struct FakeNiri {
+    path: PathBuf,
+    control: broadcast::Sender<Control>,
+    streams: watch::Receiver<usize>,
+    actions: Arc<Mutex<Actions>>,
+    server: JoinHandle<()>,
+}
+
+#[derive(Default)]
+struct Actions {
+    received: Vec<Action>,
+    /// niri's message for refusing every action from now on.
+    rejection: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+enum Control {
+    Event(Box<Event>),
+    Disconnect,
+}
+
+struct Snapshot {
+    version: String,
+    outputs: HashMap<String, Output>,
+}
+
+impl FakeNiri {
+    pub fn start(version: &str, outputs: impl IntoIterator<Item = Output>) -> io::Result<Self> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "niri-dbus-fake-{}-{}.sock",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)?;
+        let snapshot = Arc::new(Snapshot {
+            version: version.to_owned(),
+            outputs: outputs
+                .into_iter()
+                .map(|output| (output.name.clone(), output))
+                .collect(),
+        });
+        let (control, _) = broadcast::channel(64);
+        let (opened, streams) = watch::channel(0);
+        let actions = Arc::default();
+        let server = tokio::spawn(serve(
+            listener,
+            snapshot,
+            control.clone(),
+            opened,
+            Arc::clone(&actions),
+        ));
+        Ok(Self {
+            path,
+            control,
+            streams,
+            actions,
+            server,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Sends an event to every open event stream.
+    pub fn push(&self, event: Event) {
+        let _ = self.control.send(Control::Event(Box::new(event)));
+    }
+
+    /// Closes every open event stream, as niri does when it exits.
+    pub fn disconnect(&self) {
+        let _ = self.control.send(Control::Disconnect);
+    }
+
+    /// Every action received so far, rejected ones included.
+    pub fn actions(&self) -> Vec<Action> {
+        self.actions
+            .lock()
+            .expect("fake niri actions")
+            .received
+            .clone()
+    }
+
+    /// Refuses later actions with `message`, as niri does with ones it
+    /// cannot perform.
+    pub fn reject_actions(&self, message: &str) {
+        self.actions.lock().expect("fake niri actions").rejection = Some(message.to_owned());
+    }
+
+    /// Waits until `count` event streams have been opened in total, so
+    /// pushed events have a reader.
+    pub async fn wait_for_streams(&self, count: usize) {
+        let mut streams = self.streams.clone();
+        streams
+            .wait_for(|opened| *opened >= count)
+            .await
+            .expect("fake niri server stopped");
+    }
+}
+
+impl Drop for FakeNiri {
+    fn drop(&mut self) {
+        self.server.abort();
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+async fn serve(
+    listener: UnixListener,
+    snapshot: Arc<Snapshot>,
+    control: broadcast::Sender<Control>,
+    opened: watch::Sender<usize>,
+    actions: Arc<Mutex<Actions>>,
+) {
+    let opened = Arc::new(opened);
+    while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(answer(
+            stream,
+            snapshot.clone(),
+            control.clone(),
+            opened.clone(),
+            actions.clone(),
+        ));
+    }
+}
+
+async fn answer(
+    stream: UnixStream,
+    snapshot: Arc<Snapshot>,
+    control: broadcast::Sender<Control>,
+    opened: Arc<watch::Sender<usize>>,
+    actions: Arc<Mutex<Actions>>,
+) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        let request: Request = serde_json::from_str(&line)?;
+        let reply: Reply = match request {
+            Request::Version => Ok(Response::Version(snapshot.version.clone())),
+            Request::Outputs => Ok(Response::Outputs(snapshot.outputs.clone())),
+            Request::EventStream => {
+                // Subscribe before replying so no event pushed after the
+                // client sees the reply is lost.
+                let events = control.subscribe();
+                let handled: Reply = Ok(Response::Handled);
+                write_line(&mut writer, &handled).await?;
+                opened.send_modify(|opened| *opened += 1);
+                return stream_events(writer, events).await;
+            }
+            Request::Action(action) => {
+                let mut actions = actions.lock().expect("fake niri actions");
+                actions.received.push(action);
+                match &actions.rejection {
+                    Some(message) => Err(message.clone()),
+                    None => Ok(Response::Handled),
+                }
+            }
+            request => Err(format!("fake niri does not answer {request:?}")),
+        };
+        write_line(&mut writer, &reply).await?;
+    }
+    Ok(())
+}
+
+async fn stream_events(
+    mut writer: impl AsyncWrite + Unpin,
+    mut events: broadcast::Receiver<Control>,
+) -> io::Result<()> {
+    loop {
+        match events.recv().await {
+            Ok(Control::Event(event)) => write_line(&mut writer, &event).await?,
+            Ok(Control::Disconnect) | Err(RecvError::Closed) => return Ok(()),
+            Err(RecvError::Lagged(_)) => {
+                return Err(io::Error::other("fake niri event stream lagged"));
+            }
+        }
+    }
+}
+
+async fn write_line(
+    writer: &mut (impl AsyncWrite + Unpin),
+    value: &impl Serialize,
+) -> io::Result<()> {
+    let mut line = serde_json::to_string(value)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await?;
+    writer.flush().await
+}
+
+pub fn output(name: &str) -> Output {
+    Output {
+        name: name.to_owned(),
+        make: "Acme".to_owned(),
+        model: "Panel".to_owned(),
+        serial: Some("serial".to_owned()),
+        physical_size: Some((300, 200)),
+        modes: vec![Mode {
+            width: 1920,
+            height: 1080,
+            refresh_rate: 60_000,
+            is_preferred: true,
+        }],
+        current_mode: Some(0),
+        is_custom_mode: false,
+        vrr_supported: true,
+        vrr_enabled: false,
+        logical: Some(LogicalOutput {
+            x: 0,
+            y: 0,
+            width: 1920,
+            height: 1080,
+            scale: 1.0,
+            transform: Transform::Normal,
+        }),
+    }
+}
+
+pub fn workspace(
+    id: u64,
+    idx: u8,
+    output: &str,
+    is_active: bool,
+    is_focused: bool,
+    active_window_id: Option<u64>,
+) -> Workspace {
+    Workspace {
+        id,
+        idx,
+        name: None,
+        output: Some(output.to_owned()),
+        is_urgent: false,
+        is_active,
+        is_focused,
+        active_window_id,
+    }
+}
+
+pub fn window(
+    id: u64,
+    workspace_id: Option<u64>,
+    pos_in_scrolling_layout: Option<(usize, usize)>,
+    is_focused: bool,
+) -> Window {
+    Window {
+        id,
+        title: Some(format!("window {id}")),
+        app_id: Some("test-app".to_owned()),
+        pid: Some(1000 + id as i32),
+        workspace_id,
+        is_focused,
+        is_floating: false,
+        is_urgent: false,
+        layout: WindowLayout {
+            pos_in_scrolling_layout,
+            tile_size: (800.0, 600.0),
+            window_size: (780, 580),
+            tile_pos_in_workspace_view: Some((10.0, 20.0)),
+            window_offset_in_tile: (5.0, 6.0),
+        },
+        focus_timestamp: Some(Timestamp {
+            secs: 1,
+            nanos: id as u32,
+        }),
+    }
+}
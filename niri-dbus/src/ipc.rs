@@ -1,6 +1,9 @@
-use std::{env, io};
+use std::{
+    env, io,
+    path::{Path, PathBuf},
+};
 
-use niri_ipc::{Event, Reply, Request, Response, socket::SOCKET_PATH_ENV};
+use niri_ipc::{Action, Event, Reply, Request, Response, socket::SOCKET_PATH_ENV};
 use tokio::{
     io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
     net::UnixStream,
@@ -12,12 +15,10 @@ pub struct AsyncNiriSocket {
 
 impl AsyncNiriSocket {
     pub async fn connect() -> io::Result<Self> {
-        let socket_path = env::var_os(SOCKET_PATH_ENV).ok_or_else(|| {
-            io::Error::new(
-                io::ErrorKind::NotFound,
-                format!("{SOCKET_PATH_ENV} is not set, are you running this within niri?"),
-            )
-        })?;
+        Self::connect_to(&socket_path()?).await
+    }
+
+    pub async fn connect_to(socket_path: &Path) -> io::Result<Self> {
         let stream = UnixStream::connect(socket_path).await?;
         Ok(Self {
             stream: BufReader::new(stream),
@@ -70,9 +71,22 @@ impl AsyncNiriSocket {
     }
 }
 
-pub async fn initial_snapshot()
--> anyhow::Result<(String, std::collections::HashMap<String, niri_ipc::Output>)> {
-    let mut socket = AsyncNiriSocket::connect().await?;
+/// The socket named by `NIRI_SOCKET`.
+pub fn socket_path() -> io::Result<PathBuf> {
+    env::var_os(SOCKET_PATH_ENV)
+        .map(PathBuf::from)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{SOCKET_PATH_ENV} is not set, are you running this within niri?"),
+            )
+        })
+}
+
+pub async fn initial_snapshot(
+    socket_path: &Path,
+) -> anyhow::Result<(String, std::collections::HashMap<String, niri_ipc::Output>)> {
+    let mut socket = AsyncNiriSocket::connect_to(socket_path).await?;
     let version = match socket.send(Request::Version).await? {
         Response::Version(version) => version,
         response => anyhow::bail!("unexpected niri version response: {response:?}"),
@@ -84,6 +98,30 @@ pub async fn initial_snapshot()
     Ok((version, outputs))
 }
 
-pub async fn event_stream() -> anyhow::Result<AsyncNiriSocket> {
-    AsyncNiriSocket::connect().await?.start_event_stream().await
+pub async fn event_stream(socket_path: &Path) -> anyhow::Result<AsyncNiriSocket> {
+    AsyncNiriSocket::connect_to(socket_path)
+        .await?
+        .start_event_stream()
+        .await
+}
+
+/// Runs one action on its own connection, like `niri msg action`, at
+/// `socket_path` or else at `NIRI_SOCKET`. The outer error means niri could
+/// not be reached; the inner one is niri's rejection.
+pub async fn send_action(
+    socket_path: Option<&Path>,
+    action: Action,
+) -> io::Result<Result<(), String>> {
+    let mut socket = match socket_path {
+        Some(path) => AsyncNiriSocket::connect_to(path).await?,
+        None => AsyncNiriSocket::connect().await?,
+    };
+    match socket.send_raw(Request::Action(action)).await? {
+        Ok(Response::Handled) => Ok(Ok(())),
+        Ok(response) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unexpected niri action response: {response:?}"),
+        )),
+        Err(message) => Ok(Err(message)),
+    }
 }
@@ -1,8 +1,13 @@
+use zbus::{proxy, zvariant::OwnedObjectPath};
+
+pub mod client;
 pub mod paths;
 
+pub use client::{NiriClient, NiriOutput, NiriRoot, NiriSnapshot, NiriWindow, NiriWorkspace};
 pub use paths::{
-    BUS_NAME, OUTPUT_INTERFACE, ROOT_INTERFACE, ROOT_PATH, WINDOW_INTERFACE, WORKSPACE_INTERFACE,
-    output_path, window_path, workspace_path,
+    ACTIONS_INTERFACE, BUS_NAME, ERROR_PREFIX, OUTPUT_INTERFACE, ROOT_INTERFACE, ROOT_PATH,
+    WINDOW_INTERFACE, WORKSPACE_INTERFACE, output_path, window_id, window_path, workspace_id,
+    workspace_path,
 };
 
 pub mod keys {
@@ -11,3 +16,263 @@ pub mod keys {
     pub const WORKSPACE_NAME: &str = "org.rsynapse.niri.workspace.name";
     pub const WINDOW_ID: &str = "org.rsynapse.niri.window.id";
 }
+
+// The proxies mirror the wire: optional values are zero-or-one arrays. The
+// models in `client` read the same properties as `Option<T>`.
+
+#[proxy(
+    interface = "org.rsynapse.Niri1",
+    default_service = "org.rsynapse.Niri",
+    default_path = "/org/rsynapse/Niri"
+)]
+pub trait Root {
+    #[zbus(property)]
+    fn connected(&self) -> zbus::Result<bool>;
+
+    #[zbus(property)]
+    fn compositor_version(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn generation(&self) -> zbus::Result<u64>;
+
+    #[zbus(property)]
+    fn outputs(&self) -> zbus::Result<Vec<OwnedObjectPath>>;
+
+    #[zbus(property)]
+    fn workspaces(&self) -> zbus::Result<Vec<OwnedObjectPath>>;
+
+    #[zbus(property)]
+    fn windows(&self) -> zbus::Result<Vec<OwnedObjectPath>>;
+
+    #[zbus(property)]
+    fn windows_by_recent_focus(&self) -> zbus::Result<Vec<OwnedObjectPath>>;
+
+    #[zbus(property)]
+    fn focus_history(&self) -> zbus::Result<Vec<(OwnedObjectPath, u64)>>;
+
+    #[zbus(property)]
+    fn focused_output(&self) -> zbus::Result<Vec<OwnedObjectPath>>;
+
+    #[zbus(property)]
+    fn focused_workspace(&self) -> zbus::Result<Vec<OwnedObjectPath>>;
+
+    #[zbus(property)]
+    fn focused_window(&self) -> zbus::Result<Vec<OwnedObjectPath>>;
+
+    #[zbus(property)]
+    fn keyboard_layouts(&self) -> zbus::Result<Vec<String>>;
+
+    #[zbus(property)]
+    fn keyboard_layout_index(&self) -> zbus::Result<u8>;
+
+    #[zbus(property)]
+    fn overview_open(&self) -> zbus::Result<bool>;
+
+    #[zbus(property)]
+    fn config_load_failed(&self) -> zbus::Result<bool>;
+}
+
+#[proxy(
+    interface = "org.rsynapse.Niri1.Actions",
+    default_service = "org.rsynapse.Niri",
+    default_path = "/org/rsynapse/Niri"
+)]
+pub trait Actions {
+    fn focus_window(&self, id: u64) -> zbus::Result<()>;
+
+    fn close_window(&self, id: u64) -> zbus::Result<()>;
+
+    fn toggle_window_floating(&self, id: u64) -> zbus::Result<()>;
+
+    fn move_window_to_workspace(
+        &self,
+        window_id: u64,
+        workspace_id: u64,
+        focus: bool,
+    ) -> zbus::Result<()>;
+
+    fn focus_workspace(&self, id: u64) -> zbus::Result<()>;
+
+    fn set_workspace_name(&self, id: u64, name: &str) -> zbus::Result<()>;
+
+    fn unset_workspace_name(&self, id: u64) -> zbus::Result<()>;
+
+    fn switch_keyboard_layout(&self, target: &str) -> zbus::Result<()>;
+}
+
+#[proxy(
+    interface = "org.rsynapse.Niri1.Output",
+    default_service = "org.rsynapse.Niri"
+)]
+pub trait Output {
+    #[zbus(property)]
+    fn name(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn make(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn model(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn serial(&self) -> zbus::Result<Vec<String>>;
+
+    #[zbus(property)]
+    fn focused(&self) -> zbus::Result<bool>;
+
+    #[zbus(property)]
+    fn current_workspace(&self) -> zbus::Result<Vec<OwnedObjectPath>>;
+
+    #[zbus(property)]
+    fn workspaces(&self) -> zbus::Result<Vec<OwnedObjectPath>>;
+
+    #[zbus(property)]
+    fn physical_width_mm(&self) -> zbus::Result<Vec<u32>>;
+
+    #[zbus(property)]
+    fn physical_height_mm(&self) -> zbus::Result<Vec<u32>>;
+
+    #[zbus(property)]
+    fn current_mode_width(&self) -> zbus::Result<Vec<u16>>;
+
+    #[zbus(property)]
+    fn current_mode_height(&self) -> zbus::Result<Vec<u16>>;
+
+    #[zbus(property)]
+    fn current_mode_refresh_mhz(&self) -> zbus::Result<Vec<u32>>;
+
+    #[zbus(property)]
+    fn current_mode_preferred(&self) -> zbus::Result<bool>;
+
+    #[zbus(property)]
+    fn custom_mode(&self) -> zbus::Result<bool>;
+
+    #[zbus(property)]
+    fn logical_x(&self) -> zbus::Result<i32>;
+
+    #[zbus(property)]
+    fn logical_y(&self) -> zbus::Result<i32>;
+
+    #[zbus(property)]
+    fn logical_width(&self) -> zbus::Result<u32>;
+
+    #[zbus(property)]
+    fn logical_height(&self) -> zbus::Result<u32>;
+
+    #[zbus(property)]
+    fn scale(&self) -> zbus::Result<f64>;
+
+    #[zbus(property)]
+    fn transform(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn vrr_supported(&self) -> zbus::Result<bool>;
+
+    #[zbus(property)]
+    fn vrr_enabled(&self) -> zbus::Result<bool>;
+}
+
+#[proxy(
+    interface = "org.rsynapse.Niri1.Workspace",
+    default_service = "org.rsynapse.Niri"
+)]
+pub trait Workspace {
+    #[zbus(property)]
+    fn id(&self) -> zbus::Result<u64>;
+
+    #[zbus(property)]
+    fn name(&self) -> zbus::Result<Vec<String>>;
+
+    #[zbus(property)]
+    fn index(&self) -> zbus::Result<u8>;
+
+    #[zbus(property)]
+    fn output(&self) -> zbus::Result<Vec<OwnedObjectPath>>;
+
+    #[zbus(property)]
+    fn active(&self) -> zbus::Result<bool>;
+
+    #[zbus(property)]
+    fn focused(&self) -> zbus::Result<bool>;
+
+    #[zbus(property)]
+    fn urgent(&self) -> zbus::Result<bool>;
+
+    #[zbus(property)]
+    fn active_window(&self) -> zbus::Result<Vec<OwnedObjectPath>>;
+
+    #[zbus(property)]
+    fn windows(&self) -> zbus::Result<Vec<OwnedObjectPath>>;
+
+    #[zbus(property)]
+    fn windows_by_recent_focus(&self) -> zbus::Result<Vec<OwnedObjectPath>>;
+}
+
+#[proxy(
+    interface = "org.rsynapse.Niri1.Window",
+    default_service = "org.rsynapse.Niri"
+)]
+pub trait Window {
+    #[zbus(property)]
+    fn id(&self) -> zbus::Result<u64>;
+
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn title(&self) -> zbus::Result<Vec<String>>;
+
+    #[zbus(property)]
+    fn app_id(&self) -> zbus::Result<Vec<String>>;
+
+    #[zbus(property)]
+    fn pid(&self) -> zbus::Result<Vec<i32>>;
+
+    #[zbus(property)]
+    fn workspace(&self) -> zbus::Result<Vec<OwnedObjectPath>>;
+
+    #[zbus(property)]
+    fn output(&self) -> zbus::Result<Vec<OwnedObjectPath>>;
+
+    #[zbus(property)]
+    fn focused(&self) -> zbus::Result<bool>;
+
+    #[zbus(property)]
+    fn floating(&self) -> zbus::Result<bool>;
+
+    #[zbus(property)]
+    fn urgent(&self) -> zbus::Result<bool>;
+
+    #[zbus(property)]
+    fn column_index(&self) -> zbus::Result<Vec<u64>>;
+
+    #[zbus(property)]
+    fn row_index(&self) -> zbus::Result<Vec<u64>>;
+
+    #[zbus(property)]
+    fn tile_width(&self) -> zbus::Result<f64>;
+
+    #[zbus(property)]
+    fn tile_height(&self) -> zbus::Result<f64>;
+
+    #[zbus(property)]
+    fn tile_x(&self) -> zbus::Result<Vec<f64>>;
+
+    #[zbus(property)]
+    fn tile_y(&self) -> zbus::Result<Vec<f64>>;
+
+    #[zbus(property)]
+    fn window_width(&self) -> zbus::Result<i32>;
+
+    #[zbus(property)]
+    fn window_height(&self) -> zbus::Result<i32>;
+
+    #[zbus(property)]
+    fn window_offset_x(&self) -> zbus::Result<f64>;
+
+    #[zbus(property)]
+    fn window_offset_y(&self) -> zbus::Result<f64>;
+
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn focus_timestamp_secs(&self) -> zbus::Result<Vec<u64>>;
+
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn focus_timestamp_nanos(&self) -> zbus::Result<Vec<u32>>;
+}
@@ -1,6 +1,10 @@
+mod actions;
 mod dbus;
+#[cfg(test)]
+mod fake_niri;
 mod ipc;
 mod service;
+mod source;
 mod state;
 
 #[tokio::main]
@@ -12,5 +16,6 @@ async fn main() -> anyhow::Result<()> {
         )
         .init();
 
-    service::run().await
+    let source = source::NiriSource::from_args(std::env::args_os().skip(1))?;
+    service::run(source).await
 }
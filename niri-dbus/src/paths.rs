@@ -12,6 +12,10 @@ pub const OUTPUT_INTERFACE: &str = "org.rsynapse.Niri1.Output";
 pub const WORKSPACE_INTERFACE: &str = "org.rsynapse.Niri1.Workspace";
 /// Window object interface.
 pub const WINDOW_INTERFACE: &str = "org.rsynapse.Niri1.Window";
+/// Root-object interface that forwards actions to niri.
+pub const ACTIONS_INTERFACE: &str = "org.rsynapse.Niri1.Actions";
+/// Prefix of the typed errors returned by action methods.
+pub const ERROR_PREFIX: &str = "org.rsynapse.Niri1.Error";
 
 /// Live D-Bus object path for an output name.
 ///
@@ -26,7 +30,12 @@ pub fn output_path(name: &str) -> OwnedObjectPath {
 /// Workspace ids are stable while the workspace exists, but this object path is
 /// still a live service address rather than a cross-session durable identity.
 pub fn workspace_path(id: u64) -> OwnedObjectPath {
-    object_path(format!("{ROOT_PATH}/Workspaces/workspace_{id}"))
+    object_path(format!("{WORKSPACE_PATH_PREFIX}{id}"))
+}
+
+/// The workspace id behind a [`workspace_path`], if `path` is one.
+pub fn workspace_id(path: &str) -> Option<u64> {
+    path.strip_prefix(WORKSPACE_PATH_PREFIX)?.parse().ok()
 }
 
 /// Live D-Bus object path for a niri window id.
@@ -34,9 +43,17 @@ pub fn workspace_path(id: u64) -> OwnedObjectPath {
 /// Window ids are live-window scoped and must not be used as durable identity
 /// after the window closes.
 pub fn window_path(id: u64) -> OwnedObjectPath {
-    object_path(format!("{ROOT_PATH}/Windows/window_{id}"))
+    object_path(format!("{WINDOW_PATH_PREFIX}{id}"))
 }
 
+/// The window id behind a [`window_path`], if `path` is one.
+pub fn window_id(path: &str) -> Option<u64> {
+    path.strip_prefix(WINDOW_PATH_PREFIX)?.parse().ok()
+}
+
+const WORKSPACE_PATH_PREFIX: &str = "/org/rsynapse/Niri/Workspaces/workspace_";
+const WINDOW_PATH_PREFIX: &str = "/org/rsynapse/Niri/Windows/window_";
+
 fn encode_segment(input: &str) -> String {
     let mut output = String::from("x");
     for byte in input.bytes() {
@@ -84,4 +101,15 @@ mod tests {
             "/org/rsynapse/Niri/Outputs/x6544502D31"
         );
     }
+
+    #[test]
+    fn ids_round_trip_through_object_paths() {
+        assert_eq!(workspace_id(workspace_path(42).as_str()), Some(42));
+        assert_eq!(window_id(window_path(7).as_str()), Some(7));
+        assert_eq!(window_id(workspace_path(7).as_str()), None);
+        assert_eq!(
+            workspace_id("/org/rsynapse/Niri/Workspaces/workspace_x"),
+            None
+        );
+    }
 }
@@ -12,28 +12,21 @@ use zbus::{Connection, connection::Builder, zvariant::OwnedObjectPath};
 use niri_dbus::paths;
 
 use crate::{
+    actions::ActionsInterface,
     dbus::{OutputInterface, RootInterface, SharedState, WindowInterface, WorkspaceInterface},
-    ipc,
+    source::{NiriSource, ReplayFinished},
     state::{NiriState, ObjectDelta},
 };
 
-pub async fn run() -> anyhow::Result<()> {
+pub async fn run(source: NiriSource) -> anyhow::Result<()> {
     let state: SharedState = Arc::new(RwLock::new(NiriState::default()));
-    let connection = Builder::session()?
-        .serve_at(paths::ROOT_PATH, RootInterface::new(state.clone()))?
-        .serve_at(paths::ROOT_PATH, zbus::fdo::ObjectManager)?
+    let connection = serve_objects(Builder::session()?, &state, &source)?
         .name(paths::BUS_NAME)?
         .build()
         .await?;
 
     info!("owning {} at {}", paths::BUS_NAME, paths::ROOT_PATH);
-    let service = Service {
-        connection,
-        state,
-        registered_outputs: HashSet::new(),
-        registered_workspaces: HashSet::new(),
-        registered_windows: HashSet::new(),
-    };
+    let service = Service::new(connection, state, source);
 
     tokio::select! {
         result = service.run_niri_loop() => result,
@@ -44,9 +37,26 @@ pub async fn run() -> anyhow::Result<()> {
     }
 }
 
+/// Adds the objects that live as long as the service to a connection that is
+/// about to be built.
+fn serve_objects<'a>(
+    builder: Builder<'a>,
+    state: &SharedState,
+    source: &NiriSource,
+) -> zbus::Result<Builder<'a>> {
+    builder
+        .serve_at(paths::ROOT_PATH, RootInterface::new(state.clone()))?
+        .serve_at(
+            paths::ROOT_PATH,
+            ActionsInterface::new(state.clone(), source),
+        )?
+        .serve_at(paths::ROOT_PATH, zbus::fdo::ObjectManager)
+}
+
 struct Service {
     connection: Connection,
     state: SharedState,
+    source: NiriSource,
     registered_outputs: HashSet<String>,
     registered_workspaces: HashSet<u64>,
     registered_windows: HashSet<u64>,
@@ -61,10 +71,25 @@ macro_rules! emit_changed {
 }
 
 impl Service {
+    fn new(connection: Connection, state: SharedState, source: NiriSource) -> Self {
+        Self {
+            connection,
+            state,
+            source,
+            registered_outputs: HashSet::new(),
+            registered_workspaces: HashSet::new(),
+            registered_windows: HashSet::new(),
+        }
+    }
+
     async fn run_niri_loop(mut self) -> anyhow::Result<()> {
         loop {
             match self.run_connected_once().await {
                 Ok(()) => warn!("niri event stream ended"),
+                Err(error) if error.is::<ReplayFinished>() => {
+                    info!("replay finished, keeping the last state");
+                    return std::future::pending().await;
+                }
                 Err(error) => warn!("niri connection failed: {error:#}"),
             }
 
@@ -73,21 +98,22 @@ impl Service {
             let after = self.snapshot().await;
             self.apply_object_delta(delta).await?;
             self.emit_changes(&before, &after).await;
-            sleep(Duration::from_secs(1)).await;
+            if self.source.is_live() {
+                sleep(Duration::from_secs(1)).await;
+            }
         }
     }
 
     async fn run_connected_once(&mut self) -> anyhow::Result<()> {
         let before = self.snapshot().await;
-        let (version, outputs) = ipc::initial_snapshot().await?;
+        let (version, outputs) = self.source.connect().await?;
         let delta = self.state.write().await.mark_connected(version, outputs);
         let after = self.snapshot().await;
         self.apply_object_delta(delta).await?;
         self.emit_changes(&before, &after).await;
 
-        let mut stream = ipc::event_stream().await?;
         loop {
-            let event = stream.read_event().await?;
+            let event = self.source.read_event().await?;
             debug!(?event, "niri event");
             let before = self.snapshot().await;
             let delta = self.state.write().await.apply_event(event)?;
@@ -228,6 +254,22 @@ impl Service {
             workspaces_changed
         );
         emit_changed!(before, after, iface, context, windows, windows_changed);
+        emit_changed!(
+            before,
+            after,
+            iface,
+            context,
+            windows_by_recent_focus,
+            windows_by_recent_focus_changed
+        );
+        emit_changed!(
+            before,
+            after,
+            iface,
+            context,
+            focus_history,
+            focus_history_changed
+        );
         emit_changed!(
             before,
             after,
@@ -449,6 +491,14 @@ impl Service {
             active_window_changed
         );
         emit_changed!(before, after, iface, context, windows, windows_changed);
+        emit_changed!(
+            before,
+            after,
+            iface,
+            context,
+            windows_by_recent_focus,
+            windows_by_recent_focus_changed
+        );
     }
 
     async fn emit_window_changes(
@@ -547,6 +597,7 @@ struct ProjectionSnapshot {
 
 impl ProjectionSnapshot {
     fn from_state(state: &NiriState) -> Self {
+        let mut recent_windows = state.recent_windows_by_workspace();
         Self {
             root: RootProjection::from_state(state),
             outputs: state
@@ -559,7 +610,10 @@ impl ProjectionSnapshot {
                 .workspaces
                 .workspaces
                 .keys()
-                .map(|id| (*id, WorkspaceProjection::from_state(state, *id)))
+                .map(|id| {
+                    let recent = recent_windows.remove(id).unwrap_or_default();
+                    (*id, WorkspaceProjection::from_state(state, *id, recent))
+                })
                 .collect(),
             windows: state
                 .event_state
@@ -580,6 +634,8 @@ struct RootProjection {
     outputs: Vec<OwnedObjectPath>,
     workspaces: Vec<OwnedObjectPath>,
     windows: Vec<OwnedObjectPath>,
+    windows_by_recent_focus: Vec<OwnedObjectPath>,
+    focus_history: Vec<(OwnedObjectPath, u64)>,
     focused_output: Option<OwnedObjectPath>,
     focused_workspace: Option<OwnedObjectPath>,
     focused_window: Option<OwnedObjectPath>,
@@ -599,6 +655,8 @@ impl RootProjection {
             outputs: state.output_paths(),
             workspaces: state.workspace_paths(),
             windows: state.window_paths(),
+            windows_by_recent_focus: state.recent_window_paths(),
+            focus_history: state.focus_history(),
             focused_output: state.focused_output_path(),
             focused_workspace: state.focused_workspace_path(),
             focused_window: state.focused_window_path(),
@@ -684,10 +742,17 @@ struct WorkspaceProjection {
     urgent: bool,
     active_window: Option<OwnedObjectPath>,
     windows: Vec<OwnedObjectPath>,
+    windows_by_recent_focus: Vec<OwnedObjectPath>,
 }
 
 impl WorkspaceProjection {
-    fn from_state(state: &NiriState, id: u64) -> Self {
+    /// `windows_by_recent_focus` comes from the snapshot, which sorts every
+    /// window once for all workspaces.
+    fn from_state(
+        state: &NiriState,
+        id: u64,
+        windows_by_recent_focus: Vec<OwnedObjectPath>,
+    ) -> Self {
         let workspace = state.workspace(id);
         Self {
             name: workspace.and_then(|workspace| workspace.name.clone()),
@@ -708,6 +773,7 @@ impl WorkspaceProjection {
                 .and_then(|workspace| workspace.active_window_id)
                 .map(paths::window_path),
             windows: state.windows_for_workspace(id),
+            windows_by_recent_focus,
         }
     }
 }
@@ -803,3 +869,295 @@ fn transform_name(transform: Transform) -> &'static str {
         Transform::Flipped270 => "flipped-270",
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures_util::{StreamExt, stream::BoxStream};
+    use niri_dbus::{NiriClient, NiriSnapshot};
+    use niri_ipc::{Action, Event};
+    use tokio::{net::UnixStream, time::timeout};
+    use zbus::{Message, MessageStream, message::Type, zvariant::OwnedValue};
+
+    use super::*;
+    use crate::fake_niri::{FakeNiri, output, window, workspace};
+
+    /// Serves the projection on one end of a peer-to-peer connection, so
+    /// tests need no session bus, and returns the client end.
+    async fn start(fake: &FakeNiri) -> (Service, Connection) {
+        let state: SharedState = Arc::new(RwLock::new(NiriState::default()));
+        let source = NiriSource::live_at(fake.path());
+        let (server, client) = UnixStream::pair().expect("socket pair");
+        let server = serve_objects(
+            Builder::unix_stream(server)
+                .server(zbus::Guid::generate())
+                .expect("server guid")
+                .p2p(),
+            &state,
+            &source,
+        )
+        .expect("serve objects")
+        .build();
+        let client = Builder::unix_stream(client).p2p().build();
+        let (server, client) = tokio::try_join!(server, client).expect("peer connection");
+        (Service::new(server, state, source), client)
+    }
+
+    /// Signals read off the raw message stream. Signal proxies resolve the
+    /// owner of a bus name first, which a peer-to-peer connection lacks.
+    struct Signals(MessageStream);
+
+    impl Signals {
+        async fn next(&mut self, member: &str) -> Message {
+            loop {
+                let message = self
+                    .0
+                    .next()
+                    .await
+                    .expect("connection open")
+                    .expect("valid message");
+                let matches = {
+                    let header = message.header();
+                    header.message_type() == Type::Signal
+                        && header.member().is_some_and(|name| name.as_str() == member)
+                };
+                if matches {
+                    return message;
+                }
+            }
+        }
+
+        async fn added(&mut self) -> OwnedObjectPath {
+            let message = self.next("InterfacesAdded").await;
+            let (path, _): (
+                OwnedObjectPath,
+                HashMap<String, HashMap<String, OwnedValue>>,
+            ) = message.body().deserialize().expect("InterfacesAdded body");
+            path
+        }
+
+        async fn removed(&mut self) -> OwnedObjectPath {
+            let message = self.next("InterfacesRemoved").await;
+            let (path, _): (OwnedObjectPath, Vec<String>) = message
+                .body()
+                .deserialize()
+                .expect("InterfacesRemoved body");
+            path
+        }
+
+        async fn changed(&mut self, path: &OwnedObjectPath, property: &str) {
+            loop {
+                let message = self.next("PropertiesChanged").await;
+                if message.header().path().map(|changed| changed.as_str()) != Some(path.as_str()) {
+                    continue;
+                }
+                let (_, changed, invalidated): (String, HashMap<String, OwnedValue>, Vec<String>) =
+                    message
+                        .body()
+                        .deserialize()
+                        .expect("PropertiesChanged body");
+                if changed.contains_key(property) || invalidated.iter().any(|name| name == property)
+                {
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn generation(client: &Connection) -> u64 {
+        let reply = client
+            .call_method(
+                Some(paths::BUS_NAME),
+                paths::ROOT_PATH,
+                Some("org.freedesktop.DBus.Properties"),
+                "Get",
+                &(paths::ROOT_INTERFACE, "Generation"),
+            )
+            .await
+            .expect("get Generation");
+        let value: OwnedValue = reply.body().deserialize().expect("Generation value");
+        u64::try_from(value).expect("Generation is a u64")
+    }
+
+    async fn until(
+        snapshots: &mut BoxStream<'static, NiriSnapshot>,
+        done: impl Fn(&NiriSnapshot) -> bool,
+    ) -> NiriSnapshot {
+        loop {
+            let snapshot = snapshots.next().await.expect("snapshots continue");
+            if done(&snapshot) {
+                return snapshot;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn projects_niri_objects_and_survives_reconnects() {
+        let fake = FakeNiri::start("niri 26.4", [output("eDP-1")]).expect("fake niri");
+        let (service, client) = start(&fake).await;
+        let mut signals = Signals(MessageStream::from(&client));
+        let root = OwnedObjectPath::try_from(paths::ROOT_PATH).expect("root path");
+
+        let script = async {
+            assert_eq!(signals.added().await, paths::output_path("eDP-1"));
+            fake.wait_for_streams(1).await;
+
+            fake.push(Event::WorkspacesChanged {
+                workspaces: vec![workspace(5, 1, "eDP-1", true, true, None)],
+            });
+            assert_eq!(signals.added().await, paths::workspace_path(5));
+            signals.changed(&root, "FocusedWorkspace").await;
+
+            fake.push(Event::WindowsChanged {
+                windows: vec![window(10, Some(5), Some((1, 1)), true)],
+            });
+            assert_eq!(signals.added().await, paths::window_path(10));
+            fake.push(Event::WindowClosed { id: 10 });
+            assert_eq!(signals.removed().await, paths::window_path(10));
+
+            let connected_generation = generation(&client).await;
+            fake.disconnect();
+            assert_eq!(signals.removed().await, paths::workspace_path(5));
+            assert_eq!(signals.removed().await, paths::output_path("eDP-1"));
+            assert_eq!(signals.added().await, paths::output_path("eDP-1"));
+            signals.changed(&root, "Generation").await;
+            fake.wait_for_streams(2).await;
+            assert_eq!(generation(&client).await, connected_generation + 2);
+        };
+
+        tokio::select! {
+            result = service.run_niri_loop() => panic!("service stopped: {result:?}"),
+            result = timeout(Duration::from_secs(10), script) => result.expect("script finished in time"),
+        }
+    }
+
+    #[tokio::test]
+    async fn client_snapshots_follow_the_projection() {
+        let fake = FakeNiri::start("niri 26.4", [output("eDP-1")]).expect("fake niri");
+        let (service, client) = start(&fake).await;
+        let client = NiriClient::new(&client).await.expect("niri client");
+        let mut snapshots = client.watch().await.expect("watch the projection");
+
+        let script = async {
+            let snapshot = until(&mut snapshots, |snapshot| snapshot.root.connected).await;
+            assert_eq!(snapshot.root.compositor_version, "niri 26.4");
+            let output = snapshot.output("eDP-1").expect("output");
+            assert_eq!(output.serial.as_deref(), Some("serial"));
+            assert_eq!(output.current_mode_width, Some(1920));
+            assert_eq!(snapshot.focused_window(), None);
+            fake.wait_for_streams(1).await;
+
+            fake.push(Event::WorkspacesChanged {
+                workspaces: vec![workspace(5, 1, "eDP-1", true, true, None)],
+            });
+            fake.push(Event::WindowsChanged {
+                windows: vec![window(10, Some(5), Some((1, 1)), true)],
+            });
+            let snapshot = until(&mut snapshots, |snapshot| {
+                snapshot.focused_window().is_some()
+            })
+            .await;
+            let focused = snapshot.focused_window().expect("focused window");
+            assert_eq!(focused.id, 10);
+            assert_eq!(focused.title.as_deref(), Some("window 10"));
+            assert_eq!(focused.workspace, Some(paths::workspace_path(5)));
+            assert_eq!(focused.column_index, Some(1));
+            assert_eq!(focused.tile_x, Some(10.0));
+            assert_eq!(focused.focus_timestamp, Some(Duration::new(1, 10)));
+            assert_eq!(
+                snapshot.root.windows_by_recent_focus,
+                vec![paths::window_path(10)]
+            );
+            // Focus niri reports on connecting is not a focus change.
+            assert!(snapshot.root.focus_history.is_empty());
+            let workspace = snapshot.workspace(5).expect("workspace");
+            assert_eq!(workspace.name, None);
+            assert_eq!(workspace.output, Some(paths::output_path("eDP-1")));
+            assert_eq!(
+                workspace.windows_by_recent_focus,
+                vec![paths::window_path(10)]
+            );
+
+            fake.push(Event::WindowFocusChanged { id: None });
+            fake.push(Event::WindowFocusChanged { id: Some(10) });
+            let snapshot = until(&mut snapshots, |snapshot| {
+                !snapshot.root.focus_history.is_empty()
+            })
+            .await;
+            assert_eq!(snapshot.root.focus_history[0].0, paths::window_path(10));
+
+            let generation = snapshot.root.generation;
+            fake.disconnect();
+            until(&mut snapshots, |snapshot| {
+                snapshot.windows.is_empty() && snapshot.root.generation > generation
+            })
+            .await;
+        };
+
+        tokio::select! {
+            result = service.run_niri_loop() => panic!("service stopped: {result:?}"),
+            result = timeout(Duration::from_secs(10), script) => result.expect("script finished in time"),
+        }
+    }
+
+    #[tokio::test]
+    async fn actions_reach_niri_and_map_its_errors() {
+        let fake = FakeNiri::start("niri 26.4", [output("eDP-1")]).expect("fake niri");
+        let (service, client) = start(&fake).await;
+        let client = NiriClient::new(&client).await.expect("niri client");
+        let mut snapshots = client.watch().await.expect("watch the projection");
+        let method_error = |error: zbus::Error| match error {
+            zbus::Error::MethodError(name, message, _) => (name.to_string(), message),
+            error => panic!("expected a method error, got {error:?}"),
+        };
+
+        let script = async {
+            until(&mut snapshots, |snapshot| snapshot.root.connected).await;
+            fake.wait_for_streams(1).await;
+            fake.push(Event::WorkspacesChanged {
+                workspaces: vec![workspace(5, 1, "eDP-1", true, true, None)],
+            });
+            fake.push(Event::WindowsChanged {
+                windows: vec![window(10, Some(5), Some((1, 1)), true)],
+            });
+            until(&mut snapshots, |snapshot| snapshot.window(10).is_some()).await;
+
+            client
+                .actions()
+                .focus_window(10)
+                .await
+                .expect("focus window");
+            let (name, _) = method_error(
+                client
+                    .actions()
+                    .close_window(11)
+                    .await
+                    .expect_err("unknown window"),
+            );
+            assert_eq!(name, "org.rsynapse.Niri1.Error.UnknownWindow");
+
+            fake.reject_actions("window 10 is busy");
+            let (name, message) = method_error(
+                client
+                    .actions()
+                    .close_window(10)
+                    .await
+                    .expect_err("rejected close"),
+            );
+            assert_eq!(name, "org.rsynapse.Niri1.Error.Rejected");
+            assert_eq!(message.as_deref(), Some("window 10 is busy"));
+
+            assert!(matches!(
+                fake.actions()[..],
+                [
+                    Action::FocusWindow { id: 10 },
+                    Action::CloseWindow { id: Some(10) }
+                ]
+            ));
+        };
+
+        tokio::select! {
+            result = service.run_niri_loop() => panic!("service stopped: {result:?}"),
+            result = timeout(Duration::from_secs(10), script) => result.expect("script finished in time"),
+        }
+    }
+}
@@ -0,0 +1,369 @@
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, bail};
+use niri_ipc::{Event, Output};
+use serde::{Deserialize, Serialize};
+use tokio::time::{Duration, Instant, sleep_until};
+use tracing::warn;
+
+use crate::ipc::{self, AsyncNiriSocket};
+
+const USAGE: &str = "usage: niri-dbus [--record <file> | --replay <file> [--realtime]]";
+
+/// Where the projection reads niri state from.
+pub enum NiriSource {
+    /// niri's socket, optionally copied to a recording.
+    Live {
+        /// Overrides `NIRI_SOCKET`, which is otherwise read on every connect.
+        socket: Option<PathBuf>,
+        stream: Option<AsyncNiriSocket>,
+        recorder: Option<Recorder>,
+    },
+    /// A recording made with `--record`.
+    Replay(Replay),
+}
+
+/// One line of a recording. A snapshot starts every connection, so a
+/// recording that spans reconnects holds several.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RecordedLine {
+    /// Milliseconds since the recording started.
+    pub at_ms: u64,
+    #[serde(flatten)]
+    pub entry: RecordedEntry,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RecordedEntry {
+    Snapshot {
+        version: String,
+        outputs: HashMap<String, Output>,
+    },
+    Event(Event),
+}
+
+/// Returned once a replay has used up its recording.
+#[derive(Debug)]
+pub struct ReplayFinished;
+
+impl fmt::Display for ReplayFinished {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("replay reached the end of the recording")
+    }
+}
+
+impl std::error::Error for ReplayFinished {}
+
+impl NiriSource {
+    pub fn from_args(args: impl IntoIterator<Item = OsString>) -> anyhow::Result<Self> {
+        let mut args = args.into_iter();
+        let mut record = None;
+        let mut replay = None;
+        let mut realtime = false;
+        while let Some(arg) = args.next() {
+            match arg.to_str() {
+                Some("--record") => record = Some(path_arg(&mut args, "--record")?),
+                Some("--replay") => replay = Some(path_arg(&mut args, "--replay")?),
+                Some("--realtime") => realtime = true,
+                Some("-h" | "--help") => {
+                    println!("{USAGE}");
+                    std::process::exit(0);
+                }
+                _ => bail!("unexpected argument {arg:?}\n{USAGE}"),
+            }
+        }
+
+        match (record, replay) {
+            (Some(_), Some(_)) => bail!("--record and --replay cannot be combined\n{USAGE}"),
+            (_, None) if realtime => bail!("--realtime only applies to --replay\n{USAGE}"),
+            (record, None) => Ok(Self::Live {
+                socket: None,
+                stream: None,
+                recorder: record.map(Recorder::create).transpose()?,
+            }),
+            (None, Some(path)) => {
+                let file = File::open(&path)
+                    .with_context(|| format!("failed to open recording {}", path.display()))?;
+                Ok(Self::Replay(Replay::new(BufReader::new(file), realtime)))
+            }
+        }
+    }
+
+    /// A live source reading the socket at `path`, without recording.
+    #[cfg(test)]
+    pub fn live_at(path: &Path) -> Self {
+        Self::Live {
+            socket: Some(path.to_owned()),
+            stream: None,
+            recorder: None,
+        }
+    }
+
+    /// The socket this source overrides `NIRI_SOCKET` with, which actions
+    /// use too so they reach the compositor the events come from.
+    pub fn socket(&self) -> Option<&Path> {
+        match self {
+            Self::Live { socket, .. } => socket.as_deref(),
+            Self::Replay(_) => None,
+        }
+    }
+
+    pub fn is_live(&self) -> bool {
+        matches!(self, Self::Live { .. })
+    }
+
+    /// Starts a connection and returns its initial snapshot.
+    pub async fn connect(&mut self) -> anyhow::Result<(String, HashMap<String, Output>)> {
+        match self {
+            Self::Live {
+                socket,
+                stream,
+                recorder,
+            } => {
+                *stream = None;
+                let path = match socket {
+                    Some(path) => path.clone(),
+                    None => ipc::socket_path()?,
+                };
+                let (version, outputs) = ipc::initial_snapshot(&path).await?;
+                *stream = Some(ipc::event_stream(&path).await?);
+                record(recorder, || RecordedEntry::Snapshot {
+                    version: version.clone(),
+                    outputs: outputs.clone(),
+                });
+                Ok((version, outputs))
+            }
+            Self::Replay(replay) => replay.snapshot().await,
+        }
+    }
+
+    /// The next event of the current connection. An error ends the
+    /// connection.
+    pub async fn read_event(&mut self) -> anyhow::Result<Event> {
+        match self {
+            Self::Live {
+                stream, recorder, ..
+            } => {
+                let event = stream
+                    .as_mut()
+                    .context("niri event stream is not connected")?
+                    .read_event()
+                    .await?;
+                record(recorder, || RecordedEntry::Event(event.clone()));
+                Ok(event)
+            }
+            Self::Replay(replay) => replay.event().await,
+        }
+    }
+}
+
+pub struct Recorder {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    started: Instant,
+}
+
+impl Recorder {
+    fn create(path: PathBuf) -> anyhow::Result<Self> {
+        let file = File::create(&path)
+            .with_context(|| format!("failed to create recording {}", path.display()))?;
+        Ok(Self {
+            path,
+            writer: BufWriter::new(file),
+            started: Instant::now(),
+        })
+    }
+
+    /// Appends one line and flushes it, so a crash keeps everything up to
+    /// the event that caused it.
+    fn write(&mut self, entry: RecordedEntry) -> anyhow::Result<()> {
+        let line = RecordedLine {
+            at_ms: elapsed_ms(self.started),
+            entry,
+        };
+        serde_json::to_writer(&mut self.writer, &line)?;
+        self.writer.write_all(b"\n")?;
+        self.writer
+            .flush()
+            .with_context(|| format!("failed to write recording {}", self.path.display()))
+    }
+}
+
+pub struct Replay {
+    lines: Box<dyn Iterator<Item = io::Result<String>> + Send>,
+    line_number: usize,
+    /// A snapshot read while looking for an event, kept for the next
+    /// connection.
+    pending: Option<RecordedLine>,
+    /// When the replay started, if lines wait for their recorded time.
+    started: Option<Instant>,
+}
+
+impl Replay {
+    pub fn new(reader: impl BufRead + Send + 'static, realtime: bool) -> Self {
+        Self {
+            lines: Box::new(reader.lines()),
+            line_number: 0,
+            pending: None,
+            started: realtime.then(Instant::now),
+        }
+    }
+
+    async fn snapshot(&mut self) -> anyhow::Result<(String, HashMap<String, Output>)> {
+        let line = self.take_line()?;
+        self.wait_for(line.at_ms).await;
+        match line.entry {
+            RecordedEntry::Snapshot { version, outputs } => Ok((version, outputs)),
+            RecordedEntry::Event(_) => bail!(
+                "recording line {} is an event before any snapshot",
+                self.line_number
+            ),
+        }
+    }
+
+    async fn event(&mut self) -> anyhow::Result<Event> {
+        match self.take_line()? {
+            RecordedLine {
+                at_ms,
+                entry: RecordedEntry::Event(event),
+            } => {
+                self.wait_for(at_ms).await;
+                Ok(event)
+            }
+            snapshot => {
+                self.pending = Some(snapshot);
+                bail!("recorded niri connection ended")
+            }
+        }
+    }
+
+    fn take_line(&mut self) -> anyhow::Result<RecordedLine> {
+        match self.pending.take() {
+            Some(line) => Ok(line),
+            None => self.read_line(),
+        }
+    }
+
+    fn read_line(&mut self) -> anyhow::Result<RecordedLine> {
+        loop {
+            let Some(line) = self.lines.next().transpose()? else {
+                return Err(ReplayFinished.into());
+            };
+            self.line_number += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            return serde_json::from_str(&line)
+                .with_context(|| format!("failed to parse recording line {}", self.line_number));
+        }
+    }
+
+    async fn wait_for(&self, at_ms: u64) {
+        if let Some(started) = self.started {
+            sleep_until(started + Duration::from_millis(at_ms)).await;
+        }
+    }
+}
+
+/// Writes one entry, giving up on the recording rather than on niri when
+/// the file cannot be written.
+fn record(recorder: &mut Option<Recorder>, entry: impl FnOnce() -> RecordedEntry) {
+    let Some(active) = recorder.as_mut() else {
+        return;
+    };
+    if let Err(error) = active.write(entry()) {
+        warn!("stopped recording: {error:#}");
+        *recorder = None;
+    }
+}
+
+fn path_arg(args: &mut impl Iterator<Item = OsString>, flag: &str) -> anyhow::Result<PathBuf> {
+    args.next()
+        .map(PathBuf::from)
+        .with_context(|| format!("{flag} needs a file\n{USAGE}"))
+}
+
+fn elapsed_ms(started: Instant) -> u64 {
+    started.elapsed().as_millis().try_into().unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(at_ms: u64, entry: RecordedEntry) -> String {
+        serde_json::to_string(&RecordedLine { at_ms, entry }).expect("serialize line")
+    }
+
+    fn snapshot(version: &str) -> RecordedEntry {
+        RecordedEntry::Snapshot {
+            version: version.to_owned(),
+            outputs: HashMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn replay_follows_recorded_connections_until_the_end() {
+        let recording = [
+            line(0, snapshot("niri 26.4")),
+            line(
+                5,
+                RecordedEntry::Event(Event::WorkspaceActivated {
+                    id: 5,
+                    focused: true,
+                }),
+            ),
+            String::new(),
+            line(9, snapshot("niri 26.4.1")),
+        ]
+        .join("\n");
+        let mut source = NiriSource::Replay(Replay::new(io::Cursor::new(recording), false));
+
+        assert_eq!(
+            source.connect().await.expect("first snapshot").0,
+            "niri 26.4"
+        );
+        assert!(matches!(
+            source.read_event().await.expect("event"),
+            Event::WorkspaceActivated { id: 5, .. }
+        ));
+        let reconnect = source.read_event().await.expect_err("connection ends");
+        assert!(!reconnect.is::<ReplayFinished>());
+        assert_eq!(
+            source.connect().await.expect("second snapshot").0,
+            "niri 26.4.1"
+        );
+        let end = source.read_event().await.expect_err("recording ends");
+        assert!(end.is::<ReplayFinished>());
+    }
+
+    #[test]
+    fn arguments_choose_the_source() {
+        let args = |args: &[&str]| NiriSource::from_args(args.iter().map(OsString::from));
+
+        assert!(args(&[]).expect("live").is_live());
+        assert!(args(&["--record", "/dev/null"]).expect("record").is_live());
+        assert!(
+            !args(&["--replay", "/dev/null", "--realtime"])
+                .expect("replay")
+                .is_live()
+        );
+        for invalid in [
+            &["--record"][..],
+            &["--realtime"],
+            &["--record", "/dev/null", "--replay", "/dev/null"],
+            &["--verbose"],
+        ] {
+            assert!(args(invalid).is_err(), "{invalid:?} should be rejected");
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use std::{
-    collections::{HashMap, HashSet},
+    cmp::Reverse,
+    collections::{HashMap, HashSet, VecDeque},
     panic::{AssertUnwindSafe, catch_unwind},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 use niri_ipc::{
@@ -11,6 +13,9 @@ use zbus::zvariant::OwnedObjectPath;
 
 use niri_dbus::paths;
 
+/// How many focus changes `focus_history` keeps.
+pub const FOCUS_HISTORY_LIMIT: usize = 64;
+
 #[derive(Debug, Default)]
 pub struct NiriState {
     pub connected: bool,
@@ -18,6 +23,21 @@ pub struct NiriState {
     pub generation: u64,
     pub outputs: HashMap<String, Output>,
     pub event_state: EventStreamState,
+    /// When each live window was last seen gaining focus, as a count of
+    /// focus changes since the connection started.
+    focused_at: HashMap<u64, u64>,
+    focus_changes: u64,
+    focus_history: VecDeque<FocusChange>,
+    /// Whether this connection's first `WindowsChanged` has arrived. The
+    /// focus it reports is niri's from before, not a change.
+    windows_seen: bool,
+}
+
+/// A window gaining focus, timed by the service's wall clock.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct FocusChange {
+    window_id: u64,
+    at_unix_ms: u64,
 }
 
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
@@ -44,6 +64,8 @@ impl NiriState {
         self.compositor_version = version;
         self.outputs = outputs;
         self.event_state = EventStreamState::default();
+        self.windows_seen = false;
+        self.clear_focus_history();
         self.generation = self.generation.wrapping_add(1);
         self.object_set().delta_from(&before)
     }
@@ -57,6 +79,8 @@ impl NiriState {
         self.connected = false;
         self.outputs.clear();
         self.event_state = EventStreamState::default();
+        self.windows_seen = false;
+        self.clear_focus_history();
         if had_projected_state {
             self.generation = self.generation.wrapping_add(1);
         }
@@ -65,13 +89,48 @@ impl NiriState {
 
     pub fn apply_event(&mut self, event: Event) -> anyhow::Result<ObjectDelta> {
         let before = self.object_set();
+        let focused_before = self.focused_window_id();
+        let windows_seen = self.windows_seen;
+        if matches!(event, Event::WindowsChanged { .. }) {
+            self.windows_seen = true;
+        }
         let result = catch_unwind(AssertUnwindSafe(|| self.event_state.apply(event)));
         if result.is_err() {
             anyhow::bail!("niri EventStreamState rejected event ordering");
         }
+        let focused = self.focused_window_id();
+        if let Some(window_id) = focused.filter(|_| windows_seen && focused != focused_before) {
+            self.record_focus(window_id);
+        }
+        let windows = &self.event_state.windows.windows;
+        self.focused_at.retain(|id, _| windows.contains_key(id));
         Ok(self.object_set().delta_from(&before))
     }
 
+    fn record_focus(&mut self, window_id: u64) {
+        self.focus_changes += 1;
+        self.focused_at.insert(window_id, self.focus_changes);
+        self.focus_history.push_front(FocusChange {
+            window_id,
+            at_unix_ms: now_unix_ms(),
+        });
+        self.focus_history.truncate(FOCUS_HISTORY_LIMIT);
+    }
+
+    fn clear_focus_history(&mut self) {
+        self.focused_at.clear();
+        self.focus_history.clear();
+    }
+
+    /// `(window, at-unix-ms)` for each focus change since the connection
+    /// started, newest first. Entries outlive their windows.
+    pub fn focus_history(&self) -> Vec<(OwnedObjectPath, u64)> {
+        self.focus_history
+            .iter()
+            .map(|change| (paths::window_path(change.window_id), change.at_unix_ms))
+            .collect()
+    }
+
     pub fn object_set(&self) -> ObjectSet {
         ObjectSet {
             outputs: self.outputs.keys().cloned().collect(),
@@ -146,6 +205,53 @@ impl NiriState {
             .collect()
     }
 
+    /// Live windows, most recently focused first: the focused window, then
+    /// windows in the order this connection saw them gain focus, then the
+    /// rest by niri's focus timestamp, which covers focus from before the
+    /// service connected.
+    pub fn windows_by_recent_focus(&self) -> Vec<&Window> {
+        let mut windows = self
+            .event_state
+            .windows
+            .windows
+            .values()
+            .collect::<Vec<_>>();
+        windows.sort_by_key(|window| {
+            (
+                Reverse(window.is_focused),
+                Reverse(self.focused_at.get(&window.id).copied()),
+                Reverse(
+                    window
+                        .focus_timestamp
+                        .map(|timestamp| (timestamp.secs, timestamp.nanos)),
+                ),
+                window.id,
+            )
+        });
+        windows
+    }
+
+    pub fn recent_window_paths(&self) -> Vec<OwnedObjectPath> {
+        self.windows_by_recent_focus()
+            .into_iter()
+            .map(|window| paths::window_path(window.id))
+            .collect()
+    }
+
+    /// `recent_window_paths` split by workspace, from a single sort.
+    pub fn recent_windows_by_workspace(&self) -> HashMap<u64, Vec<OwnedObjectPath>> {
+        let mut by_workspace = HashMap::<u64, Vec<OwnedObjectPath>>::new();
+        for window in self.windows_by_recent_focus() {
+            if let Some(workspace_id) = window.workspace_id {
+                by_workspace
+                    .entry(workspace_id)
+                    .or_default()
+                    .push(paths::window_path(window.id));
+            }
+        }
+        by_workspace
+    }
+
     pub fn focused_window_id(&self) -> Option<u64> {
         self.event_state
             .windows
@@ -242,6 +348,13 @@ impl NiriState {
     }
 }
 
+fn now_unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis().try_into().unwrap_or(u64::MAX))
+        .unwrap_or(0)
+}
+
 fn window_layout_key(window: &Window) -> (usize, usize) {
     window
         .layout
@@ -278,11 +391,10 @@ impl ObjectSet {
 mod tests {
     use std::collections::{HashMap, HashSet};
 
-    use niri_ipc::{
-        Event, LogicalOutput, Mode, Output, Timestamp, Transform, Window, WindowLayout, Workspace,
-    };
+    use niri_ipc::Event;
 
     use super::*;
+    use crate::fake_niri::{output, window, workspace};
 
     #[test]
     fn applies_workspace_events_into_focus_paths() {
@@ -471,84 +583,101 @@ mod tests {
         assert_eq!(state.focused_window_path(), None);
     }
 
-    fn output(name: &str) -> Output {
-        Output {
-            name: name.to_owned(),
-            make: "Acme".to_owned(),
-            model: "Panel".to_owned(),
-            serial: Some("serial".to_owned()),
-            physical_size: Some((300, 200)),
-            modes: vec![Mode {
-                width: 1920,
-                height: 1080,
-                refresh_rate: 60_000,
-                is_preferred: true,
-            }],
-            current_mode: Some(0),
-            is_custom_mode: false,
-            vrr_supported: true,
-            vrr_enabled: false,
-            logical: Some(LogicalOutput {
-                x: 0,
-                y: 0,
-                width: 1920,
-                height: 1080,
-                scale: 1.0,
-                transform: Transform::Normal,
-            }),
-        }
+    fn path_string(path: OwnedObjectPath) -> String {
+        path.as_str().to_owned()
     }
 
-    fn workspace(
-        id: u64,
-        idx: u8,
-        output: &str,
-        is_active: bool,
-        is_focused: bool,
-        active_window_id: Option<u64>,
-    ) -> Workspace {
-        Workspace {
-            id,
-            idx,
-            name: None,
-            output: Some(output.to_owned()),
-            is_urgent: false,
-            is_active,
-            is_focused,
-            active_window_id,
+    fn window_ids(window_paths: Vec<OwnedObjectPath>) -> Vec<u64> {
+        window_paths
+            .iter()
+            .map(|path| paths::window_id(path.as_str()).expect("window path"))
+            .collect()
+    }
+
+    #[test]
+    fn recent_focus_follows_focus_events_after_timestamps() {
+        let mut state = NiriState::default();
+        state
+            .apply_event(Event::WindowsChanged {
+                windows: vec![
+                    window(1, Some(5), Some((1, 1)), true),
+                    window(2, Some(5), Some((2, 1)), false),
+                    window(3, Some(6), Some((1, 1)), false),
+                ],
+            })
+            .expect("windows apply");
+        // Window 3 has the newest timestamp from before the connection.
+        assert_eq!(window_ids(state.recent_window_paths()), vec![1, 3, 2]);
+
+        for id in [2, 3] {
+            state
+                .apply_event(Event::WindowFocusChanged { id: Some(id) })
+                .expect("focus applies");
         }
+        state
+            .apply_event(Event::WindowFocusChanged { id: None })
+            .expect("focus clears");
+        assert_eq!(window_ids(state.recent_window_paths()), vec![3, 2, 1]);
+        let by_workspace = state.recent_windows_by_workspace();
+        assert_eq!(window_ids(by_workspace[&5].clone()), vec![2, 1]);
+        assert_eq!(window_ids(by_workspace[&6].clone()), vec![3]);
+
+        state
+            .apply_event(Event::WindowClosed { id: 2 })
+            .expect("close applies");
+        assert_eq!(window_ids(state.recent_window_paths()), vec![3, 1]);
+        let history = state.focus_history();
+        // The initial focus of window 1 was not a change.
+        assert_eq!(
+            window_ids(history.iter().map(|(path, _)| path.clone()).collect()),
+            vec![3, 2]
+        );
+        assert!(history.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+
+        state.mark_disconnected();
+        assert!(state.focus_history().is_empty());
     }
 
-    fn window(
-        id: u64,
-        workspace_id: Option<u64>,
-        pos_in_scrolling_layout: Option<(usize, usize)>,
-        is_focused: bool,
-    ) -> Window {
-        Window {
-            id,
-            title: Some(format!("window {id}")),
-            app_id: Some("test-app".to_owned()),
-            pid: Some(1000 + id as i32),
-            workspace_id,
-            is_focused,
-            is_floating: false,
-            is_urgent: false,
-            layout: WindowLayout {
-                pos_in_scrolling_layout,
-                tile_size: (800.0, 600.0),
-                window_size: (780, 580),
-                tile_pos_in_workspace_view: Some((10.0, 20.0)),
-                window_offset_in_tile: (5.0, 6.0),
-            },
-            focus_timestamp: Some(Timestamp {
-                secs: 1,
-                nanos: id as u32,
-            }),
+    #[test]
+    fn focus_history_is_bounded() {
+        let mut state = NiriState::default();
+        state
+            .apply_event(Event::WindowsChanged {
+                windows: vec![
+                    window(1, Some(5), Some((1, 1)), true),
+                    window(2, Some(5), Some((2, 1)), false),
+                ],
+            })
+            .expect("windows apply");
+        for turn in 0..FOCUS_HISTORY_LIMIT {
+            state
+                .apply_event(Event::WindowFocusChanged {
+                    id: Some(2 - turn as u64 % 2),
+                })
+                .expect("focus applies");
         }
+
+        let history = state.focus_history();
+        assert_eq!(history.len(), FOCUS_HISTORY_LIMIT);
+        assert_eq!(history[0].0, paths::window_path(1));
     }
 
-    fn path_string(path: OwnedObjectPath) -> String {
-        path.as_str().to_owned()
+    #[test]
+    fn focus_after_an_empty_window_list_is_a_change() {
+        let mut state = NiriState::default();
+        state
+            .apply_event(Event::WindowsChanged {
+                windows: Vec::new(),
+            })
+            .expect("windows apply");
+        state
+            .apply_event(Event::WindowOpenedOrChanged {
+                window: window(1, Some(5), Some((1, 1)), true),
+            })
+            .expect("window opens");
+
+        let history = state.focus_history();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].0, paths::window_path(1));
     }
 }
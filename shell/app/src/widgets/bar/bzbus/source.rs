@@ -1,6 +1,6 @@
 use async_channel::Sender;
 use futures_util::StreamExt;
-use locus::{RelationEndpoint, RelationRecord, keys};
+use locus::{LocusClient, RelationEndpoint, keys};
 use shell_core::source::{self, Observable, rx::Observable as _};
 use shell_rx_macros::combine_latest;
 use zbus::{Connection, MatchRule, Message, MessageStream, Proxy, message::Type, names::BusName};
@@ -301,122 +301,20 @@ async fn run_locus_targets(
     subject: RelationEndpoint,
     relation: &'static str,
 ) -> Result<(), String> {
-    let connection = Connection::session()
+    let client = LocusClient::session()
         .await
-        .map_err(|error| format!("connect session bus: {error}"))?;
-    let proxy = relations_proxy(&connection)
+        .map_err(|error| format!("connect locus client: {error}"))?;
+    let mut targets = client
+        .watch_targets(subject, relation)
         .await
-        .map_err(|error| format!("connect locus proxy: {error}"))?;
-
-    send_targets(&sender, &proxy, &subject, relation).await?;
-
-    let mut added = Box::pin(
-        proxy
-            .receive_signal("RelationAdded")
-            .await
-            .map_err(to_string)?,
-    );
-    let mut updated = Box::pin(
-        proxy
-            .receive_signal("RelationUpdated")
+        .map_err(|error| format!("watch locus targets: {error}"))?;
+    while let Some(targets) = targets.next().await {
+        sender
+            .send(Ok(targets))
             .await
-            .map_err(to_string)?,
-    );
-    let mut removed = Box::pin(
-        proxy
-            .receive_signal("RelationRemoved")
-            .await
-            .map_err(to_string)?,
-    );
-    let mut cleared = Box::pin(
-        proxy
-            .receive_signal("RelationCleared")
-            .await
-            .map_err(to_string)?,
-    );
-
-    loop {
-        tokio::select! {
-            message = added.next() => {
-                let Some(message) = message else { return Ok(()); };
-                if relation_record_matches(&message, &subject, relation)? {
-                    send_targets(&sender, &proxy, &subject, relation).await?;
-                }
-            }
-            message = updated.next() => {
-                let Some(message) = message else { return Ok(()); };
-                if relation_record_matches(&message, &subject, relation)? {
-                    send_targets(&sender, &proxy, &subject, relation).await?;
-                }
-            }
-            message = removed.next() => {
-                let Some(message) = message else { return Ok(()); };
-                if relation_record_matches(&message, &subject, relation)? {
-                    send_targets(&sender, &proxy, &subject, relation).await?;
-                }
-            }
-            message = cleared.next() => {
-                let Some(message) = message else { return Ok(()); };
-                if clear_matches(&message, &subject, relation)? {
-                    send_targets(&sender, &proxy, &subject, relation).await?;
-                }
-            }
-        }
+            .map_err(|_| "locus targets subscriber dropped".to_string())?;
     }
-}
-
-async fn send_targets(
-    sender: &async_channel::Sender<Result<Vec<RelationEndpoint>, String>>,
-    proxy: &Proxy<'_>,
-    subject: &RelationEndpoint,
-    relation: &str,
-) -> Result<(), String> {
-    let targets = match proxy
-        .call::<_, _, Vec<RelationEndpoint>>("Targets", &(subject, relation))
-        .await
-    {
-        Ok(targets) => targets,
-        Err(error) if is_locus_unavailable(&error) => Vec::new(),
-        Err(error) => return Err(format!("read locus targets: {error}")),
-    };
-    sender
-        .send(Ok(targets))
-        .await
-        .map_err(|_| "locus targets subscriber dropped".to_string())
-}
-
-async fn relations_proxy(connection: &Connection) -> zbus::Result<Proxy<'_>> {
-    Proxy::new(
-        connection,
-        locus::BUS_NAME,
-        locus::OBJECT_PATH,
-        locus::RELATIONS_INTERFACE,
-    )
-    .await
-}
-
-fn relation_record_matches(
-    message: &zbus::Message,
-    subject: &RelationEndpoint,
-    relation: &str,
-) -> Result<bool, String> {
-    let record = message
-        .body()
-        .deserialize::<RelationRecord>()
-        .map_err(|error| format!("decode locus relation signal: {error}"))?;
-    Ok(record.subject == *subject && record.relation == relation)
-}
-
-fn clear_matches(
-    message: &zbus::Message,
-    subject: &RelationEndpoint,
-    relation: &str,
-) -> Result<bool, String> {
-    let (cleared_subject, cleared_relation, _count) = message
-        .body()
-        .deserialize::<(RelationEndpoint, String, u32)>()
-        .map_err(|error| format!("decode locus clear signal: {error}"))?;
-    Ok(cleared_subject == *subject && cleared_relation == relation)
+    Ok(())
 }
 
 async fn object_manager_proxy(connection: &Connection) -> Result<Proxy<'_>, String> {
@@ -521,22 +419,6 @@ fn format_dbus_error(context: &str, error: impl std::fmt::Display) -> String {
     format!("{context} failed: {error}")
 }
 
-fn to_string(error: zbus::Error) -> String {
-    error.to_string()
-}
-
-fn is_locus_unavailable(error: &zbus::Error) -> bool {
-    match error {
-        zbus::Error::MethodError(name, _, _) => {
-            name.as_str() == "org.freedesktop.DBus.Error.ServiceUnknown"
-        }
-        zbus::Error::FDO(error) => {
-            matches!(error.as_ref(), zbus::fdo::Error::ServiceUnknown(_))
-        }
-        _ => false,
-    }
-}
-
 enum WatchExit {
     Restart,
     Closed,
@@ -2,22 +2,20 @@ use locus::{RelationEndpoint, RelationRecord, keys};
 
 use super::super::IconChoice;
 
-const ICON_GLYPH_KIND: &str = "org.rsynapse.icon.glyph";
-
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub(super) struct WorkspaceIconIdentity {
     pub(super) subjects: Vec<RelationEndpoint>,
 }
 
 pub(super) fn icon_target(glyph: String) -> RelationEndpoint {
-    RelationEndpoint::stable_key(ICON_GLYPH_KIND, glyph)
+    RelationEndpoint::stable_key(keys::ICON_GLYPH, glyph)
 }
 
 pub(super) fn icon_choice_from_record(record: &RelationRecord) -> Option<IconChoice> {
     let RelationEndpoint::StableKey { kind, id } = &record.target else {
         return None;
     };
-    (kind == ICON_GLYPH_KIND)
+    (kind == keys::ICON_GLYPH)
         .then(|| non_empty(id))
         .flatten()
         .and_then(|glyph| IconChoice::new(glyph.to_owned()))
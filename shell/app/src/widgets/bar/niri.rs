@@ -1,26 +1,15 @@
-use shell_core::source::{
-    Observable,
-    dbus::{self, Bus, ObjectDescriptor, ObjectManagerDescriptor, ObjectModel, PropertyDescriptor},
-    rx::Observable as _,
-};
+use futures_util::StreamExt;
+use niri_dbus::{NiriClient, NiriSnapshot};
+use shell_core::source::{self, Observable, rx::Observable as _};
 use zbus::zvariant::OwnedObjectPath;
 
-const BUS_NAME: &str = "org.rsynapse.Niri";
-const ROOT_PATH: &str = "/org/rsynapse/Niri";
-const ROOT_INTERFACE: &str = "org.rsynapse.Niri1";
-const OUTPUT_INTERFACE: &str = "org.rsynapse.Niri1.Output";
-const WORKSPACE_INTERFACE: &str = "org.rsynapse.Niri1.Workspace";
-const WINDOW_INTERFACE: &str = "org.rsynapse.Niri1.Window";
-const WORKSPACE_PATH_PREFIX: &str = "/org/rsynapse/Niri/Workspaces/workspace_";
-const WINDOW_PATH_PREFIX: &str = "/org/rsynapse/Niri/Windows/window_";
-
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub(super) struct NiriWorkspace {
     path: OwnedObjectPath,
 }
 
 impl NiriWorkspace {
-    fn at(path: OwnedObjectPath) -> Self {
+    pub(super) fn at(path: OwnedObjectPath) -> Self {
         Self { path }
     }
 
@@ -29,50 +18,62 @@ impl NiriWorkspace {
     }
 
     pub(super) fn path_id(&self) -> Option<u64> {
-        id_from_path(&self.path, WORKSPACE_PATH_PREFIX)
+        niri_dbus::workspace_id(self.path.as_str())
     }
 
     pub(super) fn id(&self) -> Observable<u64> {
-        required(self.property("Id"), 0)
+        self.field(|workspace| workspace.id, 0)
     }
 
     pub(super) fn name(&self) -> Observable<Option<String>> {
-        optional(self.property("Name"))
+        self.field(|workspace| workspace.name.clone(), None)
     }
 
     pub(super) fn index(&self) -> Observable<u8> {
-        required(self.property("Index"), 0)
+        self.field(|workspace| workspace.index, 0)
     }
 
     pub(super) fn focused(&self) -> Observable<bool> {
-        required(self.property("Focused"), false)
+        self.field(|workspace| workspace.focused, false)
     }
 
     pub(super) fn active(&self) -> Observable<bool> {
-        required(self.property("Active"), false)
+        self.field(|workspace| workspace.active, false)
     }
 
     pub(super) fn urgent(&self) -> Observable<bool> {
-        required(self.property("Urgent"), false)
+        self.field(|workspace| workspace.urgent, false)
     }
 
     pub(super) fn output_path_key(&self) -> Observable<Option<String>> {
-        dbus::optional_array_property::<OwnedObjectPath>(self.property("Output"))
-            .map(|path| path.map(|path| path.as_str().to_owned()))
-            .distinct_until_changed()
-            .box_it()
-    }
-
-    fn property(&self, name: &'static str) -> PropertyDescriptor {
-        property(self.path.as_str(), WORKSPACE_INTERFACE, name)
+        self.field(
+            |workspace| {
+                workspace
+                    .output
+                    .as_ref()
+                    .map(|path| path.as_str().to_owned())
+            },
+            None,
+        )
     }
-}
-
-impl ObjectModel for NiriWorkspace {
-    const INTERFACE: &'static str = WORKSPACE_INTERFACE;
 
-    fn at(path: OwnedObjectPath) -> Self {
-        Self { path }
+    /// One field of this workspace's model, or `default` while the
+    /// projection does not hold it.
+    fn field<T>(&self, read: fn(&niri_dbus::NiriWorkspace) -> T, default: T) -> Observable<T>
+    where
+        T: Clone + PartialEq + Send + Sync + 'static,
+    {
+        let path = self.path.clone();
+        snapshots()
+            .map(move |snapshot| {
+                snapshot
+                    .workspaces
+                    .iter()
+                    .find(|workspace| workspace.path == path)
+                    .map_or_else(|| default.clone(), read)
+            })
+            .distinct_until_changed()
+            .box_it()
     }
 }
 
@@ -82,69 +83,98 @@ pub(super) struct NiriWindow {
 }
 
 impl NiriWindow {
+    pub(super) fn at(path: OwnedObjectPath) -> Self {
+        Self { path }
+    }
+
     pub(super) fn path_key(&self) -> &str {
         self.path.as_str()
     }
 
     pub(super) fn path_id(&self) -> Option<u64> {
-        id_from_path(&self.path, WINDOW_PATH_PREFIX)
+        niri_dbus::window_id(self.path.as_str())
     }
 
     pub(super) fn id(&self) -> Observable<u64> {
-        required(self.property("Id"), 0)
+        self.field(|window| window.id, 0)
     }
 
     pub(super) fn app_id(&self) -> Observable<Option<String>> {
-        optional(self.property("AppId"))
+        self.field(|window| window.app_id.clone(), None)
     }
 
     pub(super) fn workspace(&self) -> Observable<Option<NiriWorkspace>> {
-        model_optional(self.property("Workspace"), NiriWorkspace::at)
+        self.field(
+            |window| window.workspace.clone().map(NiriWorkspace::at),
+            None,
+        )
     }
 
     pub(super) fn focused(&self) -> Observable<bool> {
-        required(self.property("Focused"), false)
+        self.field(|window| window.focused, false)
     }
 
     pub(super) fn urgent(&self) -> Observable<bool> {
-        required(self.property("Urgent"), false)
+        self.field(|window| window.urgent, false)
     }
 
     pub(super) fn column_index(&self) -> Observable<Option<u64>> {
-        optional(self.property("ColumnIndex"))
+        self.field(|window| window.column_index, None)
     }
 
     pub(super) fn row_index(&self) -> Observable<Option<u64>> {
-        optional(self.property("RowIndex"))
-    }
-
-    fn property(&self, name: &'static str) -> PropertyDescriptor {
-        property(self.path.as_str(), WINDOW_INTERFACE, name)
+        self.field(|window| window.row_index, None)
     }
-}
-
-impl ObjectModel for NiriWindow {
-    const INTERFACE: &'static str = WINDOW_INTERFACE;
 
-    fn at(path: OwnedObjectPath) -> Self {
-        Self { path }
+    /// One field of this window's model, or `default` while the projection
+    /// does not hold it.
+    fn field<T>(&self, read: fn(&niri_dbus::NiriWindow) -> T, default: T) -> Observable<T>
+    where
+        T: Clone + PartialEq + Send + Sync + 'static,
+    {
+        let path = self.path.clone();
+        snapshots()
+            .map(move |snapshot| {
+                snapshot
+                    .windows
+                    .iter()
+                    .find(|window| window.path == path)
+                    .map_or_else(|| default.clone(), read)
+            })
+            .distinct_until_changed()
+            .box_it()
     }
 }
 
 pub(super) fn workspaces() -> Observable<Vec<NiriWorkspace>> {
-    dbus::models::<NiriWorkspace>(niri_object_manager())
+    snapshots()
+        .map(|snapshot| {
+            snapshot
+                .workspaces
+                .into_iter()
+                .map(|workspace| NiriWorkspace::at(workspace.path))
+                .collect()
+        })
         .distinct_until_changed()
         .box_it()
 }
 
 pub(super) fn windows() -> Observable<Vec<NiriWindow>> {
-    dbus::models::<NiriWindow>(niri_object_manager())
+    snapshots()
+        .map(|snapshot| {
+            snapshot
+                .windows
+                .into_iter()
+                .map(|window| NiriWindow::at(window.path))
+                .collect()
+        })
         .distinct_until_changed()
         .box_it()
 }
 
 pub(super) fn focused_workspace() -> Observable<Option<NiriWorkspace>> {
-    model_optional(root_property("FocusedWorkspace"), NiriWorkspace::at)
+    snapshots()
+        .map(|snapshot| snapshot.root.focused_workspace.map(NiriWorkspace::at))
         .distinct_until_changed()
         .box_it()
 }
@@ -154,85 +184,50 @@ pub(super) fn current_workspace(output_name: Option<String>) -> Observable<Optio
         return focused_workspace();
     };
 
-    model_optional(
-        property(
-            output_path_for_name(&output_name).as_str(),
-            OUTPUT_INTERFACE,
-            "CurrentWorkspace",
-        ),
-        NiriWorkspace::at,
-    )
-    .distinct_until_changed()
-    .box_it()
-}
-
-fn required<T>(descriptor: PropertyDescriptor, default: T) -> Observable<T>
-where
-    T: TryFrom<zbus::zvariant::OwnedValue> + Clone + PartialEq + Send + 'static,
-    T::Error: std::fmt::Display,
-{
-    dbus::property_or(descriptor, default)
-}
-
-fn optional<T>(descriptor: PropertyDescriptor) -> Observable<Option<T>>
-where
-    T: Clone + PartialEq + Send + 'static,
-    Vec<T>: TryFrom<zbus::zvariant::OwnedValue>,
-    <Vec<T> as TryFrom<zbus::zvariant::OwnedValue>>::Error: std::fmt::Display,
-{
-    dbus::optional_array_property(descriptor)
-}
-
-fn model_optional<T>(
-    descriptor: PropertyDescriptor,
-    map: fn(OwnedObjectPath) -> T,
-) -> Observable<Option<T>>
-where
-    T: Send + 'static,
-{
-    dbus::optional_array_property::<OwnedObjectPath>(descriptor)
-        .map(move |path| path.map(map))
+    snapshots()
+        .map(move |snapshot| {
+            snapshot
+                .output(&output_name)
+                .and_then(|output| output.current_workspace.clone())
+                .map(NiriWorkspace::at)
+        })
+        .distinct_until_changed()
         .box_it()
 }
 
-pub(super) fn output_path_for_name(name: &str) -> String {
-    format!("{ROOT_PATH}/Outputs/{}", encode_path_segment(name))
+/// The whole niri projection, read once for every widget that follows it.
+fn snapshots() -> Observable<NiriSnapshot> {
+    source::shared_by_key("rsynapse.niri-snapshots", "session", || {
+        source::from_task(|sender| async move {
+            if let Err(error) = run_niri_snapshots(sender).await {
+                eprintln!("[niri] failed to watch the niri projection: {error}");
+            }
+        })
+        .box_it()
+    })
 }
 
-fn encode_path_segment(input: &str) -> String {
-    let mut output = String::from("x");
-    for byte in input.bytes() {
-        output.push_str(&format!("{byte:02X}"));
+async fn run_niri_snapshots(
+    sender: async_channel::Sender<Result<NiriSnapshot, String>>,
+) -> Result<(), String> {
+    let client = NiriClient::session()
+        .await
+        .map_err(|error| format!("connect niri client: {error}"))?;
+    let mut snapshots = client
+        .watch()
+        .await
+        .map_err(|error| format!("watch niri projection: {error}"))?;
+    while let Some(snapshot) = snapshots.next().await {
+        sender
+            .send(Ok(snapshot))
+            .await
+            .map_err(|_| "niri snapshot subscriber dropped".to_string())?;
     }
-    output
-}
-
-fn root_property(name: &'static str) -> PropertyDescriptor {
-    property(ROOT_PATH, ROOT_INTERFACE, name)
-}
-
-fn niri_object_manager() -> ObjectManagerDescriptor {
-    ObjectManagerDescriptor::parse(Bus::Session, BUS_NAME, ROOT_PATH)
-        .expect("static niri ObjectManager descriptor should be valid")
-}
-
-fn property(path: &str, interface: &str, name: &'static str) -> PropertyDescriptor {
-    PropertyDescriptor::new(object(path, interface), name)
-}
-
-fn object(path: &str, interface: &str) -> ObjectDescriptor {
-    ObjectDescriptor::parse(Bus::Session, BUS_NAME, path, interface)
-        .expect("static niri D-Bus descriptor should be valid")
-}
-
-fn id_from_path(path: &OwnedObjectPath, prefix: &str) -> Option<u64> {
-    path.as_str().strip_prefix(prefix)?.parse().ok()
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use shell_core::source::dbus::ObjectModel;
-
     use super::{NiriWindow, NiriWorkspace};
 
     #[test]
@@ -240,20 +235,17 @@ mod tests {
         let path = zbus::zvariant::OwnedObjectPath::try_from("/org/rsynapse/Niri/Windows/window_7")
             .unwrap();
 
-        assert_eq!(
-            <NiriWindow as ObjectModel>::at(path.clone()),
-            <NiriWindow as ObjectModel>::at(path)
-        );
+        assert_eq!(NiriWindow::at(path.clone()), NiriWindow::at(path));
         let _ = std::any::type_name::<NiriWorkspace>();
     }
 
     #[test]
     fn model_ids_are_derived_from_paths() {
-        let workspace = <NiriWorkspace as ObjectModel>::at(
+        let workspace = NiriWorkspace::at(
             zbus::zvariant::OwnedObjectPath::try_from("/org/rsynapse/Niri/Workspaces/workspace_42")
                 .unwrap(),
         );
-        let window = <NiriWindow as ObjectModel>::at(
+        let window = NiriWindow::at(
             zbus::zvariant::OwnedObjectPath::try_from("/org/rsynapse/Niri/Windows/window_7")
                 .unwrap(),
         );
@@ -1,9 +1,10 @@
-use std::{collections::HashMap, path::Path};
+use std::path::Path;
 
 use futures_util::StreamExt;
-use locus::{RelationEndpoint, RelationRecord, keys};
+use locus::{
+    LocusClient, Metadata, MetadataValue, RecordQuery, RelationEndpoint, RelationRecord, keys,
+};
 use shell_core::source::{self, Observable, rx::Observable as _};
-use zbus::{Connection, Proxy};
 
 use super::niri::NiriWorkspace;
 
@@ -53,121 +54,25 @@ async fn run_locus_workspace_project(
     sender: async_channel::Sender<Result<ProjectDetails, String>>,
     subject: RelationEndpoint,
 ) -> Result<(), String> {
-    let connection = Connection::session()
+    let client = LocusClient::session()
         .await
-        .map_err(|error| format!("connect session bus: {error}"))?;
-    let proxy = locus_proxy(&connection)
+        .map_err(|error| format!("connect locus client: {error}"))?;
+    let mut records = client
+        .watch(RecordQuery::new(WORKSPACE_PROJECT_RELATION).with_subject(subject))
         .await
-        .map_err(|error| format!("connect locus proxy: {error}"))?;
-
-    send_project(&sender, &proxy, &subject).await?;
-
-    let mut added = Box::pin(
-        proxy
-            .receive_signal("RelationAdded")
-            .await
-            .map_err(to_string)?,
-    );
-    let mut updated = Box::pin(
-        proxy
-            .receive_signal("RelationUpdated")
-            .await
-            .map_err(to_string)?,
-    );
-    let mut removed = Box::pin(
-        proxy
-            .receive_signal("RelationRemoved")
+        .map_err(|error| format!("watch locus project relations: {error}"))?;
+    while let Some(records) = records.next().await {
+        let project = records
+            .into_iter()
+            .next()
+            .map(ProjectDetails::from)
+            .unwrap_or_default();
+        sender
+            .send(Ok(project))
             .await
-            .map_err(to_string)?,
-    );
-    let mut cleared = Box::pin(
-        proxy
-            .receive_signal("RelationCleared")
-            .await
-            .map_err(to_string)?,
-    );
-
-    loop {
-        tokio::select! {
-            message = added.next() => {
-                let Some(message) = message else { return Ok(()); };
-                if relation_record_matches(&message, &subject)? {
-                    send_project(&sender, &proxy, &subject).await?;
-                }
-            }
-            message = updated.next() => {
-                let Some(message) = message else { return Ok(()); };
-                if relation_record_matches(&message, &subject)? {
-                    send_project(&sender, &proxy, &subject).await?;
-                }
-            }
-            message = removed.next() => {
-                let Some(message) = message else { return Ok(()); };
-                if relation_record_matches(&message, &subject)? {
-                    send_project(&sender, &proxy, &subject).await?;
-                }
-            }
-            message = cleared.next() => {
-                let Some(message) = message else { return Ok(()); };
-                if clear_matches(&message, &subject)? {
-                    send_project(&sender, &proxy, &subject).await?;
-                }
-            }
-        }
+            .map_err(|_| "project relation subscriber dropped".to_string())?;
     }
-}
-
-async fn send_project(
-    sender: &async_channel::Sender<Result<ProjectDetails, String>>,
-    proxy: &Proxy<'_>,
-    subject: &RelationEndpoint,
-) -> Result<(), String> {
-    let records = match proxy
-        .call::<_, _, Vec<RelationRecord>>("List", &(WORKSPACE_PROJECT_RELATION,))
-        .await
-    {
-        Ok(records) => records,
-        Err(error) if is_locus_unavailable(&error) => Vec::new(),
-        Err(error) => return Err(format!("read locus project relations: {error}")),
-    };
-    let project = records
-        .into_iter()
-        .find(|record| record.subject == *subject)
-        .map(ProjectDetails::from)
-        .unwrap_or_default();
-    sender
-        .send(Ok(project))
-        .await
-        .map_err(|_| "project relation subscriber dropped".to_string())
-}
-
-async fn locus_proxy(connection: &Connection) -> zbus::Result<Proxy<'_>> {
-    Proxy::new(
-        connection,
-        locus::BUS_NAME,
-        locus::OBJECT_PATH,
-        locus::RELATIONS_INTERFACE,
-    )
-    .await
-}
-
-fn relation_record_matches(
-    message: &zbus::Message,
-    subject: &RelationEndpoint,
-) -> Result<bool, String> {
-    let record = message
-        .body()
-        .deserialize::<RelationRecord>()
-        .map_err(|error| format!("decode locus relation signal: {error}"))?;
-    Ok(record.subject == *subject && record.relation == WORKSPACE_PROJECT_RELATION)
-}
-
-fn clear_matches(message: &zbus::Message, subject: &RelationEndpoint) -> Result<bool, String> {
-    let (cleared_subject, cleared_relation, _count) = message
-        .body()
-        .deserialize::<(RelationEndpoint, String, u32)>()
-        .map_err(|error| format!("decode locus clear signal: {error}"))?;
-    Ok(cleared_subject == *subject && cleared_relation == WORKSPACE_PROJECT_RELATION)
+    Ok(())
 }
 
 impl From<RelationRecord> for ProjectDetails {
@@ -233,9 +138,15 @@ fn path_basename(path: Option<&str>) -> Option<String> {
         .map(str::to_owned)
 }
 
-fn metadata_value(metadata: &HashMap<String, String>, keys: &[&str]) -> Option<String> {
-    keys.iter()
-        .find_map(|key| non_empty(metadata.get(*key).cloned()))
+fn metadata_value(metadata: &Metadata, keys: &[&str]) -> Option<String> {
+    keys.iter().find_map(|key| {
+        non_empty(
+            metadata
+                .get(*key)
+                .and_then(MetadataValue::as_str)
+                .map(str::to_owned),
+        )
+    })
 }
 
 fn workspace_subject(id: u64) -> RelationEndpoint {
@@ -257,19 +168,3 @@ fn non_empty_str(value: &str) -> Option<&str> {
 fn non_root_relative(value: String) -> Option<String> {
     non_empty(Some(value)).filter(|value| value != ".")
 }
-
-fn to_string(error: zbus::Error) -> String {
-    error.to_string()
-}
-
-fn is_locus_unavailable(error: &zbus::Error) -> bool {
-    match error {
-        zbus::Error::MethodError(name, _, _) => {
-            name.as_str() == "org.freedesktop.DBus.Error.ServiceUnknown"
-        }
-        zbus::Error::FDO(error) => {
-            matches!(error.as_ref(), zbus::fdo::Error::ServiceUnknown(_))
-        }
-        _ => false,
-    }
-}
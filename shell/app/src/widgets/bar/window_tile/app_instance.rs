@@ -1,9 +1,8 @@
-use std::collections::HashMap;
-
 use futures_util::StreamExt;
-use locus::{RelationEndpoint, RelationRecord, keys};
+use locus::{
+    LocusClient, Metadata, MetadataValue, RecordQuery, RelationEndpoint, RelationRecord, keys,
+};
 use shell_core::source::{self, Observable, rx::Observable as _};
-use zbus::{Connection, Proxy};
 
 const WINDOW_APP_INSTANCE_RELATION: &str = "org.rsynapse.window.app-instance";
 
@@ -38,121 +37,25 @@ async fn run_locus_app_instance(
     sender: async_channel::Sender<Result<AppInstance, String>>,
     subject: RelationEndpoint,
 ) -> Result<(), String> {
-    let connection = Connection::session()
+    let client = LocusClient::session()
         .await
-        .map_err(|error| format!("connect session bus: {error}"))?;
-    let proxy = locus_proxy(&connection)
+        .map_err(|error| format!("connect locus client: {error}"))?;
+    let mut records = client
+        .watch(RecordQuery::new(WINDOW_APP_INSTANCE_RELATION).with_subject(subject))
         .await
-        .map_err(|error| format!("connect locus proxy: {error}"))?;
-
-    send_app_instance(&sender, &proxy, &subject).await?;
-
-    let mut added = Box::pin(
-        proxy
-            .receive_signal("RelationAdded")
-            .await
-            .map_err(to_string)?,
-    );
-    let mut updated = Box::pin(
-        proxy
-            .receive_signal("RelationUpdated")
+        .map_err(|error| format!("watch locus app-instance relations: {error}"))?;
+    while let Some(records) = records.next().await {
+        let app_instance = records
+            .into_iter()
+            .next()
+            .map(AppInstance::from)
+            .unwrap_or_default();
+        sender
+            .send(Ok(app_instance))
             .await
-            .map_err(to_string)?,
-    );
-    let mut removed = Box::pin(
-        proxy
-            .receive_signal("RelationRemoved")
-            .await
-            .map_err(to_string)?,
-    );
-    let mut cleared = Box::pin(
-        proxy
-            .receive_signal("RelationCleared")
-            .await
-            .map_err(to_string)?,
-    );
-
-    loop {
-        tokio::select! {
-            message = added.next() => {
-                let Some(message) = message else { return Ok(()); };
-                if relation_record_matches(&message, &subject)? {
-                    send_app_instance(&sender, &proxy, &subject).await?;
-                }
-            }
-            message = updated.next() => {
-                let Some(message) = message else { return Ok(()); };
-                if relation_record_matches(&message, &subject)? {
-                    send_app_instance(&sender, &proxy, &subject).await?;
-                }
-            }
-            message = removed.next() => {
-                let Some(message) = message else { return Ok(()); };
-                if relation_record_matches(&message, &subject)? {
-                    send_app_instance(&sender, &proxy, &subject).await?;
-                }
-            }
-            message = cleared.next() => {
-                let Some(message) = message else { return Ok(()); };
-                if clear_matches(&message, &subject)? {
-                    send_app_instance(&sender, &proxy, &subject).await?;
-                }
-            }
-        }
+            .map_err(|_| "app-instance relation subscriber dropped".to_string())?;
     }
-}
-
-async fn send_app_instance(
-    sender: &async_channel::Sender<Result<AppInstance, String>>,
-    proxy: &Proxy<'_>,
-    subject: &RelationEndpoint,
-) -> Result<(), String> {
-    let records = match proxy
-        .call::<_, _, Vec<RelationRecord>>("List", &(WINDOW_APP_INSTANCE_RELATION,))
-        .await
-    {
-        Ok(records) => records,
-        Err(error) if is_locus_unavailable(&error) => Vec::new(),
-        Err(error) => return Err(format!("read locus app-instance relations: {error}")),
-    };
-    let app_instance = records
-        .into_iter()
-        .find(|record| record.subject == *subject)
-        .map(AppInstance::from)
-        .unwrap_or_default();
-    sender
-        .send(Ok(app_instance))
-        .await
-        .map_err(|_| "app-instance relation subscriber dropped".to_string())
-}
-
-async fn locus_proxy(connection: &Connection) -> zbus::Result<Proxy<'_>> {
-    Proxy::new(
-        connection,
-        locus::BUS_NAME,
-        locus::OBJECT_PATH,
-        locus::RELATIONS_INTERFACE,
-    )
-    .await
-}
-
-fn relation_record_matches(
-    message: &zbus::Message,
-    subject: &RelationEndpoint,
-) -> Result<bool, String> {
-    let record = message
-        .body()
-        .deserialize::<RelationRecord>()
-        .map_err(|error| format!("decode locus relation signal: {error}"))?;
-    Ok(record.subject == *subject && record.relation == WINDOW_APP_INSTANCE_RELATION)
-}
-
-fn clear_matches(message: &zbus::Message, subject: &RelationEndpoint) -> Result<bool, String> {
-    let (cleared_subject, cleared_relation, _count) = message
-        .body()
-        .deserialize::<(RelationEndpoint, String, u32)>()
-        .map_err(|error| format!("decode locus clear signal: {error}"))?;
-    Ok(cleared_subject == *subject && cleared_relation == WINDOW_APP_INSTANCE_RELATION)
+    Ok(())
 }
 
 impl From<RelationRecord> for AppInstance {
@@ -164,28 +67,17 @@ impl From<RelationRecord> for AppInstance {
     }
 }
 
-fn metadata_value(metadata: &HashMap<String, String>, keys: &[&str]) -> Option<String> {
-    keys.iter()
-        .find_map(|key| metadata.get(*key).cloned().and_then(non_empty))
+fn metadata_value(metadata: &Metadata, keys: &[&str]) -> Option<String> {
+    keys.iter().find_map(|key| {
+        metadata
+            .get(*key)
+            .and_then(MetadataValue::as_str)
+            .map(str::to_owned)
+            .and_then(non_empty)
+    })
 }
 
 fn non_empty(value: String) -> Option<String> {
     let value = value.trim().to_owned();
     (!value.is_empty()).then_some(value)
 }
-
-fn to_string(error: zbus::Error) -> String {
-    error.to_string()
-}
-
-fn is_locus_unavailable(error: &zbus::Error) -> bool {
-    match error {
-        zbus::Error::MethodError(name, _, _) => {
-            name.as_str() == "org.freedesktop.DBus.Error.ServiceUnknown"
-        }
-        zbus::Error::FDO(error) => {
-            matches!(error.as_ref(), zbus::fdo::Error::ServiceUnknown(_))
-        }
-        _ => false,
-    }
-}
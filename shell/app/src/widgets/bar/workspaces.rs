@@ -15,7 +15,9 @@ pub(super) struct WorkspaceEntry {
 }
 
 pub(super) fn workspaces(output_name: Option<String>) -> Observable<Vec<WorkspaceNode>> {
-    let output_path = output_name.as_deref().map(niri::output_path_for_name);
+    let output_path = output_name
+        .as_deref()
+        .map(|name| niri_dbus::output_path(name).as_str().to_owned());
     source::switch_map_list(niri::workspaces(), workspace_entry)
         .map(move |workspaces| filter_workspaces_for_output(workspaces, output_path.as_deref()))
         .distinct_until_changed()
@@ -132,7 +134,6 @@ mod tests {
         niri::{NiriWindow, NiriWorkspace},
         window_source::WindowSnapshot,
     };
-    use shell_core::source::dbus::ObjectModel;
     use zbus::zvariant::OwnedObjectPath;
 
     #[test]
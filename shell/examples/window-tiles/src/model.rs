@@ -11,14 +11,14 @@ pub(crate) struct WindowTile {
 }
 
 impl WindowTile {
+    /// A changed window replaces its row, so each row shows one model.
     pub(crate) fn source(window: NiriWindow) -> Source<Self> {
-        window
-            .id()
-            .combine_latest(window.title(), |id, title| (id, title))
-            .combine_latest(window.app_id(), |(id, title), app_id| (id, title, app_id))
-            .combine_latest(window.focused(), |(id, title, app_id), focused| {
-                Self::from_dbus(id, title, app_id, focused)
-            })
+        Source::once(Self::from_dbus(
+            window.id,
+            window.title,
+            window.app_id,
+            window.focused,
+        ))
     }
 
     pub(crate) fn from_dbus(
@@ -1,39 +1,34 @@
+use futures_util::StreamExt;
+use niri_dbus::NiriClient;
 use shell_core::source::Source;
 
-#[shell_macros::dbus_model(
-    module = root,
-    interface = "org.rsynapse.Niri1",
-    default_service = "org.rsynapse.Niri",
-    default_path = "/org/rsynapse/Niri"
-)]
-struct NiriRoot {
-    #[dbus(model)]
-    windows: Vec<NiriWindow>,
-}
-
-#[shell_macros::dbus_model(
-    module = window,
-    interface = "org.rsynapse.Niri1.Window",
-    default_service = "org.rsynapse.Niri"
-)]
-pub(crate) struct NiriWindow {
-    id: u64,
-    title: Option<String>,
-    app_id: Option<String>,
-    focused: bool,
-}
+pub(crate) use niri_dbus::NiriWindow;
 
+/// Every niri window, in the order of the projection's `Windows` list.
 pub(crate) fn windows() -> Source<Vec<NiriWindow>> {
-    NiriRoot::new().windows()
-}
-
-#[cfg(test)]
-mod tests {
-    use super::{NiriRoot, NiriWindow};
-
-    #[test]
-    fn dbus_models_are_generated() {
-        let _ = std::any::type_name::<NiriRoot>();
-        let _ = std::any::type_name::<NiriWindow>();
-    }
+    Source::from_task(|sender| async move {
+        let client = match NiriClient::session().await {
+            Ok(client) => client,
+            Err(error) => {
+                let _ = sender
+                    .send(Err(format!("connect niri client: {error}")))
+                    .await;
+                return;
+            }
+        };
+        let mut snapshots = match client.watch().await {
+            Ok(snapshots) => snapshots,
+            Err(error) => {
+                let _ = sender
+                    .send(Err(format!("watch niri projection: {error}")))
+                    .await;
+                return;
+            }
+        };
+        while let Some(snapshot) = snapshots.next().await {
+            if sender.send(Ok(snapshot.windows)).await.is_err() {
+                return;
+            }
+        }
+    })
 }